@@ -0,0 +1,284 @@
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Format {
+	/// One line per 16-bit word, for pasting into the game's Program component.
+	Tc,
+
+	/// The format used by the save-breaker branch's assembler.
+	SaveBreaker,
+
+	/// Raw little-endian bytes.
+	Bin,
+
+	/// Intel HEX.
+	Ihex,
+
+	/// Motorola S-record.
+	Srec,
+
+	/// One 16-bit word per line, for `$readmemh`.
+	Readmemh,
+
+	/// A C `unsigned char` array.
+	CArray,
+
+	/// A Rust `[u8; N]` constant.
+	RustArray,
+}
+
+pub(crate) struct Encoded {
+	pub(crate) pc: u64,
+	pub(crate) lo: u16,
+	pub(crate) hi: Option<u16>,
+	pub(crate) instruction: riscv::Instruction,
+}
+
+impl Format {
+	pub(crate) fn write(self, mut w: impl std::io::Write, program: &[Encoded]) -> std::io::Result<()> {
+		match self {
+			Self::Tc => {
+				for &Encoded { pc, lo, hi, instruction } in program {
+					if let Some(hi) = hi {
+						writeln!(w, "0x{lo:04x} 0x{hi:04x} # {pc:3}: {instruction}")?;
+					}
+					else {
+						writeln!(w, "0x{lo:04x}        # {pc:3}: {instruction}")?;
+					}
+				}
+			},
+
+			Self::SaveBreaker => {
+				for &Encoded { pc, lo, hi, instruction } in program {
+					if let Some(hi) = hi {
+						writeln!(w, "<U32>0x{hi:04x}{lo:04x} ; {pc:3}: {instruction}")?;
+					}
+					else {
+						writeln!(w, "<U16>0x{lo:04x}     ; {pc:3}: {instruction}")?;
+					}
+				}
+			},
+
+			Self::Bin => w.write_all(&to_bytes(program))?,
+
+			Self::Ihex => {
+				let bytes = to_bytes(program);
+
+				let mut upper_address = 0_u16;
+				for (i, chunk) in bytes.chunks(16).enumerate() {
+					let address = u32::try_from(i * 16).map_err(|_| std::io::Error::other("program is too large for Intel HEX"))?;
+					#[allow(clippy::cast_possible_truncation)]
+					let (hi, lo) = ((address >> 16) as u16, address as u16);
+					if hi != upper_address {
+						write_ihex_record(&mut w, 0, 0x04, &hi.to_be_bytes())?;
+						upper_address = hi;
+					}
+					write_ihex_record(&mut w, lo, 0x00, chunk)?;
+				}
+				write_ihex_record(&mut w, 0, 0x01, &[])?;
+			},
+
+			Self::Srec => {
+				let bytes = to_bytes(program);
+
+				let (data_type, end_type, address_len) = match bytes.len() {
+					0x0000_0000..=0x0001_0000 => (b'1', b'9', 2),
+					0x0001_0001..=0x0100_0000 => (b'2', b'8', 3),
+					_ => (b'3', b'7', 4),
+				};
+
+				write_srec_record(&mut w, b'0', 0, 2, &[])?;
+				for (i, chunk) in bytes.chunks(16).enumerate() {
+					let address = u32::try_from(i * 16).map_err(|_| std::io::Error::other("program is too large for S-record"))?;
+					write_srec_record(&mut w, data_type, address, address_len, chunk)?;
+				}
+				write_srec_record(&mut w, end_type, 0, address_len, &[])?;
+			},
+
+			Self::Readmemh => {
+				for &Encoded { pc, lo, hi, instruction } in program {
+					writeln!(w, "{lo:04x} // {pc:3}: {instruction}")?;
+					if let Some(hi) = hi {
+						writeln!(w, "{hi:04x}")?;
+					}
+				}
+			},
+
+			Self::CArray => {
+				writeln!(w, "const unsigned char program[{}] = {{", to_bytes(program).len())?;
+				write_array_body(&mut w, program)?;
+				writeln!(w, "}};")?;
+			},
+
+			Self::RustArray => {
+				writeln!(w, "pub const PROGRAM: [u8; {}] = [", to_bytes(program).len())?;
+				write_array_body(&mut w, program)?;
+				writeln!(w, "];")?;
+			},
+		}
+
+		Ok(())
+	}
+}
+
+impl std::str::FromStr for Format {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"tc" => Self::Tc,
+			"save-breaker" | "sb" => Self::SaveBreaker,
+			"bin" => Self::Bin,
+			"ihex" => Self::Ihex,
+			"srec" => Self::Srec,
+			"readmemh" => Self::Readmemh,
+			"c-array" => Self::CArray,
+			"rust-array" => Self::RustArray,
+			_ => return Err(()),
+		})
+	}
+}
+
+fn to_bytes(program: &[Encoded]) -> Vec<u8> {
+	let mut result = Vec::with_capacity(program.len() * 4);
+	for &Encoded { lo, hi, .. } in program {
+		result.extend_from_slice(&lo.to_le_bytes());
+		if let Some(hi) = hi {
+			result.extend_from_slice(&hi.to_le_bytes());
+		}
+	}
+	result
+}
+
+fn write_ihex_record(mut w: impl std::io::Write, address: u16, record_type: u8, data: &[u8]) -> std::io::Result<()> {
+	let len = u8::try_from(data.len()).expect("record data is at most 255 bytes");
+	let [address_hi, address_lo] = address.to_be_bytes();
+
+	let mut checksum = len.wrapping_add(address_hi).wrapping_add(address_lo).wrapping_add(record_type);
+	write!(w, ":{len:02X}{address:04X}{record_type:02X}")?;
+	for &b in data {
+		checksum = checksum.wrapping_add(b);
+		write!(w, "{b:02X}")?;
+	}
+	writeln!(w, "{:02X}", checksum.wrapping_neg())?;
+
+	Ok(())
+}
+
+fn write_srec_record(mut w: impl std::io::Write, record_type: u8, address: u32, address_len: usize, data: &[u8]) -> std::io::Result<()> {
+	let count = u8::try_from(address_len + data.len() + 1).expect("record data is at most 255 bytes");
+	let address = &address.to_be_bytes()[(4 - address_len)..];
+
+	let mut checksum = count;
+	write!(w, "S{}{count:02X}", char::from(record_type))?;
+	for &b in address.iter().chain(data) {
+		checksum = checksum.wrapping_add(b);
+		write!(w, "{b:02X}")?;
+	}
+	writeln!(w, "{:02X}", !checksum)?;
+
+	Ok(())
+}
+
+fn write_array_body(mut w: impl std::io::Write, program: &[Encoded]) -> std::io::Result<()> {
+	for &Encoded { pc, lo, hi, instruction } in program {
+		let [b0, b1] = lo.to_le_bytes();
+		if let Some(hi) = hi {
+			let [b2, b3] = hi.to_le_bytes();
+			writeln!(w, "\t0x{b0:02x}, 0x{b1:02x}, 0x{b2:02x}, 0x{b3:02x}, // {pc:3}: {instruction}")?;
+		}
+		else {
+			writeln!(w, "\t0x{b0:02x}, 0x{b1:02x},             // {pc:3}: {instruction}")?;
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Encoded, Format};
+
+	#[test]
+	fn it_works() {
+		static TESTS: &[(Format, &str)] = &[
+			(Format::Tc, "\
+0x8513 0x00b5 #   0: addi x10, x11, 11
+0x952e        #   4: add x10, x10, x11
+"),
+
+			(Format::SaveBreaker, "\
+<U32>0x00b58513 ;   0: addi x10, x11, 11
+<U16>0x952e     ;   4: add x10, x10, x11
+"),
+
+			(Format::Bin, "\x13\u{85}\u{b5}\x00\x2e\u{95}"),
+
+			(Format::Ihex, "\
+:060000001385B5002E95EA
+:00000001FF
+"),
+
+			(Format::Srec, "\
+S0030000FC
+S10900001385B5002E95E6
+S9030000FC
+"),
+
+			(Format::Readmemh, "\
+8513 //   0: addi x10, x11, 11
+00b5
+952e //   4: add x10, x10, x11
+"),
+
+			(Format::CArray, "\
+const unsigned char program[6] = {
+	0x13, 0x85, 0xb5, 0x00, //   0: addi x10, x11, 11
+	0x2e, 0x95,             //   4: add x10, x10, x11
+};
+"),
+
+			(Format::RustArray, "\
+pub const PROGRAM: [u8; 6] = [
+	0x13, 0x85, 0xb5, 0x00, //   0: addi x10, x11, 11
+	0x2e, 0x95,             //   4: add x10, x10, x11
+];
+"),
+		];
+
+		let supported_extensions = riscv::SupportedExtensions::RV64I | riscv::SupportedExtensions::RVC;
+		let mut program = vec![];
+		let mut pc = 0;
+		for instruction in riscv::parse_program([&b"addi a0, a1, 11"[..], b"add a0, a0, a1"], supported_extensions) {
+			let instruction = instruction.unwrap();
+			let (lo, hi) = instruction.encode(supported_extensions).unwrap();
+			program.push(Encoded { pc, lo, hi, instruction });
+			pc += if hi.is_some() { 4 } else { 2 };
+		}
+
+		for &(format, expected) in TESTS {
+			let mut actual = vec![];
+			format.write(&mut actual, &program).unwrap();
+			if matches!(format, Format::Bin) {
+				let expected: Vec<u8> = expected.chars().map(|c| u8::try_from(c).unwrap()).collect();
+				assert_eq!(actual, expected, "{format:?}");
+			}
+			else {
+				assert_eq!(String::from_utf8(actual).unwrap(), expected, "{format:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn ihex_extended_address() {
+		let instruction = riscv::Instruction::EBreak;
+		let program: Vec<_> = (0..0x4001).map(|i| Encoded { pc: i * 4, lo: 0x0073, hi: Some(0x0010), instruction }).collect();
+
+		let mut actual = vec![];
+		Format::Ihex.write(&mut actual, &program).unwrap();
+		let actual = String::from_utf8(actual).unwrap();
+		let lines: Vec<_> = actual.lines().collect();
+		assert_eq!(lines.len(), 0x1000 + 1 + 1 + 1);
+		assert_eq!(lines[0x1000], ":020000040001F9");
+		assert_eq!(lines[0x1001], ":040000007300100079");
+		assert_eq!(lines[0x1002], ":00000001FF");
+	}
+}
@@ -1,49 +1,73 @@
+mod format;
+use format::{Encoded, Format};
+
+use std::io::Write;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let mut args = std::env::args_os();
 	let argv0 = args.next().unwrap_or_else(|| env!("CARGO_BIN_NAME").into());
-	let (path, supported_extensions, save_breaker) = parse_args(args, &argv0);
+	let (path, supported_extensions, format, output_path, symbols_path, symbols_base) = parse_args(args, &argv0);
 
 	let program = std::fs::read_to_string(path)?;
 
+	let mut encoded = vec![];
+	let mut symbols = vec![];
+
 	let mut pc = 0_u64;
 
-	for instruction in riscv::parse_program(program.lines().map(str::as_bytes), supported_extensions) {
-		let instruction = instruction.map_err(|err| err.to_string())?;
-		let (lo, hi) =
-			instruction.encode(supported_extensions)
-			.map_err(|err| format!("instruction could not be encoded {instruction:?}: {err}"))?;
-		#[allow(clippy::collapsible_else_if)]
-		if let Some(hi) = hi {
-			if save_breaker {
-				println!("<U32>0x{hi:04x}{lo:04x} ; {pc:3}: {instruction}");
-			}
-			else {
-				println!("0x{lo:04x} 0x{hi:04x} # {pc:3}: {instruction}");
-			}
-
-			pc += 4;
+	for line in program.lines() {
+		let (label, line) = riscv::split_label(line.as_bytes());
+		if let Some(label) = label {
+			symbols.push((symbols_base.wrapping_add(pc), label));
 		}
-		else {
-			if save_breaker {
-				println!("<U16>0x{lo:04x}     ; {pc:3}: {instruction}");
-			}
-			else {
-				println!("0x{lo:04x}        # {pc:3}: {instruction}");
-			}
-
-			pc += 2;
+
+		for instruction in riscv::parse_program(std::iter::once(line), supported_extensions) {
+			let instruction = instruction.map_err(|err| err.to_string())?;
+			let (lo, hi) =
+				instruction.encode(supported_extensions)
+				.map_err(|err| format!("instruction could not be encoded {instruction:?}: {err}"))?;
+			encoded.push(Encoded { pc, lo, hi, instruction });
+			pc += if hi.is_some() { 4 } else { 2 };
 		}
 	}
 
+	if let Some(symbols_path) = symbols_path {
+		// The format printed by `nm`
+		let mut w = std::io::BufWriter::new(std::fs::File::create(symbols_path)?);
+		for (address, name) in symbols {
+			writeln!(w, "{address:016x} T {name}")?;
+		}
+		std::io::Write::flush(&mut w)?;
+	}
+
+	if let Some(output_path) = output_path {
+		let mut w = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+		format.write(&mut w, &encoded)?;
+		std::io::Write::flush(&mut w)?;
+	}
+	else {
+		format.write(std::io::stdout().lock(), &encoded)?;
+	}
+
 	Ok(())
 }
 
-fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::ffi::OsStr) -> (std::path::PathBuf, riscv::SupportedExtensions, bool) {
+fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::ffi::OsStr) -> (
+	std::path::PathBuf,
+	riscv::SupportedExtensions,
+	Format,
+	Option<std::path::PathBuf>,
+	Option<std::path::PathBuf>,
+	u64,
+) {
 	let mut path = None;
 	let mut supported_extensions = riscv::SupportedExtensions::RV32I;
-	let mut save_breaker = false;
+	let mut format = Format::Tc;
+	let mut output_path = None;
+	let mut symbols_path = None;
+	let mut symbols_base = 0;
 
-	for opt in &mut args {
+	while let Some(opt) = args.next() {
 		match opt.to_str() {
 			Some("--help") => {
 				write_usage(std::io::stdout(), argv0);
@@ -61,7 +85,29 @@ fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::f
 
 			Some("--compressed=Zcb") => supported_extensions |= riscv::SupportedExtensions::ZCB,
 
-			Some("--save-breaker" | "--sb") => save_breaker = true,
+			Some(opt) if opt.starts_with("--format=") => {
+				let Ok(format_) = opt["--format=".len()..].parse() else { write_usage_and_crash(argv0); };
+				format = format_;
+			},
+
+			Some("-o") if output_path.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				output_path = Some(arg.into());
+			},
+
+			Some("--save-breaker" | "--sb") => format = Format::SaveBreaker,
+
+			Some("--symbols") if symbols_path.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				symbols_path = Some(arg.into());
+			},
+
+			Some("--symbols-base") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				let Some(arg) = arg.to_str() else { write_usage_and_crash(argv0); };
+				let Ok(arg) = u64::from_str_radix(arg.strip_prefix("0x").unwrap_or(arg), 16) else { write_usage_and_crash(argv0); };
+				symbols_base = arg;
+			},
 
 			Some("--zba") => supported_extensions |= riscv::SupportedExtensions::ZBA,
 
@@ -80,7 +126,7 @@ fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::f
 	let None = args.next() else { write_usage_and_crash(argv0); };
 
 	let Some(path) = path else { write_usage_and_crash(argv0); };
-	(path.into(), supported_extensions, save_breaker)
+	(path.into(), supported_extensions, format, output_path, symbols_path, symbols_base)
 }
 
 fn write_usage_and_crash(argv0: &std::ffi::OsStr) -> ! {
@@ -89,5 +135,5 @@ fn write_usage_and_crash(argv0: &std::ffi::OsStr) -> ! {
 }
 
 fn write_usage(mut w: impl std::io::Write, argv0: &std::ffi::OsStr) {
-	_ = writeln!(w, "Usage: {} [ --32 | --64 ] [ -c | --compressed | --compressed=[true|false|Zcb] ] [ --sb | --save-breaker ] [ --zba ] [ --zbb ] [ -- ] <program.S>", argv0.to_string_lossy());
+	_ = writeln!(w, "Usage: {} [ --32 | --64 ] [ -c | --compressed | --compressed=[true|false|Zcb] ] [ --format=[tc|save-breaker|bin|ihex|srec|readmemh|c-array|rust-array] ] [ --sb | --save-breaker ] [ --symbols <symbols.map> [ --symbols-base <hex address> ] ] [ --zba ] [ --zbb ] [ -o <output> ] [ -- ] <program.S>", argv0.to_string_lossy());
 }
@@ -0,0 +1,445 @@
+/// Predicts the direction of conditional branches.
+///
+/// Predictors are trained when a branch retires, not when it is fetched or resolved, so the global history that gshare and TAGE
+/// use only contains branches on the correct path.
+pub(crate) trait BranchPredictor {
+	fn name(&self) -> &'static str;
+
+	/// Predicts whether the conditional branch at `pc` is taken. `backward` is whether its target is before it.
+	fn predict(&self, pc: i64, backward: bool) -> Prediction;
+
+	/// Trains the predictor with the outcome of a branch that it predicted.
+	fn update(&mut self, pc: i64, prediction: Prediction, taken: bool);
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Prediction {
+	pub(crate) taken: bool,
+	/// The global history that the prediction was made with, so that the same entries are updated.
+	history: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Kind {
+	Btfnt,
+	AlwaysTaken,
+	Bimodal,
+	Gshare,
+	Tage,
+}
+
+impl Kind {
+	pub(crate) fn build(self) -> Box<dyn BranchPredictor> {
+		match self {
+			Self::Btfnt => Box::new(Btfnt),
+			Self::AlwaysTaken => Box::new(AlwaysTaken),
+			Self::Bimodal => Box::new(Bimodal::default()),
+			Self::Gshare => Box::new(Gshare::default()),
+			Self::Tage => Box::new(Tage::default()),
+		}
+	}
+}
+
+impl std::str::FromStr for Kind {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"btfnt" => Self::Btfnt,
+			"always-taken" => Self::AlwaysTaken,
+			"bimodal" => Self::Bimodal,
+			"gshare" => Self::Gshare,
+			"tage" => Self::Tage,
+			_ => return Err(()),
+		})
+	}
+}
+
+/// Backward taken, forward not taken.
+struct Btfnt;
+
+impl BranchPredictor for Btfnt {
+	fn name(&self) -> &'static str { "btfnt" }
+
+	fn predict(&self, _pc: i64, backward: bool) -> Prediction {
+		Prediction { taken: backward, history: 0 }
+	}
+
+	fn update(&mut self, _pc: i64, _prediction: Prediction, _taken: bool) {}
+}
+
+struct AlwaysTaken;
+
+impl BranchPredictor for AlwaysTaken {
+	fn name(&self) -> &'static str { "always-taken" }
+
+	fn predict(&self, _pc: i64, _backward: bool) -> Prediction {
+		Prediction { taken: true, history: 0 }
+	}
+
+	fn update(&mut self, _pc: i64, _prediction: Prediction, _taken: bool) {}
+}
+
+/// A table of 2-bit saturating counters indexed by the branch's address.
+struct Bimodal {
+	counters: Box<[Counter]>,
+}
+
+const BIMODAL_INDEX_BITS: u32 = 10;
+
+impl Default for Bimodal {
+	fn default() -> Self {
+		Self {
+			counters: vec![Counter::WEAKLY_NOT_TAKEN; 1 << BIMODAL_INDEX_BITS].into(),
+		}
+	}
+}
+
+impl BranchPredictor for Bimodal {
+	fn name(&self) -> &'static str { "bimodal" }
+
+	fn predict(&self, pc: i64, _backward: bool) -> Prediction {
+		Prediction { taken: self.counters[pc_index(pc, BIMODAL_INDEX_BITS)].taken(), history: 0 }
+	}
+
+	fn update(&mut self, pc: i64, _prediction: Prediction, taken: bool) {
+		self.counters[pc_index(pc, BIMODAL_INDEX_BITS)].update(taken);
+	}
+}
+
+/// A table of 2-bit saturating counters indexed by the XOR of the branch's address and the global history.
+struct Gshare {
+	counters: Box<[Counter]>,
+	history: u64,
+}
+
+const GSHARE_INDEX_BITS: u32 = 10;
+
+impl Default for Gshare {
+	fn default() -> Self {
+		Self {
+			counters: vec![Counter::WEAKLY_NOT_TAKEN; 1 << GSHARE_INDEX_BITS].into(),
+			history: 0,
+		}
+	}
+}
+
+impl Gshare {
+	fn index(pc: i64, history: u64) -> usize {
+		pc_index(pc, GSHARE_INDEX_BITS) ^ fold(history, GSHARE_INDEX_BITS, GSHARE_INDEX_BITS)
+	}
+}
+
+impl BranchPredictor for Gshare {
+	fn name(&self) -> &'static str { "gshare" }
+
+	fn predict(&self, pc: i64, _backward: bool) -> Prediction {
+		Prediction { taken: self.counters[Self::index(pc, self.history)].taken(), history: self.history }
+	}
+
+	fn update(&mut self, pc: i64, prediction: Prediction, taken: bool) {
+		self.counters[Self::index(pc, prediction.history)].update(taken);
+		self.history = (self.history << 1) | u64::from(taken);
+	}
+}
+
+// Ref: A. Seznec, P. Michaud. A case for (partially) TAgged GEometric history length branch prediction.
+//
+// A bimodal base predictor and four tagged tables indexed with geometrically increasing lengths of global history.
+// The table with the longest history whose tag matches provides the prediction.
+// On a misprediction an entry is allocated in a table with a longer history than the provider.
+// Unlike the paper, the usefulness counters are never reset periodically.
+struct Tage {
+	base: Bimodal,
+	tables: [Box<[TageEntry]>; TAGE_HISTORY_LENGTHS.len()],
+	history: u64,
+}
+
+const TAGE_HISTORY_LENGTHS: [u32; 4] = [4, 8, 16, 32];
+const TAGE_INDEX_BITS: u32 = 8;
+const TAGE_TAG_BITS: u32 = 8;
+
+#[derive(Clone, Copy, Default)]
+struct TageEntry {
+	/// `None` if the entry has never been allocated.
+	tag: Option<u16>,
+	/// 3-bit signed counter, taken if non-negative.
+	counter: i8,
+	/// 2-bit usefulness counter.
+	useful: u8,
+}
+
+impl Default for Tage {
+	fn default() -> Self {
+		Self {
+			base: Default::default(),
+			tables: std::array::from_fn(|_| vec![TageEntry::default(); 1 << TAGE_INDEX_BITS].into()),
+			history: 0,
+		}
+	}
+}
+
+impl Tage {
+	fn index(pc: i64, history: u64, table: usize) -> usize {
+		pc_index(pc, TAGE_INDEX_BITS) ^ fold(history, TAGE_HISTORY_LENGTHS[table], TAGE_INDEX_BITS)
+	}
+
+	fn tag(pc: i64, history: u64, table: usize) -> u16 {
+		let tag =
+			(pc_index(pc >> TAGE_INDEX_BITS, TAGE_TAG_BITS) ^ fold(history, TAGE_HISTORY_LENGTHS[table], TAGE_TAG_BITS - 1) << 1) &
+			((1 << TAGE_TAG_BITS) - 1);
+		tag.try_into().expect("tag fits in TAGE_TAG_BITS")
+	}
+
+	/// Returns the tables that match the branch, from the longest history to the shortest.
+	fn matches(&self, pc: i64, history: u64) -> impl Iterator<Item = usize> {
+		(0..self.tables.len()).rev()
+			.filter(move |&table| self.tables[table][Self::index(pc, history, table)].tag == Some(Self::tag(pc, history, table)))
+	}
+}
+
+impl BranchPredictor for Tage {
+	fn name(&self) -> &'static str { "tage" }
+
+	fn predict(&self, pc: i64, backward: bool) -> Prediction {
+		let taken = match self.matches(pc, self.history).next() {
+			Some(provider) => self.tables[provider][Self::index(pc, self.history, provider)].counter >= 0,
+			None => self.base.predict(pc, backward).taken,
+		};
+		Prediction { taken, history: self.history }
+	}
+
+	fn update(&mut self, pc: i64, prediction: Prediction, taken: bool) {
+		let history = prediction.history;
+
+		let (provider, alternate_taken) = {
+			let mut matches = self.matches(pc, history);
+			let provider = matches.next();
+			let alternate_taken = match matches.next() {
+				Some(alternate) => self.tables[alternate][Self::index(pc, history, alternate)].counter >= 0,
+				None => self.base.predict(pc, false).taken,
+			};
+			(provider, alternate_taken)
+		};
+
+		match provider {
+			Some(provider) => {
+				let entry = &mut self.tables[provider][Self::index(pc, history, provider)];
+				let provider_taken = entry.counter >= 0;
+				if provider_taken != alternate_taken {
+					entry.useful = if provider_taken == taken { (entry.useful + 1).min(3) } else { entry.useful.saturating_sub(1) };
+				}
+				entry.counter = if taken { (entry.counter + 1).min(3) } else { (entry.counter - 1).max(-4) };
+			},
+
+			None => self.base.update(pc, prediction, taken),
+		}
+
+		if prediction.taken != taken {
+			let longer = provider.map_or(0, |provider| provider + 1);
+			let free = (longer..self.tables.len()).find(|&table| self.tables[table][Self::index(pc, history, table)].useful == 0);
+			if let Some(table) = free {
+				self.tables[table][Self::index(pc, history, table)] = TageEntry {
+					tag: Some(Self::tag(pc, history, table)),
+					counter: if taken { 0 } else { -1 },
+					useful: 0,
+				};
+			}
+			else {
+				for table in longer..self.tables.len() {
+					let entry = &mut self.tables[table][Self::index(pc, history, table)];
+					entry.useful = entry.useful.saturating_sub(1);
+				}
+			}
+		}
+
+		self.history = (self.history << 1) | u64::from(taken);
+	}
+}
+
+/// A direct-mapped cache of the targets of indirect jumps.
+#[derive(Debug)]
+pub(crate) struct BranchTargetBuffer {
+	entries: Box<[Option<(i64, i64)>]>,
+}
+
+impl BranchTargetBuffer {
+	/// `num_entries` is rounded up to a power of two. 0 disables the buffer.
+	pub(crate) fn new(num_entries: usize) -> Self {
+		let num_entries = if num_entries == 0 { 0 } else { num_entries.next_power_of_two() };
+		Self {
+			entries: vec![None; num_entries].into(),
+		}
+	}
+
+	pub(crate) fn predict(&self, pc: i64) -> Option<i64> {
+		let (entry_pc, target) = (*self.entry(pc)?)?;
+		(entry_pc == pc).then_some(target)
+	}
+
+	pub(crate) fn update(&mut self, pc: i64, target: i64) {
+		let len = self.entries.len();
+		if let Some(entry) = self.entries.get_mut(Self::index(pc, len)) {
+			*entry = Some((pc, target));
+		}
+	}
+
+	fn entry(&self, pc: i64) -> Option<&Option<(i64, i64)>> {
+		self.entries.get(Self::index(pc, self.entries.len()))
+	}
+
+	fn index(pc: i64, len: usize) -> usize {
+		usize::try_from(pc.cast_unsigned() >> 1).unwrap_or_default() & len.wrapping_sub(1)
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Counter(u8);
+
+impl Counter {
+	const WEAKLY_NOT_TAKEN: Self = Self(1);
+
+	fn taken(self) -> bool {
+		self.0 >= 2
+	}
+
+	fn update(&mut self, taken: bool) {
+		self.0 = if taken { (self.0 + 1).min(3) } else { self.0.saturating_sub(1) };
+	}
+}
+
+/// Returns the low `bits` bits of the address of the halfword at `pc`.
+fn pc_index(pc: i64, bits: u32) -> usize {
+	usize::try_from((pc.cast_unsigned() >> 1) & ((1 << bits) - 1)).expect("index fits in usize")
+}
+
+/// XORs together the `bits`-bit chunks of the most recent `len` bits of `history`.
+fn fold(history: u64, len: u32, bits: u32) -> usize {
+	let mut history = history & (u64::MAX >> (64 - len));
+	let mut result = 0;
+	while history != 0 {
+		result ^= history & ((1 << bits) - 1);
+		history >>= bits;
+	}
+	usize::try_from(result).expect("index fits in usize")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{BranchPredictor, BranchTargetBuffer, Gshare, Kind};
+
+	/// Predicts and trains the branch at `pc` with each outcome of `pattern` in turn, `repeat` times,
+	/// and returns the number of mispredictions in the last repetition.
+	fn mispredictions(predictor: &mut dyn BranchPredictor, pc: i64, pattern: &[bool], repeat: usize) -> usize {
+		let mut result = 0;
+		for _ in 0..repeat {
+			result = 0;
+			for &taken in pattern {
+				let prediction = predictor.predict(pc, false);
+				if prediction.taken != taken {
+					result += 1;
+				}
+				predictor.update(pc, prediction, taken);
+			}
+		}
+		result
+	}
+
+	#[test]
+	fn static_predictors() {
+		let btfnt = Kind::Btfnt.build();
+		assert!(btfnt.predict(0x100, true).taken);
+		assert!(!btfnt.predict(0x100, false).taken);
+
+		let always_taken = Kind::AlwaysTaken.build();
+		assert!(always_taken.predict(0x100, false).taken);
+	}
+
+	#[test]
+	fn bimodal() {
+		let mut bimodal = Kind::Bimodal.build();
+
+		// Starts weakly not taken, and one taken outcome makes it weakly taken.
+		assert!(!bimodal.predict(0x100, true).taken);
+		assert_eq!(mispredictions(&mut *bimodal, 0x100, &[true], 1), 1);
+		assert!(bimodal.predict(0x100, false).taken);
+
+		// Saturates, so a single not-taken outcome of a loop branch does not flip it.
+		assert_eq!(mispredictions(&mut *bimodal, 0x100, &[true, true, true, false], 10), 1);
+
+		// Other branches have their own counters, except that branches 2 KiB apart share one.
+		assert!(!bimodal.predict(0x102, false).taken);
+		assert!(bimodal.predict(0x100 + (1 << 11), false).taken);
+
+		// Mispredicts every outcome of a pattern that alternates.
+		assert_eq!(mispredictions(&mut *bimodal, 0x200, &[true, false], 10), 2);
+	}
+
+	#[test]
+	fn gshare() {
+		let mut gshare = Kind::Gshare.build();
+
+		// The global history distinguishes the outcomes of a pattern that alternates,
+		assert_eq!(mispredictions(&mut *gshare, 0x200, &[true, false], 10), 0);
+
+		// and of a loop branch that is not taken every fourth time.
+		assert_eq!(mispredictions(&mut *gshare, 0x300, &[true, true, true, false], 10), 0);
+
+		// The prediction records the history it was made with, so the same counter is trained
+		// even if other branches are trained in between.
+		let mut gshare = Gshare::default();
+		let prediction = gshare.predict(0x400, false);
+		for _ in 0..3 {
+			let other = gshare.predict(0x500, false);
+			gshare.update(0x500, other, true);
+		}
+		gshare.update(0x400, prediction, true);
+		assert!(gshare.counters[Gshare::index(0x400, 0)].taken());
+		assert!(!gshare.counters[Gshare::index(0x400, 0b111)].taken());
+		assert_eq!(gshare.history, 0b1111);
+	}
+
+	#[test]
+	fn tage() {
+		let mut tage = Kind::Tage.build();
+
+		// Without any tagged entries, it predicts like the bimodal base predictor.
+		assert!(!tage.predict(0x100, true).taken);
+		assert_eq!(mispredictions(&mut *tage, 0x100, &[true], 3), 0);
+
+		// Learns patterns that the base predictor cannot, by allocating tagged entries on mispredictions.
+		assert_eq!(mispredictions(&mut *tage, 0x200, &[true, false], 20), 0);
+		assert_eq!(mispredictions(&mut *tage, 0x300, &[true, true, true, true, true, true, false], 20), 0);
+
+		// A loop branch with a trip count that needs more history than gshare's 10 bits.
+		let pattern: Vec<_> = (0..12).map(|i| i != 11).collect();
+		assert_eq!(mispredictions(&mut *tage, 0x400, &pattern, 50), 0);
+		assert_ne!(mispredictions(&mut *Kind::Gshare.build(), 0x400, &pattern, 50), 0);
+	}
+
+	#[test]
+	fn branch_target_buffer() {
+		// Disabled
+		let mut btb = BranchTargetBuffer::new(0);
+		btb.update(0x100, 0x200);
+		assert_eq!(btb.predict(0x100), None);
+
+		// Rounded up to 4 entries, indexed by halfword address.
+		let mut btb = BranchTargetBuffer::new(3);
+		assert_eq!(btb.predict(0x100), None);
+		btb.update(0x100, 0x200);
+		btb.update(0x102, 0x300);
+		assert_eq!(btb.predict(0x100), Some(0x200));
+		assert_eq!(btb.predict(0x102), Some(0x300));
+
+		// 0x108 maps to the same entry as 0x100, but does not hit for it.
+		assert_eq!(btb.predict(0x108), None);
+		btb.update(0x108, 0x400);
+		assert_eq!(btb.predict(0x108), Some(0x400));
+		assert_eq!(btb.predict(0x100), None);
+
+		// The target is replaced when it changes.
+		btb.update(0x102, 0x500);
+		assert_eq!(btb.predict(0x102), Some(0x500));
+	}
+}
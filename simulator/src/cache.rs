@@ -0,0 +1,425 @@
+// A timing model of set-associative caches in front of the memory-backed regions.
+//
+// The caches only track which lines they hold and whether those lines are dirty. The data itself is always read from
+// and written to the regions, so the caches affect how many cycles an access takes but never what it reads.
+//
+// The L1I cache is accessed by instruction fetches and the L1D cache by loads and stores. Both are backed by the L2 cache
+// if there is one. An access that misses in an L1 cache takes that cache's miss latency to fetch the line from the L2 cache,
+// plus the L2 cache's miss latency if it misses there too. All caches are write-back and write-allocate.
+// Dirty lines that are evicted from an L1 cache are written to the L2 cache through a write buffer, so writebacks take
+// no cycles. The L2 cache is neither inclusive nor exclusive of the L1 caches.
+//
+// A cache can be given on the command line as comma-separated `key=value` pairs,
+// like `name=l1d,size=0x4000,ways=4,line_size=64,replacement=tree-plru,miss_latency=10`.
+
+use crate::config;
+
+#[derive(Debug, Default)]
+pub(crate) struct Caches {
+	l1i: Option<Cache>,
+	l1d: Option<Cache>,
+	l2: Option<Cache>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Access {
+	Fetch,
+	Load,
+	Store,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Config {
+	/// In bytes.
+	size: u64,
+	ways: usize,
+	/// In bytes.
+	line_size: u64,
+	replacement: Replacement,
+	/// The extra cycles taken by an access that misses.
+	miss_latency: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Replacement {
+	/// Like `tc/sv/ram_cache_tree_plru.sv`, a binary tree of bits per set where each bit points away from the half of the set
+	/// that was accessed more recently.
+	TreePlru,
+	Lru,
+	/// Chosen by a xorshift generator with a fixed seed, so that runs are repeatable.
+	Random,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Statistics {
+	hits: usize,
+	misses: usize,
+	evictions: usize,
+	writebacks: usize,
+}
+
+#[derive(Debug)]
+struct Cache {
+	config: Config,
+	sets: Box<[Set]>,
+	/// Incremented on every access, to order the accesses for LRU replacement.
+	clock: u64,
+	rng: u64,
+	statistics: Statistics,
+}
+
+#[derive(Debug)]
+struct Set {
+	lines: Box<[Line]>,
+	/// The bits of the tree-PLRU tree, with the root at bit 1 and the children of bit `n` at bits `2n` and `2n + 1`.
+	plru: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Line {
+	/// The address of the line divided by the line size, or `None` if the line is invalid.
+	tag: Option<u64>,
+	dirty: bool,
+	last_used: u64,
+}
+
+struct Lookup {
+	hit: bool,
+	/// The address of the dirty line that was evicted to make room for the accessed line.
+	writeback: Option<u64>,
+}
+
+impl Caches {
+	/// Adds a cache given on the command line, or replaces the cache with the same name.
+	pub(crate) fn set(&mut self, arg: &str) -> Result<(), config::Error> {
+		let mut name = None;
+		let mut config = Config::default();
+		for pair in config::pairs(arg) {
+			let pair = pair?;
+			match pair.key {
+				"name" => name = Some(match pair.value {
+					"l1i" => &mut self.l1i,
+					"l1d" => &mut self.l1d,
+					"l2" => &mut self.l2,
+					_ => return Err(pair.invalid_value()),
+				}),
+				"size" => config.size = pair.integer()?,
+				"ways" => config.ways = pair.integer()?,
+				"line_size" => config.line_size = pair.integer()?,
+				"replacement" => config.replacement = match pair.value {
+					"tree-plru" => Replacement::TreePlru,
+					"lru" => Replacement::Lru,
+					"random" => Replacement::Random,
+					_ => return Err(pair.invalid_value()),
+				},
+				"miss_latency" => config.miss_latency = pair.integer()?,
+				_ => return Err(pair.unknown_key()),
+			}
+		}
+
+		let name = name.ok_or(config::Error::MissingKey { key: "name" })?;
+		*name = Some(Cache::new(config)?);
+		Ok(())
+	}
+
+	/// Returns the extra cycles taken by an access to the `len` bytes at `address`, which must not wrap around.
+	pub(crate) fn access(&mut self, address: u64, len: usize, access: Access) -> u8 {
+		let Self { l1i, l1d, l2 } = self;
+		let l1 = match access {
+			Access::Fetch => l1i,
+			Access::Load | Access::Store => l1d,
+		};
+		let write = matches!(access, Access::Store);
+
+		let Some(l1) = l1 else {
+			return l2.as_mut().map_or(0, |l2| l2.access_lines(address, len, write));
+		};
+
+		let mut cycles = 0_u8;
+		for line in l1.lines(address, len) {
+			let Lookup { hit, writeback } = l1.access(line, write);
+
+			if let (Some(writeback), Some(l2)) = (writeback, &mut *l2) {
+				_ = l2.access_lines(writeback, l1.line_len(), true);
+			}
+
+			if !hit {
+				cycles = cycles.saturating_add(l1.config.miss_latency);
+				if let Some(l2) = l2 {
+					cycles = cycles.saturating_add(l2.access_lines(line, l1.line_len(), false));
+				}
+			}
+		}
+		cycles
+	}
+
+	pub(crate) fn statistics(&self) -> std::collections::BTreeMap<&'static str, Statistics> {
+		[("l1i", &self.l1i), ("l1d", &self.l1d), ("l2", &self.l2)].into_iter()
+			.filter_map(|(name, cache)| Some((name, cache.as_ref()?.statistics)))
+			.collect()
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			size: 0x4000,
+			ways: 4,
+			line_size: 64,
+			replacement: Replacement::TreePlru,
+			miss_latency: 10,
+		}
+	}
+}
+
+impl Cache {
+	fn new(config: Config) -> Result<Self, config::Error> {
+		if !config.line_size.is_power_of_two() || config.line_size < 8 {
+			return Err(config::Error::InvalidValue { key: "line_size".to_owned() });
+		}
+		if !config.ways.is_power_of_two() || config.ways > 64 {
+			return Err(config::Error::InvalidValue { key: "ways".to_owned() });
+		}
+		let ways = u64::try_from(config.ways).expect("ways fit in u64");
+		let num_sets = config.size / (config.line_size * ways);
+		if !config.size.is_power_of_two() || num_sets == 0 {
+			return Err(config::Error::InvalidValue { key: "size".to_owned() });
+		}
+		let num_sets = usize::try_from(num_sets).map_err(|_| config::Error::InvalidValue { key: "size".to_owned() })?;
+
+		Ok(Self {
+			config,
+			sets: (0..num_sets).map(|_| Set { lines: vec![Line::default(); config.ways].into(), plru: 0 }).collect(),
+			clock: 0,
+			rng: 0x2545_f491_4f6c_dd1d,
+			statistics: Default::default(),
+		})
+	}
+
+	fn line_len(&self) -> usize {
+		usize::try_from(self.config.line_size).expect("line size fits in usize")
+	}
+
+	/// Returns the addresses of the lines that contain the `len` bytes at `address`.
+	fn lines(&self, address: u64, len: usize) -> impl Iterator<Item = u64> + use<> {
+		let line_size = self.config.line_size;
+		let last = address + (u64::try_from(len).expect("usize fits in u64") - 1);
+		(address / line_size..=last / line_size).map(move |line| line * line_size)
+	}
+
+	/// Accesses every line that contains the `len` bytes at `address`, and returns the extra cycles taken.
+	fn access_lines(&mut self, address: u64, len: usize, write: bool) -> u8 {
+		let mut cycles = 0_u8;
+		for line in self.lines(address, len) {
+			if !self.access(line, write).hit {
+				cycles = cycles.saturating_add(self.config.miss_latency);
+			}
+		}
+		cycles
+	}
+
+	fn access(&mut self, address: u64, write: bool) -> Lookup {
+		self.clock += 1;
+
+		let tag = address / self.config.line_size;
+		let num_sets = self.sets.len();
+		let set = &mut self.sets[usize::try_from(tag).unwrap_or_default() & (num_sets - 1)];
+
+		if let Some(way) = set.lines.iter().position(|line| line.tag == Some(tag)) {
+			self.statistics.hits += 1;
+			let line = &mut set.lines[way];
+			line.dirty |= write;
+			line.last_used = self.clock;
+			set.touch(way);
+			return Lookup { hit: true, writeback: None };
+		}
+
+		self.statistics.misses += 1;
+
+		let way = set.lines.iter().position(|line| line.tag.is_none()).unwrap_or_else(|| match self.config.replacement {
+			Replacement::TreePlru => set.plru_victim(),
+			Replacement::Lru => set.lines.iter().enumerate().min_by_key(|(_, line)| line.last_used).map_or(0, |(way, _)| way),
+			Replacement::Random => {
+				self.rng ^= self.rng << 13;
+				self.rng ^= self.rng >> 7;
+				self.rng ^= self.rng << 17;
+				usize::try_from(self.rng % u64::try_from(set.lines.len()).expect("ways fit in u64")).expect("way fits in usize")
+			},
+		});
+
+		let victim = std::mem::replace(&mut set.lines[way], Line { tag: Some(tag), dirty: write, last_used: self.clock });
+		set.touch(way);
+
+		let writeback = victim.tag.and_then(|victim_tag| {
+			self.statistics.evictions += 1;
+			victim.dirty.then(|| {
+				self.statistics.writebacks += 1;
+				victim_tag * self.config.line_size
+			})
+		});
+		Lookup { hit: false, writeback }
+	}
+}
+
+impl Set {
+	fn touch(&mut self, way: usize) {
+		let mut node = way + self.lines.len();
+		while node > 1 {
+			let parent = node / 2;
+			// Point at the other child of the parent.
+			if node.is_multiple_of(2) {
+				self.plru |= 1 << parent;
+			}
+			else {
+				self.plru &= !(1 << parent);
+			}
+			node = parent;
+		}
+	}
+
+	fn plru_victim(&self) -> usize {
+		let mut node = 1;
+		while node < self.lines.len() {
+			node = 2 * node + usize::from(self.plru & (1 << node) != 0);
+		}
+		node - self.lines.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Access, Cache, Caches, Config, Replacement};
+
+	const LINE_SIZE: u64 = 64;
+
+	/// A cache with a single set of `ways` lines.
+	fn cache(ways: usize, replacement: Replacement) -> Cache {
+		let ways_ = u64::try_from(ways).unwrap();
+		Cache::new(Config { size: ways_ * LINE_SIZE, ways, line_size: LINE_SIZE, replacement, miss_latency: 10 }).unwrap()
+	}
+
+	/// Accesses each line in turn, and returns the tags in each way of the set afterwards.
+	fn ways_after(cache: &mut Cache, lines: &[u64]) -> Vec<Option<u64>> {
+		for &line in lines {
+			_ = cache.access(line * LINE_SIZE, false);
+		}
+		cache.sets[0].lines.iter().map(|line| line.tag).collect()
+	}
+
+	#[test]
+	fn tree_plru() {
+		// Like a tree of `tc/sv/ram_cache_tree_plru.sv` nodes, each of which starts with its left side as the oldest,
+		// and points at the other side after an access to one side.
+		let mut cache = cache(4, Replacement::TreePlru);
+		assert_eq!(ways_after(&mut cache, &[0xa, 0xb, 0xc, 0xd]), [Some(0xa), Some(0xb), Some(0xc), Some(0xd)]);
+		// The victims are ways 0, 2, 1 and 3 in that order.
+		assert_eq!(ways_after(&mut cache, &[0xe]), [Some(0xe), Some(0xb), Some(0xc), Some(0xd)]);
+		assert_eq!(ways_after(&mut cache, &[0xf]), [Some(0xe), Some(0xb), Some(0xf), Some(0xd)]);
+		assert_eq!(ways_after(&mut cache, &[0x10]), [Some(0xe), Some(0x10), Some(0xf), Some(0xd)]);
+		assert_eq!(ways_after(&mut cache, &[0x11]), [Some(0xe), Some(0x10), Some(0xf), Some(0x11)]);
+
+		// A hit moves the victim away from the accessed line's half of the set.
+		let mut cache = self::cache(4, Replacement::TreePlru);
+		assert_eq!(ways_after(&mut cache, &[0xa, 0xb, 0xc, 0xd, 0xa, 0xe]), [Some(0xa), Some(0xb), Some(0xe), Some(0xd)]);
+		assert_eq!(ways_after(&mut cache, &[0xb, 0xf]), [Some(0xa), Some(0xb), Some(0xe), Some(0xf)]);
+		assert_eq!(ways_after(&mut cache, &[0x10]), [Some(0x10), Some(0xb), Some(0xe), Some(0xf)]);
+
+		assert_eq!((cache.statistics.hits, cache.statistics.misses, cache.statistics.evictions, cache.statistics.writebacks), (2, 7, 3, 0));
+	}
+
+	#[test]
+	fn lru() {
+		let mut cache = cache(4, Replacement::Lru);
+		assert_eq!(ways_after(&mut cache, &[0xa, 0xb, 0xc, 0xd, 0xa, 0xe]), [Some(0xa), Some(0xe), Some(0xc), Some(0xd)]);
+		assert_eq!(ways_after(&mut cache, &[0xc, 0xf]), [Some(0xa), Some(0xe), Some(0xc), Some(0xf)]);
+		assert_eq!(ways_after(&mut cache, &[0x10]), [Some(0x10), Some(0xe), Some(0xc), Some(0xf)]);
+		assert_eq!((cache.statistics.hits, cache.statistics.misses, cache.statistics.evictions), (2, 7, 3));
+	}
+
+	#[test]
+	fn random() {
+		// Repeatable across runs, and not stuck on one way.
+		let lines: Vec<_> = (0..32).collect();
+		let mut a = cache(4, Replacement::Random);
+		let mut b = cache(4, Replacement::Random);
+		let mut victims = std::collections::BTreeSet::new();
+		for &line in &lines {
+			let before = ways_after(&mut a, &[]);
+			let after = ways_after(&mut a, &[line]);
+			assert_eq!(after, ways_after(&mut b, &[line]));
+			victims.extend((0..4).filter(|&way| before[way].is_some() && before[way] != after[way]));
+		}
+		assert_eq!(victims.len(), 4);
+		assert_eq!((a.statistics.hits, a.statistics.misses, a.statistics.evictions), (0, 32, 28));
+	}
+
+	#[test]
+	fn write_back() {
+		let mut cache = cache(2, Replacement::Lru);
+
+		// Stores allocate the line and make it dirty, and hits on it keep it dirty.
+		assert!(!cache.access(0xa * LINE_SIZE, true).hit);
+		assert!(cache.access(0xa * LINE_SIZE + 8, false).hit);
+		assert!(!cache.access(0xb * LINE_SIZE, false).hit);
+
+		// Evicting the clean line does not write it back, but evicting the dirty line does.
+		let lookup = cache.access(0xc * LINE_SIZE, false);
+		assert!(!lookup.hit);
+		assert_eq!(lookup.writeback, Some(0xa * LINE_SIZE));
+		let lookup = cache.access(0xd * LINE_SIZE, false);
+		assert_eq!(lookup.writeback, None);
+
+		assert_eq!((cache.statistics.hits, cache.statistics.misses, cache.statistics.evictions, cache.statistics.writebacks), (1, 4, 2, 1));
+	}
+
+	#[test]
+	fn l1_and_l2() {
+		let mut caches = Caches::default();
+		caches.set("name=l1d,size=128,ways=2,line_size=64,replacement=lru,miss_latency=3").unwrap();
+		caches.set("name=l2,size=1024,ways=4,line_size=64,miss_latency=20").unwrap();
+
+		// Misses in both, then hits in the L1 cache.
+		assert_eq!(caches.access(0x1000, 8, Access::Store), 23);
+		assert_eq!(caches.access(0x1000, 8, Access::Load), 0);
+		// An access that spans two lines misses on each of them. The second line evicts the dirty line,
+		// which is written back to the L2 cache where it hits, without taking any cycles.
+		assert_eq!(caches.access(0x2000 - 4, 8, Access::Load), 46);
+		// The line that was written back is still in the L2 cache.
+		assert_eq!(caches.access(0x1000, 8, Access::Load), 3);
+
+		// Fetches have no L1I cache, so they only go through the L2 cache.
+		assert_eq!(caches.access(0x1000, 2, Access::Fetch), 0);
+		assert_eq!(caches.access(0x8000, 2, Access::Fetch), 20);
+
+		let statistics = caches.statistics();
+		assert_eq!(statistics.keys().copied().collect::<Vec<_>>(), ["l1d", "l2"]);
+		let l1d = statistics["l1d"];
+		assert_eq!((l1d.hits, l1d.misses, l1d.evictions, l1d.writebacks), (1, 4, 2, 1));
+		let l2 = statistics["l2"];
+		assert_eq!((l2.hits, l2.misses, l2.evictions, l2.writebacks), (3, 4, 0, 0));
+	}
+
+	#[test]
+	fn config() {
+		static TESTS: &[(&str, Option<&str>)] = &[
+			("name=l1i", None),
+			("name=l2,size=0x10000,ways=8,line_size=32,replacement=random,miss_latency=40", None),
+			("size=0x4000", Some("name is required")),
+			("name=l3", Some("name has an invalid value")),
+			("name=l1d,size", Some("\"name=l1d,size\" is not a list of key=value pairs")),
+			("name=l1d,color=red", Some("unknown key color")),
+			("name=l1d,line_size=48", Some("line_size has an invalid value")),
+			("name=l1d,line_size=4", Some("line_size has an invalid value")),
+			("name=l1d,ways=3", Some("ways has an invalid value")),
+			("name=l1d,ways=128,size=0x10000", Some("ways has an invalid value")),
+			("name=l1d,size=0x3000", Some("size has an invalid value")),
+			("name=l1d,size=128,ways=4", Some("size has an invalid value")),
+			("name=l1d,replacement=fifo", Some("replacement has an invalid value")),
+			("name=l1d,miss_latency=256", Some("miss_latency has an invalid value")),
+		];
+		for &(arg, expected) in TESTS {
+			let actual = Caches::default().set(arg).err().map(|err| err.to_string());
+			assert_eq!(actual.as_deref(), expected, "{arg}");
+		}
+	}
+}
@@ -0,0 +1,183 @@
+use crate::csrs::{MIP_MSIP, MIP_MTIP};
+
+// Ref: SiFive FU540-C000 Manual 9 Core Local Interruptor (CLINT)
+//
+// +--------+----------+-------+
+// | Offset | Register | Width |
+// +========+==========+=======+
+// | 0x0000 | msip     | 4     |
+// | 0x4000 | mtimecmp | 8     |
+// | 0xbff8 | mtime    | 8     |
+// +--------+----------+-------+
+//
+// mtime increments once per cycle, so timer interrupts are deterministic.
+// Every other offset is reserved, reads as 0 and ignores writes.
+#[derive(Debug)]
+pub(crate) struct Clint {
+	msip: u32,
+	mtimecmp: u64,
+	mtime: u64,
+}
+
+const MSIP: u64 = 0x0000;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+
+impl Clint {
+	pub(crate) fn load(&self, offset: u64) -> [u8; 8] {
+		let mut result = [0_u8; 8];
+		for (r, offset) in result.iter_mut().zip(offset..) {
+			*r = self.load_byte(offset);
+		}
+		result
+	}
+
+	pub(crate) fn store(&mut self, offset: u64, data: &[u8]) {
+		for (offset, &b) in (offset..).zip(data) {
+			self.store_byte(offset, b);
+		}
+	}
+
+	pub(crate) fn tick(&mut self, cycles: i64) {
+		self.mtime = self.mtime.wrapping_add(cycles.cast_unsigned());
+	}
+
+	pub(crate) fn mtime(&self) -> u64 {
+		self.mtime
+	}
+
+	/// Returns the bits of `mip` that the CLINT drives.
+	pub(crate) fn interrupts_pending(&self) -> i64 {
+		let mut result = 0;
+		if self.msip & 0b1 != 0 {
+			result |= MIP_MSIP;
+		}
+		if self.mtime >= self.mtimecmp {
+			result |= MIP_MTIP;
+		}
+		result
+	}
+
+	fn load_byte(&self, offset: u64) -> u8 {
+		#[allow(clippy::cast_possible_truncation)]
+		match offset {
+			MSIP..MTIMECMP => self.msip.to_le_bytes().get((offset - MSIP) as usize).copied().unwrap_or_default(),
+			MTIMECMP..MTIME => self.mtimecmp.to_le_bytes().get((offset - MTIMECMP) as usize).copied().unwrap_or_default(),
+			MTIME.. => self.mtime.to_le_bytes().get((offset - MTIME) as usize).copied().unwrap_or_default(),
+		}
+	}
+
+	fn store_byte(&mut self, offset: u64, b: u8) {
+		fn store(reg: &mut [u8], offset: u64, b: u8) {
+			if let Some(r) = usize::try_from(offset).ok().and_then(|offset| reg.get_mut(offset)) {
+				*r = b;
+			}
+		}
+
+		match offset {
+			MSIP..MTIMECMP => {
+				let mut reg = self.msip.to_le_bytes();
+				store(&mut reg, offset - MSIP, b);
+				// Only bit 0 is writable
+				self.msip = u32::from_le_bytes(reg) & 0b1;
+			},
+
+			MTIMECMP..MTIME => {
+				let mut reg = self.mtimecmp.to_le_bytes();
+				store(&mut reg, offset - MTIMECMP, b);
+				self.mtimecmp = u64::from_le_bytes(reg);
+			},
+
+			MTIME.. => {
+				let mut reg = self.mtime.to_le_bytes();
+				store(&mut reg, offset - MTIME, b);
+				self.mtime = u64::from_le_bytes(reg);
+			},
+		}
+	}
+}
+
+impl Default for Clint {
+	fn default() -> Self {
+		Self {
+			msip: 0,
+			// mtimecmp is not reset by the hardware, but software expects no timer interrupt
+			// until it has written to it.
+			mtimecmp: u64::MAX,
+			mtime: 0,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::csrs::{MIP_MSIP, MIP_MTIP};
+
+	#[test]
+	fn registers() {
+		let mut clint: super::Clint = Default::default();
+		assert_eq!(clint.load(super::MSIP), [0; 8]);
+		assert_eq!(clint.load(super::MTIMECMP), [0xff; 8]);
+		assert_eq!(clint.load(super::MTIME), [0; 8]);
+
+		clint.store(super::MSIP, &0xffff_ffff_u32.to_le_bytes());
+		assert_eq!(clint.load(super::MSIP), [1, 0, 0, 0, 0, 0, 0, 0]);
+
+		clint.store(super::MTIMECMP, &0x0102_0304_0506_0708_u64.to_le_bytes());
+		assert_eq!(u64::from_le_bytes(clint.load(super::MTIMECMP)), 0x0102_0304_0506_0708);
+
+		clint.store(super::MTIME, &0x1112_1314_1516_1718_u64.to_le_bytes());
+		assert_eq!(u64::from_le_bytes(clint.load(super::MTIME)), 0x1112_1314_1516_1718);
+		assert_eq!(clint.mtime(), 0x1112_1314_1516_1718);
+
+		clint.tick(2);
+		assert_eq!(clint.mtime(), 0x1112_1314_1516_171a);
+
+		// Reserved offsets
+		clint.store(0x8, &[0xff; 8]);
+		assert_eq!(clint.load(0x8), [0; 8]);
+		clint.store(0x4008, &[0xff; 8]);
+		assert_eq!(clint.load(0x4008), [0; 8]);
+		assert_eq!(u64::from_le_bytes(clint.load(super::MTIMECMP)), 0x0102_0304_0506_0708);
+	}
+
+	#[test]
+	fn mtimecmp_halves() {
+		let mut clint: super::Clint = Default::default();
+
+		clint.store(super::MTIMECMP, &0x1234_5678_u32.to_le_bytes());
+		assert_eq!(u64::from_le_bytes(clint.load(super::MTIMECMP)), 0xffff_ffff_1234_5678);
+
+		clint.store(super::MTIMECMP + 4, &0x9abc_def0_u32.to_le_bytes());
+		assert_eq!(u64::from_le_bytes(clint.load(super::MTIMECMP)), 0x9abc_def0_1234_5678);
+
+		assert_eq!(clint.load(super::MTIMECMP + 4)[..4], 0x9abc_def0_u32.to_le_bytes());
+	}
+
+	#[test]
+	fn interrupts_pending() {
+		let mut clint: super::Clint = Default::default();
+		assert_eq!(clint.interrupts_pending(), 0);
+
+		clint.store(super::MTIMECMP, &3_u64.to_le_bytes());
+		clint.tick(2);
+		assert_eq!(clint.interrupts_pending(), 0);
+		clint.tick(1);
+		assert_eq!(clint.interrupts_pending(), MIP_MTIP);
+		clint.tick(1);
+		assert_eq!(clint.interrupts_pending(), MIP_MTIP);
+
+		clint.store(super::MTIMECMP, &u64::MAX.to_le_bytes());
+		assert_eq!(clint.interrupts_pending(), 0);
+
+		clint.store(super::MSIP, &[1]);
+		assert_eq!(clint.interrupts_pending(), MIP_MSIP);
+		clint.store(super::MSIP, &[2]);
+		assert_eq!(clint.interrupts_pending(), 0);
+		clint.store(super::MSIP, &[3]);
+		assert_eq!(clint.interrupts_pending(), MIP_MSIP);
+
+		clint.store(super::MTIMECMP, &0_u64.to_le_bytes());
+		assert_eq!(clint.interrupts_pending(), MIP_MSIP | MIP_MTIP);
+	}
+}
@@ -0,0 +1,105 @@
+//! The comma-separated `key=value` pairs taken by `--ooo-config`, `--pipeline-config`, `--dual-issue-config`, `--cache` and `--fusion`.
+
+/// Splits `arg` into its pairs.
+pub(crate) fn pairs(arg: &str) -> impl Iterator<Item = Result<Pair<'_>, Error>> {
+	arg.split(',').map(move |pair| match pair.split_once('=') {
+		Some((key, value)) => Ok(Pair { key, value }),
+		None => Err(Error::InvalidPair { arg: arg.to_owned() }),
+	})
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Pair<'a> {
+	pub(crate) key: &'a str,
+	pub(crate) value: &'a str,
+}
+
+impl Pair<'_> {
+	pub(crate) fn invalid_value(self) -> Error {
+		Error::InvalidValue { key: self.key.to_owned() }
+	}
+
+	pub(crate) fn unknown_key(self) -> Error {
+		Error::UnknownKey { key: self.key.to_owned() }
+	}
+
+	pub(crate) fn bool(self) -> Result<bool, Error> {
+		match self.value {
+			"true" => Ok(true),
+			"false" => Ok(false),
+			_ => Err(self.invalid_value()),
+		}
+	}
+
+	/// Parses the value like a memory map integer, and checks that it fits in a `T`.
+	pub(crate) fn integer<T: TryFrom<u64>>(self) -> Result<T, Error> {
+		crate::memory_map::parse_integer(self.value).and_then(|value| value.try_into().ok()).ok_or_else(|| self.invalid_value())
+	}
+
+	pub(crate) fn non_zero(self) -> Result<std::num::NonZero<usize>, Error> {
+		std::num::NonZero::new(self.integer()?).ok_or_else(|| self.invalid_value())
+	}
+
+	pub(crate) fn div_radix(self) -> Result<crate::divider::Radix, Error> {
+		self.integer::<usize>()?.try_into().map_err(|()| self.invalid_value())
+	}
+}
+
+#[derive(Debug)]
+pub(crate) enum Error {
+	InvalidPair { arg: String },
+	InvalidValue { key: String },
+	MissingKey { key: &'static str },
+	TooSmall { key: &'static str, min: &'static str },
+	UnknownKey { key: String },
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::InvalidPair { arg } => write!(f, "{arg:?} is not a list of key=value pairs"),
+			Self::InvalidValue { key } => write!(f, "{key} has an invalid value"),
+			Self::MissingKey { key } => write!(f, "{key} is required"),
+			Self::TooSmall { key, min } => write!(f, "{key} must be at least {min}"),
+			Self::UnknownKey { key } => write!(f, "unknown key {key}"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	#[test]
+	fn pairs() {
+		static TESTS: &[(&str, Result<&[(&str, &str)], &str>)] = &[
+			("a=1", Ok(&[("a", "1")])),
+			("a=1,b=,c=x=y", Ok(&[("a", "1"), ("b", ""), ("c", "x=y")])),
+			("a=1,b", Err(r#""a=1,b" is not a list of key=value pairs"#)),
+			("", Err(r#""" is not a list of key=value pairs"#)),
+		];
+
+		for &(arg, expected) in TESTS {
+			let actual: Result<Vec<_>, _> = super::pairs(arg).map(|pair| pair.map(|pair| (pair.key, pair.value))).collect();
+			let actual = actual.as_deref().map_err(ToString::to_string);
+			assert_eq!(actual, expected.map_err(ToOwned::to_owned), "{arg:?}");
+		}
+	}
+
+	#[test]
+	fn values() {
+		let pair = |value| super::Pair { key: "k", value };
+
+		assert!(pair("true").bool().unwrap());
+		assert!(!pair("false").bool().unwrap());
+		assert_eq!(pair("1").bool().unwrap_err().to_string(), "k has an invalid value");
+
+		assert_eq!(pair("0x10").integer::<u8>().unwrap(), 16);
+		assert!(pair("0x100").integer::<u8>().is_err());
+		assert!(pair("x").integer::<u8>().is_err());
+
+		assert_eq!(pair("3").non_zero().unwrap().get(), 3);
+		assert!(pair("0").non_zero().is_err());
+
+		assert!(pair("4").div_radix().is_ok());
+		assert!(pair("3").div_radix().is_err());
+	}
+}
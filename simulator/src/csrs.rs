@@ -1,10 +1,21 @@
-use crate::{RegisterValue, Tag};
+use crate::{RegisterValue, Tag, exception::Exception, memory::Memory};
 
 #[derive(Debug)]
 pub(crate) struct Csrs {
+	ticks: i64,
 	cycle: i64,
 	instret: i64,
 	time: i64,
+	mstatus: (i64, Option<Tag>),
+	mtvec: (i64, Option<Tag>),
+	mepc: (i64, Option<Tag>),
+	mcause: (i64, Option<Tag>),
+	mtval: (i64, Option<Tag>),
+	mscratch: (i64, Option<Tag>),
+	mie: (i64, Option<Tag>),
+	mip: i64,
+	mcountinhibit: (i64, Option<Tag>),
+	misa: i64,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -12,68 +23,285 @@ pub(crate) enum Csr {
 	Cycle,
 	Instret,
 	Time,
+	Mstatus,
+	Misa,
+	Mie,
+	Mtvec,
+	Mcountinhibit,
+	Mscratch,
+	Mepc,
+	Mcause,
+	Mtval,
+	Mip,
+	Mhartid,
 }
 
+// Ref: Privileged spec 3.1.6 Machine Status Registers (mstatus and mstatush)
+//
+// Only M-mode is implemented, so MPP is hard-wired to M.
+pub(crate) const MSTATUS_MIE: i64 = 1 << 3;
+pub(crate) const MSTATUS_MPIE: i64 = 1 << 7;
+const MSTATUS_MPP: i64 = 0b11 << 11;
+
+// Ref: Privileged spec 3.1.1 Machine ISA (misa) Register
+//
+// Reflects the extensions that the decoder accepts. B is never set since Zbs is not implemented. Writes are ignored.
+const MISA_MXL_32: i64 = 0b01 << 30;
+const MISA_MXL_64: i64 = 0b10 << 62;
+const MISA_C: i64 = 1 << 2;
+const MISA_I: i64 = 1 << 8;
+const MISA_M: i64 = 1 << 12;
+
+// Ref: Privileged spec 3.1.7 Machine Trap-Vector Base-Address (mtvec) Register
+//
+// Direct (0) and vectored (1) modes are implemented.
+const MTVEC_MODE_VECTORED: i64 = 1;
+
+// Ref: Privileged spec 3.1.9 Machine Interrupt (mip and mie) Registers
+//
+// mip is read-only since its bits are driven by devices.
+pub(crate) const MIP_MSIP: i64 = 1 << 3;
+pub(crate) const MIP_MTIP: i64 = 1 << 7;
+const MIE_WRITABLE: i64 = (1 << 3) | (1 << 7) | (1 << 11);
+
+// Ref: Privileged spec 3.1.12 Machine Counter-Inhibit (mcountinhibit) Register
+const MCOUNTINHIBIT_CY: i64 = 1 << 0;
+const MCOUNTINHIBIT_IR: i64 = 1 << 2;
+
 impl Csrs {
 	pub(crate) fn load(&self, csr: Csr) -> RegisterValue {
-		match csr {
-			Csr::Cycle => RegisterValue::Value(self.cycle),
-			Csr::Instret => RegisterValue::Value(self.instret),
-			Csr::Time => RegisterValue::Value(self.time),
+		let reg = match csr {
+			Csr::Cycle => return RegisterValue::Value(self.cycle),
+			Csr::Instret => return RegisterValue::Value(self.instret),
+			Csr::Time => return RegisterValue::Value(self.time),
+			Csr::Misa => return RegisterValue::Value(self.misa),
+			Csr::Mip => return RegisterValue::Value(self.mip),
+			Csr::Mhartid => return RegisterValue::Value(0),
+			Csr::Mstatus => self.mstatus,
+			Csr::Mie => self.mie,
+			Csr::Mtvec => self.mtvec,
+			Csr::Mcountinhibit => self.mcountinhibit,
+			Csr::Mscratch => self.mscratch,
+			Csr::Mepc => self.mepc,
+			Csr::Mcause => self.mcause,
+			Csr::Mtval => self.mtval,
+		};
+		if let Some(tag) = reg.1 {
+			RegisterValue::Tag(tag)
+		}
+		else {
+			RegisterValue::Value(reg.0)
 		}
 	}
 
-	#[allow(clippy::unused_self)]
-	pub(crate) fn rename(&mut self, csr: Csr, _tag: Tag) -> bool {
-		#[allow(clippy::match_same_arms)]
-		match csr {
-			Csr::Cycle => false,
-			Csr::Instret => false,
-			Csr::Time => false,
+	/// Returns false for CSRs that do not need to be renamed because writes to them are ignored.
+	pub(crate) fn rename(&mut self, csr: Csr, tag: Tag) -> bool {
+		if let Some(reg) = self.renamable_mut(csr) {
+			reg.1 = Some(tag);
+			true
+		}
+		else {
+			false
 		}
 	}
 
-	#[allow(clippy::unused_self)]
-	pub(crate) fn store(&mut self, csr: Csr, _tag: Tag, _value: i64) {
-		match csr {
-			Csr::Cycle => panic!("cycle CSR is read-only"),
-			Csr::Instret => panic!("instret CSR is read-only"),
-			Csr::Time => panic!("time CSR is read-only"),
+	pub(crate) fn store(&mut self, csr: Csr, tag: Tag, value: i64) {
+		if let Some(reg) = self.renamable_mut(csr) {
+			reg.0 = csr.legalize(value);
+			if reg.1 == Some(tag) {
+				reg.1 = None;
+			}
 		}
 	}
 
-	#[allow(clippy::unused_self)]
 	pub(crate) fn reset_all_tags(
 		&mut self,
-		_tags: impl IntoIterator<Item = (Csr, Tag, Option<i64>)>,
-	) {}
+		tags: impl IntoIterator<Item = (Csr, Tag, Option<i64>)>,
+	) {
+		for reg in [
+			&mut self.mstatus,
+			&mut self.mtvec,
+			&mut self.mepc,
+			&mut self.mcause,
+			&mut self.mtval,
+			&mut self.mscratch,
+			&mut self.mie,
+			&mut self.mcountinhibit,
+		] {
+			reg.1 = None;
+		}
+
+		for (csr, tag, _) in tags {
+			if let Some(reg) = self.renamable_mut(csr) {
+				reg.1 = Some(tag);
+			}
+		}
+	}
+
+	/// Records the trap in mstatus, mepc, mcause and mtval, and returns the address of the trap handler.
+	///
+	/// Returns `None` if no trap handler has been installed, ie mtvec is 0.
+	///
+	/// Must only be called when no CSR is renamed.
+	pub(crate) fn take_trap(&mut self, exception: Exception, pc: i64) -> Option<i64> {
+		let base = self.mtvec.0 & !0b11;
+		if base == 0 {
+			return None;
+		}
+
+		// Ref: Privileged spec 3.1.7: In vectored mode, interrupts go to BASE + 4 * cause and exceptions go to BASE.
+		let cause = exception.cause();
+		let handler =
+			if self.mtvec.0 & 0b11 == MTVEC_MODE_VECTORED && cause < 0 {
+				base.wrapping_add((cause & i64::MAX) << 2)
+			}
+			else {
+				base
+			};
+
+		let mie = self.mstatus.0 & MSTATUS_MIE;
+		self.mstatus.0 = Csr::Mstatus.legalize((self.mstatus.0 & !(MSTATUS_MIE | MSTATUS_MPIE)) | (mie << 4));
+		self.mepc.0 = pc;
+		self.mcause.0 = cause;
+		self.mtval.0 = exception.tval();
+		Some(handler)
+	}
+
+	/// Returns the interrupt that should be taken before the next instruction, if any.
+	///
+	/// Must only be called at an instruction boundary when no CSR is renamed.
+	pub(crate) fn pending_interrupt(&self) -> Option<Exception> {
+		if self.mstatus.0 & MSTATUS_MIE == 0 {
+			return None;
+		}
+
+		// Ref: Privileged spec 3.1.9: MSI has a higher priority than MTI
+		let pending = self.mip & self.mie.0;
+		if pending & MIP_MSIP != 0 {
+			Some(Exception::MachineSoftwareInterrupt)
+		}
+		else if pending & MIP_MTIP != 0 {
+			Some(Exception::MachineTimerInterrupt)
+		}
+		else {
+			None
+		}
+	}
 
-	pub(crate) fn cycle(&self) -> i64 {
-		self.cycle
+	/// Returns true if a `wfi` can complete, ie an interrupt enabled in mie is pending regardless of mstatus.MIE.
+	///
+	/// If no interrupt is enabled then nothing could ever wake the hart, so the `wfi` completes immediately.
+	pub(crate) fn wfi_done(&self) -> bool {
+		self.mie.0 == 0 || self.mip & self.mie.0 != 0
 	}
 
-	pub(crate) fn tick(&mut self, cycles: i64, instret: i64) {
-		self.cycle += cycles;
-		self.instret += instret;
-		self.time = nanos_since_unix_epoch();
+	pub(crate) fn ticks(&self) -> i64 {
+		self.ticks
+	}
+
+	pub(crate) fn tick(&mut self, cycles: i64, instret: i64, memory: &mut Memory) {
+		self.ticks += cycles;
+		if self.mcountinhibit.0 & MCOUNTINHIBIT_CY == 0 {
+			self.cycle += cycles;
+		}
+		if self.mcountinhibit.0 & MCOUNTINHIBIT_IR == 0 {
+			self.instret += instret;
+		}
+		memory.tick(cycles);
+		// Ref: Privileged spec 3.2.1: time is a read-only shadow of mtime.
+		//
+		// Without a CLINT, time counts cycles like mtime would.
+		self.time = memory.mtime().unwrap_or(self.ticks);
+		self.mip = memory.interrupts_pending();
+	}
+
+	fn renamable_mut(&mut self, csr: Csr) -> Option<&mut (i64, Option<Tag>)> {
+		match csr {
+			Csr::Cycle | Csr::Instret | Csr::Time | Csr::Misa | Csr::Mip | Csr::Mhartid => None,
+			Csr::Mstatus => Some(&mut self.mstatus),
+			Csr::Mie => Some(&mut self.mie),
+			Csr::Mtvec => Some(&mut self.mtvec),
+			Csr::Mcountinhibit => Some(&mut self.mcountinhibit),
+			Csr::Mscratch => Some(&mut self.mscratch),
+			Csr::Mepc => Some(&mut self.mepc),
+			Csr::Mcause => Some(&mut self.mcause),
+			Csr::Mtval => Some(&mut self.mtval),
+		}
 	}
 }
 
-impl Default for Csrs {
-	fn default() -> Self {
+impl Csr {
+	/// Returns the value that the CSR holds after `value` is written to it.
+	///
+	/// This only depends on `value` and not on the previous value of the CSR,
+	/// so it can be applied to a renamed CSR before its previous value is known.
+	pub(crate) fn legalize(self, value: i64) -> i64 {
+		match self {
+			Self::Mstatus => (value & (MSTATUS_MIE | MSTATUS_MPIE)) | MSTATUS_MPP,
+
+			Self::Mtvec => if value & 0b11 <= MTVEC_MODE_VECTORED { value } else { value & !0b11 },
+
+			Self::Mie => value & MIE_WRITABLE,
+
+			Self::Mcountinhibit => value & (MCOUNTINHIBIT_CY | MCOUNTINHIBIT_IR),
+
+			// IALIGN is 16
+			Self::Mepc => value & !0b1,
+
+			Self::Cycle |
+			Self::Instret |
+			Self::Time |
+			Self::Misa |
+			Self::Mscratch |
+			Self::Mcause |
+			Self::Mtval |
+			Self::Mip |
+			Self::Mhartid => value,
+		}
+	}
+}
+
+impl Csrs {
+	pub(crate) fn new(supported_extensions: riscv::SupportedExtensions) -> Self {
+		let mut misa = MISA_I | MISA_M;
+		misa |= if supported_extensions.contains(riscv::SupportedExtensions::RV64I) { MISA_MXL_64 } else { MISA_MXL_32 };
+		if supported_extensions.contains(riscv::SupportedExtensions::RVC) {
+			misa |= MISA_C;
+		}
+
 		Self {
+			ticks: 0,
 			cycle: 0,
 			instret: 0,
-			time: nanos_since_unix_epoch(),
+			time: 0,
+			mstatus: (MSTATUS_MPP, None),
+			mtvec: (0, None),
+			mepc: (0, None),
+			mcause: (0, None),
+			mtval: (0, None),
+			mscratch: (0, None),
+			mie: (0, None),
+			mip: 0,
+			mcountinhibit: (0, None),
+			misa,
 		}
 	}
 }
 
 impl std::fmt::Display for Csrs {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		writeln!(f, "cycle:   0x{:016x}", self.cycle)?;
-		writeln!(f, "time:    0x{:016x}", self.time)?;
-		writeln!(f, "instret: 0x{:016x}", self.instret)?;
+		writeln!(f, "cycle:         0x{:016x}", self.cycle)?;
+		writeln!(f, "time:          0x{:016x}", self.time)?;
+		writeln!(f, "instret:       0x{:016x}", self.instret)?;
+		writeln!(f, "mstatus:       0x{:016x}", self.mstatus.0)?;
+		writeln!(f, "mtvec:         0x{:016x}", self.mtvec.0)?;
+		writeln!(f, "mepc:          0x{:016x}", self.mepc.0)?;
+		writeln!(f, "mcause:        0x{:016x}", self.mcause.0)?;
+		writeln!(f, "mtval:         0x{:016x}", self.mtval.0)?;
+		writeln!(f, "mscratch:      0x{:016x}", self.mscratch.0)?;
+		writeln!(f, "mie:           0x{:016x}", self.mie.0)?;
+		writeln!(f, "mip:           0x{:016x}", self.mip)?;
+		writeln!(f, "mcountinhibit: 0x{:016x}", self.mcountinhibit.0)?;
 		Ok(())
 	}
 }
@@ -86,15 +314,40 @@ impl TryFrom<u32> for Csr {
 			0xc00 => Self::Cycle,
 			0xc01 => Self::Time,
 			0xc02 => Self::Instret,
+			0x300 => Self::Mstatus,
+			0x301 => Self::Misa,
+			0x304 => Self::Mie,
+			0x305 => Self::Mtvec,
+			0x320 => Self::Mcountinhibit,
+			0x340 => Self::Mscratch,
+			0x341 => Self::Mepc,
+			0x342 => Self::Mcause,
+			0x343 => Self::Mtval,
+			0x344 => Self::Mip,
+			0xf14 => Self::Mhartid,
 			_ => return Err(()),
 		})
 	}
 }
 
-fn nanos_since_unix_epoch() -> i64 {
-	std::time::SystemTime::now()
-		.duration_since(std::time::SystemTime::UNIX_EPOCH)
-		.unwrap()
-		.as_nanos()
-		.try_into().unwrap()
+#[cfg(test)]
+mod tests {
+	use super::{Csr, Csrs};
+	use crate::RegisterValue;
+
+	#[test]
+	fn misa() {
+		static TESTS: &[(riscv::SupportedExtensions, i64)] = &[
+			(riscv::SupportedExtensions::RV32I, 0x4000_1100),
+			(riscv::SupportedExtensions::RV32C, 0x4000_1104),
+			(riscv::SupportedExtensions::RV64I, 0x8000_0000_0000_1100_u64.cast_signed()),
+			(riscv::SupportedExtensions::RV64C_ZCB, 0x8000_0000_0000_1104_u64.cast_signed()),
+		];
+
+		for &(supported_extensions, expected) in TESTS {
+			let csrs = Csrs::new(supported_extensions | riscv::SupportedExtensions::ZBA | riscv::SupportedExtensions::ZBB);
+			let RegisterValue::Value(actual) = csrs.load(Csr::Misa) else { panic!("misa is not a value"); };
+			assert_eq!(actual, expected, "{supported_extensions:?}");
+		}
+	}
 }
@@ -0,0 +1,218 @@
+//! An SRT divider.
+//!
+//! The divisor is normalized so that its top bit is set, and the partial remainder is kept in `[-d, d)` instead of `[0, d)`.
+//! This lets each quotient digit be selected from {-1, 0, 1} by comparing the partial remainder with `±d / 2`,
+//! which only needs its top bits, instead of comparing it with the whole divisor like a restoring divider does.
+//! The positive and negative digits are kept separately and combined once all of them have been selected.
+//!
+//! A radix-2 divider selects one digit per round. A radix-4 divider selects two per round, by chaining two radix-2 stages.
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Radix {
+	Two,
+	Four,
+}
+
+impl TryFrom<usize> for Radix {
+	type Error = ();
+
+	fn try_from(radix: usize) -> Result<Self, Self::Error> {
+		match radix {
+			2 => Ok(Self::Two),
+			4 => Ok(Self::Four),
+			_ => Err(()),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct State {
+	/// 32 or 64.
+	width: u32,
+	/// The number of quotient digits that are left to be selected.
+	remaining: u32,
+	/// The bits of the normalized dividend that have not been shifted into the partial remainder yet.
+	n: u64,
+	/// The normalized divisor, or 0 for a division by zero.
+	d: u64,
+	/// How far the divisor was shifted to normalize it.
+	shift: u32,
+	/// The partial remainder.
+	p: i128,
+	q_pos: u64,
+	q_neg: u64,
+	negate_quotient: bool,
+	negate_remainder: bool,
+	/// The original dividend, which is the remainder of a division by zero.
+	dividend: i64,
+}
+
+#[derive(Debug)]
+pub(crate) enum Round {
+	Pending(State),
+	/// The results, sign-extended from `width` bits.
+	Done { quotient: i64, remainder: i64 },
+}
+
+impl State {
+	/// Starts dividing the low `width` bits of `n` by those of `d`.
+	pub(crate) fn initial(is_signed: bool, width: u32, n: i64, d: i64) -> Self {
+		let mask = u64::MAX >> (64 - width);
+		let truncate = |value: i64| value.cast_unsigned() & mask;
+		let is_negative = |value: i64| is_signed && sign_extend(width, truncate(value)) < 0;
+		let abs = |value: i64| if is_negative(value) { truncate(value.wrapping_neg()) } else { truncate(value) };
+
+		let (un, ud) = (abs(n), abs(d));
+		let shift = if ud == 0 { 0 } else { ud.leading_zeros() - (64 - width) };
+		let x = u128::from(un) << shift;
+
+		Self {
+			width,
+			remaining: width,
+			#[allow(clippy::cast_possible_truncation)]
+			n: x as u64 & mask,
+			d: ud << shift,
+			shift,
+			p: (x >> width).cast_signed(),
+			q_pos: 0,
+			q_neg: 0,
+			negate_quotient: is_negative(n) != is_negative(d),
+			negate_remainder: is_negative(n),
+			dividend: sign_extend(width, truncate(n)),
+		}
+	}
+}
+
+pub(crate) fn round(mut state: State, radix: Radix) -> Round {
+	if state.d == 0 {
+		// Ref: Unprivileged spec 13.2 Division Operations
+		//
+		// The quotient of a division by zero has all bits set, and the remainder is the dividend.
+		return Round::Done { quotient: -1, remainder: state.dividend };
+	}
+
+	let digits = match radix {
+		Radix::Two => 1,
+		Radix::Four => 2,
+	};
+	let half_d = 1_i128 << (state.width - 1);
+	for _ in 0..digits {
+		state.remaining -= 1;
+		state.p = (state.p << 1) | i128::from((state.n >> state.remaining) & 1);
+		if state.p >= half_d {
+			state.p -= i128::from(state.d);
+			state.q_pos |= 1 << state.remaining;
+		}
+		else if state.p < -half_d {
+			state.p += i128::from(state.d);
+			state.q_neg |= 1 << state.remaining;
+		}
+	}
+
+	if state.remaining > 0 {
+		return Round::Pending(state);
+	}
+
+	let mut quotient = state.q_pos.wrapping_sub(state.q_neg);
+	if state.p < 0 {
+		state.p += i128::from(state.d);
+		quotient = quotient.wrapping_sub(1);
+	}
+	let remainder = u64::try_from(state.p).expect("corrected partial remainder is in [0, d)") >> state.shift;
+
+	let negate = |value: u64, negate: bool| sign_extend(state.width, if negate { value.wrapping_neg() } else { value });
+	Round::Done {
+		quotient: negate(quotient, state.negate_quotient),
+		remainder: negate(remainder, state.negate_remainder),
+	}
+}
+
+fn sign_extend(width: u32, value: u64) -> i64 {
+	(value.cast_signed() << (64 - width)) >> (64 - width)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Radix, Round, State, round};
+
+	#[test]
+	fn it_works() {
+		const TESTS: &[(i64, i64)] = &[
+			(0, 0),
+			(0, 1),
+			(1, 0),
+			(1, 1),
+
+			(1, -1),
+			(-1, 1),
+			(-1, -1),
+
+			(-0x8000_0000_0000_0000, -1),
+			(-0x8000_0000_0000_0000, 1),
+			(-0x8000_0000, -1),
+			(0x7fff_ffff_ffff_ffff, -0x8000_0000_0000_0000),
+
+			(15, 6),
+			(-15, 6),
+			(15, -6),
+			(-15, -6),
+			(5, 15),
+
+			(0xa0b6_b812_9b5b_dfd9_u64.cast_signed(), 0xbcba_1c19_8109_3535_u64.cast_signed()),
+			(0xbcba_1c19_8109_3535_u64.cast_signed(), 0xa0b6_b812_9b5b_dfd9_u64.cast_signed()),
+			(0xbcba_1c19_8109_3535_u64.cast_signed(), 0x1c19),
+			(0x1234_5678_9abc_def0, 0x8109_3535),
+		];
+		for &(n, d) in TESTS {
+			for (is_signed, width) in [
+				(false, 32),
+				(false, 64),
+				(true, 32),
+				(true, 64),
+			] {
+				for radix in [Radix::Two, Radix::Four] {
+					let mut state = State::initial(is_signed, width, n, d);
+					let mut rounds = 0;
+					let (quotient, remainder) = loop {
+						rounds += 1;
+						match round(state, radix) {
+							Round::Pending(state_) => state = state_,
+							Round::Done { quotient, remainder } => break (quotient, remainder),
+						}
+					};
+					println!(
+						"0x{n:016x} / 0x{d:016x} ({}{width}, {radix:?}) -> 0x{quotient:016x} r 0x{remainder:016x} in {rounds} rounds",
+						if is_signed { "i" } else { "u" },
+					);
+
+					#[allow(clippy::cast_possible_truncation)]
+					let (expected_quotient, expected_remainder) = match (is_signed, width) {
+						(false, 32) => {
+							let (n, d) = ((n as i32).cast_unsigned(), (d as i32).cast_unsigned());
+							let (quotient, remainder) = n.checked_div(d).zip(n.checked_rem(d)).unwrap_or((u32::MAX, n));
+							(i64::from(quotient.cast_signed()), i64::from(remainder.cast_signed()))
+						},
+
+						(false, _) => {
+							let (n, d) = (n.cast_unsigned(), d.cast_unsigned());
+							let (quotient, remainder) = n.checked_div(d).zip(n.checked_rem(d)).unwrap_or((u64::MAX, n));
+							(quotient.cast_signed(), remainder.cast_signed())
+						},
+
+						(true, 32) => {
+							let (n, d) = (n as i32, d as i32);
+							let (quotient, remainder) = if d == 0 { (-1, n) } else { (n.wrapping_div(d), n.wrapping_rem(d)) };
+							(i64::from(quotient), i64::from(remainder))
+						},
+
+						(true, _) =>
+							if d == 0 { (-1, n) } else { (n.wrapping_div(d), n.wrapping_rem(d)) },
+					};
+
+					assert_eq!(quotient, expected_quotient);
+					assert_eq!(remainder, expected_remainder);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,486 @@
+use crate::{
+	config,
+	csrs::Csrs,
+	exception::Exception,
+	fusion,
+	gdb,
+	htif::Htif,
+	in_order::take_trap,
+	instruction::Instruction,
+	memory::Memory,
+	out_of_order::{FunctionalUnit, FunctionalUnitConfig},
+	symbols::Symbols,
+	syscalls::{SyscallResult, Syscalls},
+	tag::TagAllocator,
+	ucode::Ucode,
+	x_regs::XRegs,
+	LogLevel,
+	RegisterValue,
+	Statistics,
+	load_inst,
+};
+
+// An in-order core that can issue two adjacent instructions in the same cycle, like the SiFive U7 or the Cortex-A53.
+//
+// Instructions are decoded to ucodes and executed one at a time like the in-order ucode implementation, and the core
+// only models their timing. The ucodes of an instruction are issued one per cycle, each to one of the out-of-order
+// implementation's functional units or to a memory unit, and the next ucode is issued once the unit has produced its result.
+//
+// The first ucode of an instruction is issued in the same cycle as the last ucode of the instruction before it,
+// after which both take the longer of their times, if
+// - the instruction before it did not jump or branch away,
+// - neither of them accesses CSRs, fences, waits for interrupts or traps on purpose,
+// - the instruction was fetched without waiting for the instruction cache,
+// - the instruction neither reads nor writes the register written by the instruction before it,
+// - the pairing rules of the config allow it, and
+// - a unit other than the one used by the instruction before it can execute the ucode.
+
+/// The parameters of the core, for comparing designs.
+///
+/// Given on the command line as comma-separated key=value pairs, like `pair_memory=false,add_units=1`.
+#[derive(Clone, Debug)]
+pub(crate) struct Config {
+	/// Set with the same `<kind>_units`, `<kind>_latency` and `div_radix` keys as for the out-of-order core.
+	pub(crate) functional_units: [FunctionalUnitConfig; 8],
+	/// The number of loads and stores that can be issued per cycle, at most 2 since at most two instructions are issued per cycle.
+	pub(crate) memory_units: std::num::NonZero<usize>,
+	/// Whether a load or store can be paired with another instruction.
+	pub(crate) pair_memory: bool,
+	/// Whether a multiplication can be paired with another instruction.
+	pub(crate) pair_mul: bool,
+	/// Whether a jump or branch can be paired with the instruction before it.
+	pub(crate) pair_jumps: bool,
+}
+
+impl Config {
+	/// Sets the keys given on the command line.
+	pub(crate) fn set(&mut self, arg: &str) -> Result<(), config::Error> {
+		for pair in config::pairs(arg) {
+			let pair = pair?;
+			match pair.key {
+				"memory_units" => self.memory_units = Some(pair.non_zero()?).filter(|value| value.get() <= 2).ok_or_else(|| pair.invalid_value())?,
+				"pair_memory" => self.pair_memory = pair.bool()?,
+				"pair_mul" => self.pair_mul = pair.bool()?,
+				"pair_jumps" => self.pair_jumps = pair.bool()?,
+				"div_radix" => *FunctionalUnitConfig::div_radix_mut(&mut self.functional_units) = pair.div_radix()?,
+				key => {
+					let Some(field) = FunctionalUnitConfig::field_mut(&mut self.functional_units, key) else { return Err(pair.unknown_key()); };
+					*field = pair.non_zero()?;
+				},
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			functional_units: FunctionalUnitConfig::defaults(),
+			memory_units: std::num::NonZero::<usize>::MIN,
+			pair_memory: true,
+			pair_mul: true,
+			pair_jumps: true,
+		}
+	}
+}
+
+pub(crate) fn run(
+	memory: &mut Memory,
+	x_regs: &mut XRegs,
+	csrs: &mut Csrs,
+	statistics: &mut Statistics,
+	pc: &mut i64,
+	symbols: &Symbols,
+	mut syscalls: Option<&mut Syscalls>,
+	mut htif: Option<&mut Htif>,
+	mut gdb: Option<&mut gdb::Stub>,
+	fusions: &fusion::Config,
+	config: &Config,
+	log_level: LogLevel,
+) -> Result<i32, Exception> {
+	let mut functional_units = FunctionalUnitConfig::build(&config.functional_units);
+	let memory_units: Vec<_> = (1..=config.memory_units.get()).map(|i| format!("memory{i}")).collect();
+	for name in functional_units.iter().map(|functional_unit| &functional_unit.name).chain(&memory_units) {
+		statistics.fu_utilization.entry(name.clone()).or_default();
+	}
+
+	// The last ucode of the previous instruction, if it was issued alone in its cycle.
+	let mut previous: Option<Slot> = None;
+
+	loop {
+		let previous_slot = previous.take();
+
+		{
+			let tick = csrs.ticks();
+			if log_level == LogLevel::Debug {
+				if tick % 100 == 0 {
+					eprintln!();
+					eprintln!("===== {tick} =====");
+					eprintln!("{}", symbols.display(*pc));
+				}
+			}
+			else if log_level >= LogLevel::Trace {
+				eprintln!();
+				eprintln!("===== {tick} =====");
+				eprintln!("{x_regs}");
+				eprintln!("{csrs}");
+			}
+		}
+
+		if let Some(gdb) = gdb.as_deref_mut() && let Some(status) = gdb.before_instruction(pc, x_regs, csrs, memory) {
+			return Ok(status);
+		}
+
+		if let Some(interrupt) = csrs.pending_interrupt() {
+			take_trap(interrupt, pc, csrs, memory, statistics, log_level)?;
+			continue;
+		}
+
+		let (inst, inst_len, mut instret) = match load_inst(memory, *pc, fusions, statistics) {
+			Ok(inst) => inst,
+			Err(exception) => {
+				take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+				continue;
+			},
+		};
+
+		let fetch_latency = memory.fetch_latency(*pc, inst_len.unsigned_abs().try_into().expect("instruction length fits in usize"));
+
+		if log_level >= LogLevel::Trace {
+			eprintln!("+ {} : {inst:?}", symbols.display(*pc));
+		}
+
+		// The previous instruction that this one can be paired with, so far.
+		let mut pair_with = previous_slot.filter(|previous| match pairing_blocker(previous, inst, fetch_latency, config) {
+			Some(blocker) => {
+				*statistics.dual_issue_blockers.entry(blocker).or_default() += 1;
+				false
+			},
+			None => true,
+		});
+
+		// The interrupt that wakes up the wfi is taken after it retires, so that it returns to the instruction after the wfi.
+		if let Instruction::Wfi = inst {
+			while !csrs.wfi_done() {
+				statistics.num_ticks_where_instructions_not_retired += 1;
+				csrs.tick(1, 0, memory);
+			}
+		}
+
+		let next_inst_pc = pc.wrapping_add(inst_len);
+		let predicted_next_pc = match inst {
+			// Constant
+			Instruction::Jal { rd: _, imm } => pc.wrapping_add(imm),
+
+			_ => pc.wrapping_add(inst_len),
+		};
+
+		let ucode_1234 = Ucode::new(
+			inst,
+			*pc,
+			next_inst_pc,
+			predicted_next_pc,
+			x_regs,
+			csrs,
+			TagAllocator::default().allocate(),
+		);
+		let mut ucodes = std::collections::VecDeque::with_capacity(4);
+		if let Some((ucode_1, ucode_234)) = ucode_1234 {
+			ucodes.push_back(ucode_1);
+			if let Some((ucode_2, ucode_34)) = ucode_234 {
+				ucodes.push_back(ucode_2);
+				if let Some((ucode_3, ucode_4)) = ucode_34 {
+					ucodes.push_back(ucode_3);
+					if let Some(ucode_4) = ucode_4 {
+						ucodes.push_back(ucode_4);
+					}
+				}
+			}
+		}
+
+		// Instructions with no architectural effect, like `nop` or writes to read-only fields of CSRs, have no ucodes
+		// but still take an issue slot.
+		let mut cycles = 0;
+		let mut last_slot = Some((None, Unit::None, 1));
+		let mut next_pc = next_inst_pc;
+		let mut exception = None;
+		let first_half = Ucode::first_half(inst);
+		let mut executed = 0;
+
+		while let Some(mut ucode) = ucodes.pop_front() {
+			if let Ucode::Ebreak = ucode {
+				return Ok(0);
+			}
+
+			if let Ucode::Ecall { rd } = &mut ucode {
+				let Some(syscalls) = syscalls.as_deref_mut() else {
+					exception = Some(Exception::EnvironmentCallFromMMode);
+					break;
+				};
+				match syscalls.ecall(x_regs, memory) {
+					SyscallResult::Return(value) => rd.2 = Some(value),
+					SyscallResult::Exit(status) => return Ok(status),
+				}
+			}
+
+			let mut paired = pair_with.take().filter(|previous| match ucode_pairing_blocker(previous.ucode.as_ref(), &ucode, config) {
+				Some(blocker) => {
+					*statistics.dual_issue_blockers.entry(blocker).or_default() += 1;
+					false
+				},
+				None => true,
+			});
+
+			let issued = match execute(&mut ucode, paired.map(|previous| previous.unit), &mut functional_units, memory_units.len(), memory) {
+				Ok(Some(issued)) => Ok(issued),
+				Ok(None) => {
+					*statistics.dual_issue_blockers.entry("busy unit").or_default() += 1;
+					paired = None;
+					execute(&mut ucode, None, &mut functional_units, memory_units.len(), memory)
+						.map(|issued| issued.expect("all units are free for a ucode issued alone"))
+				},
+				Err(exception) => Err(exception),
+			};
+			let (unit, ucode_cycles) = match issued {
+				Ok(issued) => issued,
+				Err(exception_) => {
+					// If the second instruction of a fused pair faults, only the first one retires,
+					// and the second one is executed again by itself to take the trap.
+					if let Some((first_half_ucodes, first_half_len)) = first_half && executed >= first_half_ucodes {
+						x_regs.reset_all_tags(std::iter::empty());
+						next_pc = pc.wrapping_add(first_half_len);
+						instret = 1;
+					}
+					else {
+						exception = Some(exception_);
+					}
+					break;
+				},
+			};
+			executed += 1;
+
+			match unit {
+				Unit::None => (),
+				Unit::Functional(i) => *statistics.fu_utilization.entry(functional_units[i].name.clone()).or_default() += usize::try_from(ucode_cycles).expect("cycles fit in usize"),
+				Unit::Memory(i) => *statistics.fu_utilization.entry(memory_units[i].clone()).or_default() += usize::try_from(ucode_cycles).expect("cycles fit in usize"),
+			}
+
+			if let Some(previous) = paired {
+				if log_level >= LogLevel::Trace {
+					eprintln!("dual issue");
+				}
+				statistics.dual_issues += 1;
+				cycles += ucode_cycles.saturating_sub(previous.cycles);
+				last_slot = None;
+			}
+			else {
+				cycles += ucode_cycles;
+				last_slot = Some((Some(ucode), unit, ucode_cycles));
+			}
+
+			if let Some((rd, tag, value)) = ucode.done_rd() {
+				x_regs.store(rd, tag, value);
+				for ucode in &mut ucodes {
+					ucode.update(tag, value);
+				}
+			}
+			if let Some((csr, tag, value)) = ucode.done_csr() {
+				csrs.store(csr, tag, value);
+				for ucode in &mut ucodes {
+					ucode.update(tag, value);
+				}
+			}
+
+			if let Ucode::Jump { pc: RegisterValue::Value(jump_pc), predicted_next_pc: _ } = ucode {
+				next_pc = jump_pc;
+			}
+		}
+
+		if let Some(exception) = exception {
+			// Only the last ucode of an instruction can fault, and the ucodes before it only write temporaries,
+			// so undoing the renames of the instruction is enough to make the trap precise.
+			x_regs.reset_all_tags(std::iter::empty());
+			csrs.reset_all_tags(std::iter::empty());
+			take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+			continue;
+		}
+
+		// An instruction without ucodes that is paired takes no cycles of its own.
+		if pair_with.is_some() {
+			statistics.dual_issues += 1;
+			last_slot = None;
+		}
+		else if let Some((None, _, slot_cycles)) = last_slot {
+			cycles += slot_cycles;
+		}
+
+		previous = last_slot.map(|(ucode, unit, cycles)| Slot {
+			inst,
+			redirected: next_pc != next_inst_pc,
+			ucode,
+			unit,
+			cycles,
+		});
+
+		*pc = next_pc;
+
+		let cycles = cycles + u64::from(fetch_latency);
+
+		// An instruction that is paired with the previous one retires in the same cycle as it, unless it takes longer.
+		if let Some(not_retired) = cycles.checked_sub(1) {
+			statistics.num_ticks_where_instructions_retired += 1;
+			statistics.num_ticks_where_instructions_not_retired += usize::try_from(not_retired).expect("cycles fit in usize");
+		}
+
+		csrs.tick(cycles.cast_signed(), instret, memory);
+
+		if let Some(status) = htif.as_deref_mut().and_then(|htif| htif.poll(memory)) {
+			return Ok(status);
+		}
+
+		if let Some(status) = memory.exit_status() {
+			return Ok(status);
+		}
+
+		if log_level >= LogLevel::Trace {
+			eprintln!("->");
+			eprintln!("{x_regs}");
+			eprintln!("{csrs}");
+		}
+	}
+}
+
+/// The last ucode of an instruction that was issued alone in its cycle, that the next instruction can be paired with.
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+	inst: Instruction,
+	/// Whether the instruction jumped or branched away from the next instruction.
+	redirected: bool,
+	/// `None` if the instruction has no ucodes.
+	ucode: Option<Ucode>,
+	unit: Unit,
+	cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Unit {
+	/// Moves and jumps are done without a unit.
+	None,
+	/// The index into the functional units.
+	Functional(usize),
+	Memory(usize),
+}
+
+/// Returns the reason that an instruction cannot be paired with the one before it, as far as can be told before it is executed.
+fn pairing_blocker(previous: &Slot, inst: Instruction, fetch_latency: u8, config: &Config) -> Option<&'static str> {
+	let serializing = |inst| matches!(
+		inst,
+		Instruction::Csrrw { .. } |
+		Instruction::Csrrwi { .. } |
+		Instruction::Csrrs { .. } |
+		Instruction::Csrrsi { .. } |
+		Instruction::Csrrc { .. } |
+		Instruction::Csrrci { .. } |
+		Instruction::Ebreak |
+		Instruction::Ecall |
+		Instruction::Fence |
+		Instruction::Mret |
+		Instruction::Wfi
+	);
+
+	if previous.redirected {
+		Some("taken jump")
+	}
+	else if serializing(previous.inst) || serializing(inst) {
+		Some("serializing")
+	}
+	else if fetch_latency != 0 {
+		Some("fetch")
+	}
+	else if previous.inst.destinations().into_iter().flatten().any(|rd| inst.sources().contains(&Some(rd)) || inst.destinations().contains(&Some(rd))) {
+		Some("dependency")
+	}
+	else if !config.pair_jumps && matches!(inst, Instruction::Branch { .. } | Instruction::Jal { .. } | Instruction::Jalr { .. } | Instruction::SltBranch { .. }) {
+		Some("pairing rule")
+	}
+	else {
+		None
+	}
+}
+
+/// Returns the reason that a ucode cannot be paired with the last ucode of the instruction before it,
+/// other than the lack of a free unit.
+fn ucode_pairing_blocker(previous: Option<&Ucode>, ucode: &Ucode, config: &Config) -> Option<&'static str> {
+	let either = |f: fn(&Ucode) -> bool| previous.is_some_and(f) || f(ucode);
+
+	if
+		(!config.pair_memory && either(|ucode| matches!(ucode, Ucode::Load { .. } | Ucode::Store { .. }))) ||
+		(!config.pair_mul && either(|ucode| matches!(ucode, Ucode::Mul { .. })))
+	{
+		Some("pairing rule")
+	}
+	else {
+		None
+	}
+}
+
+/// Executes a ucode on a unit other than `taken`, and returns the unit and the number of cycles until the result is available,
+/// or `None` if only `taken` can execute it.
+fn execute(
+	ucode: &mut Ucode,
+	taken: Option<Unit>,
+	functional_units: &mut [FunctionalUnit],
+	num_memory_units: usize,
+	memory: &mut Memory,
+) -> Result<Option<(Unit, u64)>, Exception> {
+	let memory_unit = match taken {
+		Some(Unit::Memory(_)) => (num_memory_units > 1).then_some(Unit::Memory(1)),
+		_ => Some(Unit::Memory(0)),
+	};
+
+	Ok(Some(match ucode {
+		Ucode::Load { op, rd, addr: RegisterValue::Value(addr) } => {
+			let Some(unit) = memory_unit else { return Ok(None); };
+			let result = op.exec(memory, *addr)?;
+			rd.2 = Some(result);
+			(unit, u64::from(op.latency(memory, *addr)) + 1)
+		},
+
+		Ucode::Store {
+			op,
+			addr: RegisterValue::Value(addr),
+			value: RegisterValue::Value(value),
+		} => {
+			let Some(unit) = memory_unit else { return Ok(None); };
+			op.exec(memory, *addr, *value)?;
+			(unit, u64::from(op.latency(memory, *addr)) + 1)
+		},
+
+		Ucode::Fence |
+		Ucode::Jump { pc: RegisterValue::Value(_), predicted_next_pc: _ } |
+		Ucode::Mv { rd: _, value: RegisterValue::Value(_) } |
+		Ucode::MvCsr { csr: _, value: RegisterValue::Value(_) } |
+		// run() has already serviced the syscall
+		Ucode::Ecall { rd: (_, _, Some(_)) } |
+		// run() has already waited for an interrupt
+		Ucode::Wfi => (Unit::None, 1),
+
+		_ => {
+			let Some(i) = (0..functional_units.len()).find(|&i| taken != Some(Unit::Functional(i)) && functional_units[i].inner.try_execute(ucode)) else {
+				assert!(taken.is_some(), "no functional unit can execute {ucode:?}");
+				return Ok(None);
+			};
+
+			// Multiplications take a round per cycle.
+			let functional_unit = &mut functional_units[i];
+			let mut rounds = 1;
+			while !ucode.done() {
+				assert!(functional_unit.inner.try_execute(ucode));
+				rounds += 1;
+			}
+			(Unit::Functional(i), rounds + u64::try_from(functional_unit.latency.get() - 1).expect("latency fits in u64"))
+		},
+	}))
+}
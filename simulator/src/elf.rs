@@ -0,0 +1,331 @@
+// Ref: System V ABI, Chapter 4 "Object Files" and Chapter 5 "Program Loading"
+
+const EI_CLASS_64: u8 = 2;
+const EI_DATA_LE: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+pub(crate) struct Elf<'a> {
+	raw: &'a [u8],
+	pub(crate) entry: u64,
+	phoff: usize,
+	phentsize: usize,
+	phnum: usize,
+	shoff: usize,
+	shentsize: usize,
+	shnum: usize,
+}
+
+pub(crate) struct Segment<'a> {
+	pub(crate) vaddr: u64,
+	pub(crate) data: &'a [u8],
+	pub(crate) memsz: u64,
+}
+
+impl<'a> Elf<'a> {
+	pub(crate) fn is_elf(raw: &[u8]) -> bool {
+		raw.starts_with(b"\x7fELF")
+	}
+
+	pub(crate) fn parse(raw: &'a [u8]) -> Result<Self, Error> {
+		let ident = raw.get(..16).ok_or(Error::Truncated)?;
+		if !Self::is_elf(ident) {
+			return Err(Error::NotElf);
+		}
+		if ident[4] != EI_CLASS_64 {
+			return Err(Error::WrongXlen { class: ident[4] });
+		}
+		if ident[5] != EI_DATA_LE {
+			return Err(Error::BigEndian);
+		}
+
+		let ty = read_u16(raw, 16)?;
+		if ty != ET_EXEC {
+			return Err(Error::NotExecutable { ty });
+		}
+
+		let machine = read_u16(raw, 18)?;
+		if machine != EM_RISCV {
+			return Err(Error::NotRiscV { machine });
+		}
+
+		let entry = read_u64(raw, 24)?;
+		let phoff = read_u64(raw, 32)?.try_into().map_err(|_| Error::Truncated)?;
+		let phentsize = read_u16(raw, 54)?.into();
+		let phnum = read_u16(raw, 56)?.into();
+		let shoff = read_u64(raw, 40)?.try_into().map_err(|_| Error::Truncated)?;
+		let shentsize = read_u16(raw, 58)?.into();
+		let shnum = read_u16(raw, 60)?.into();
+
+		Ok(Self {
+			raw,
+			entry,
+			phoff,
+			phentsize,
+			phnum,
+			shoff,
+			shentsize,
+			shnum,
+		})
+	}
+
+	pub(crate) fn load_segments(&self) -> Result<Vec<Segment<'a>>, Error> {
+		let mut result = vec![];
+
+		for i in 0..self.phnum {
+			let phdr = self.header(self.phoff, self.phentsize, i)?;
+
+			let p_type = read_u32(phdr, 0)?;
+			if p_type != PT_LOAD {
+				continue;
+			}
+
+			let offset: usize = read_u64(phdr, 8)?.try_into().map_err(|_| Error::Truncated)?;
+			let vaddr = read_u64(phdr, 16)?;
+			let filesz: usize = read_u64(phdr, 32)?.try_into().map_err(|_| Error::Truncated)?;
+			let memsz = read_u64(phdr, 40)?;
+			if u64::try_from(filesz).map_err(|_| Error::Truncated)? > memsz {
+				return Err(Error::SegmentLargerThanMemsz { vaddr });
+			}
+
+			let data = self.raw.get(offset..).and_then(|raw| raw.get(..filesz)).ok_or(Error::Truncated)?;
+
+			result.push(Segment { vaddr, data, memsz });
+		}
+
+		Ok(result)
+	}
+
+	/// Returns the address, size and name of the defined function, object and untyped symbols from the `.symtab` section, if any.
+	pub(crate) fn symbols(&self) -> Result<Vec<(u64, u64, &'a str)>, Error> {
+		let mut result = vec![];
+
+		for i in 0..self.shnum {
+			let shdr = self.header(self.shoff, self.shentsize, i)?;
+
+			let sh_type = read_u32(shdr, 4)?;
+			if sh_type != SHT_SYMTAB {
+				continue;
+			}
+
+			let symtab = self.section_data(shdr)?;
+			let strtab_index: usize = read_u32(shdr, 40)?.try_into().map_err(|_| Error::Truncated)?;
+			let strtab = self.section_data(self.header(self.shoff, self.shentsize, strtab_index)?)?;
+
+			for sym in symtab.chunks_exact(24) {
+				let st_name: usize = read_u32(sym, 0)?.try_into().map_err(|_| Error::Truncated)?;
+				let st_info = sym[4];
+				let st_shndx = read_u16(sym, 6)?;
+				let st_value = read_u64(sym, 8)?;
+				let st_size = read_u64(sym, 16)?;
+
+				if !matches!(st_info & 0xf, STT_NOTYPE | STT_OBJECT | STT_FUNC) || st_shndx == SHN_UNDEF {
+					continue;
+				}
+
+				let name = strtab.get(st_name..).ok_or(Error::Truncated)?;
+				let name = name.split(|&b| b == b'\0').next().unwrap_or_default();
+				let Ok(name) = std::str::from_utf8(name) else { continue; };
+				if name.is_empty() {
+					continue;
+				}
+
+				result.push((st_value, st_size, name));
+			}
+		}
+
+		Ok(result)
+	}
+
+	/// Returns the bytes from the start of the `i`th of the `entsize`-byte headers at `off` to the end of the file.
+	fn header(&self, off: usize, entsize: usize, i: usize) -> Result<&'a [u8], Error> {
+		let start = i.checked_mul(entsize).and_then(|i| i.checked_add(off)).ok_or(Error::Truncated)?;
+		self.raw.get(start..).ok_or(Error::Truncated)
+	}
+
+	fn section_data(&self, shdr: &[u8]) -> Result<&'a [u8], Error> {
+		let offset: usize = read_u64(shdr, 24)?.try_into().map_err(|_| Error::Truncated)?;
+		let size: usize = read_u64(shdr, 32)?.try_into().map_err(|_| Error::Truncated)?;
+		self.raw.get(offset..).and_then(|raw| raw.get(..size)).ok_or(Error::Truncated)
+	}
+}
+
+fn read_u16(raw: &[u8], offset: usize) -> Result<u16, Error> {
+	let raw = raw.get(offset..).and_then(<[u8]>::first_chunk).ok_or(Error::Truncated)?;
+	Ok(u16::from_le_bytes(*raw))
+}
+
+fn read_u32(raw: &[u8], offset: usize) -> Result<u32, Error> {
+	let raw = raw.get(offset..).and_then(<[u8]>::first_chunk).ok_or(Error::Truncated)?;
+	Ok(u32::from_le_bytes(*raw))
+}
+
+fn read_u64(raw: &[u8], offset: usize) -> Result<u64, Error> {
+	let raw = raw.get(offset..).and_then(<[u8]>::first_chunk).ok_or(Error::Truncated)?;
+	Ok(u64::from_le_bytes(*raw))
+}
+
+#[derive(Debug)]
+pub(crate) enum Error {
+	BigEndian,
+	NotElf,
+	NotExecutable { ty: u16 },
+	NotRiscV { machine: u16 },
+	SegmentLargerThanMemsz { vaddr: u64 },
+	Truncated,
+	WrongXlen { class: u8 },
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::BigEndian => f.write_str("ELF file is big-endian"),
+			Self::NotElf => f.write_str("not an ELF file"),
+			Self::NotExecutable { ty } => write!(f, "ELF file has type {ty} but only executables are supported"),
+			Self::NotRiscV { machine } => write!(f, "ELF file has machine {machine} but only RISC-V is supported"),
+			Self::SegmentLargerThanMemsz { vaddr } => write!(f, "ELF segment at 0x{vaddr:016x} has more bytes in the file than in memory"),
+			Self::Truncated => f.write_str("ELF file is truncated"),
+			Self::WrongXlen { class: 1 } => f.write_str("ELF file is for RV32 but the simulator is RV64"),
+			Self::WrongXlen { class } => write!(f, "ELF file has unknown class {class}"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	fn put(raw: &mut [u8], offset: usize, value: &[u8]) {
+		raw[offset..][..value.len()].copy_from_slice(value);
+	}
+
+	/// An executable with one 4-byte load segment at 0x1000 with 8 bytes in memory,
+	/// and a `.symtab` with a defined `main` and an undefined `puts`.
+	fn elf() -> Vec<u8> {
+		let mut raw = vec![0; 408];
+
+		put(&mut raw, 0, b"\x7fELF\x02\x01\x01");
+		put(&mut raw, 16, &2_u16.to_le_bytes());
+		put(&mut raw, 18, &243_u16.to_le_bytes());
+		put(&mut raw, 24, &0x1000_u64.to_le_bytes());
+		put(&mut raw, 32, &64_u64.to_le_bytes());
+		put(&mut raw, 40, &216_u64.to_le_bytes());
+		put(&mut raw, 54, &56_u16.to_le_bytes());
+		put(&mut raw, 56, &1_u16.to_le_bytes());
+		put(&mut raw, 58, &64_u16.to_le_bytes());
+		put(&mut raw, 60, &3_u16.to_le_bytes());
+
+		put(&mut raw, 64, &1_u32.to_le_bytes());
+		put(&mut raw, 64 + 8, &120_u64.to_le_bytes());
+		put(&mut raw, 64 + 16, &0x1000_u64.to_le_bytes());
+		put(&mut raw, 64 + 32, &4_u64.to_le_bytes());
+		put(&mut raw, 64 + 40, &8_u64.to_le_bytes());
+		put(&mut raw, 120, &[0x13, 0, 0, 0]);
+
+		put(&mut raw, 128 + 24, &1_u32.to_le_bytes());
+		put(&mut raw, 128 + 24 + 4, &[0x12]);
+		put(&mut raw, 128 + 24 + 6, &1_u16.to_le_bytes());
+		put(&mut raw, 128 + 24 + 8, &0x1000_u64.to_le_bytes());
+		put(&mut raw, 128 + 24 + 16, &4_u64.to_le_bytes());
+		put(&mut raw, 128 + 48, &6_u32.to_le_bytes());
+		put(&mut raw, 128 + 48 + 4, &[0x12]);
+		put(&mut raw, 200, b"\0main\0puts\0");
+
+		put(&mut raw, 216 + 64 + 4, &2_u32.to_le_bytes());
+		put(&mut raw, 216 + 64 + 24, &128_u64.to_le_bytes());
+		put(&mut raw, 216 + 64 + 32, &72_u64.to_le_bytes());
+		put(&mut raw, 216 + 64 + 40, &2_u32.to_le_bytes());
+		put(&mut raw, 216 + 128 + 4, &3_u32.to_le_bytes());
+		put(&mut raw, 216 + 128 + 24, &200_u64.to_le_bytes());
+		put(&mut raw, 216 + 128 + 32, &11_u64.to_le_bytes());
+
+		raw
+	}
+
+	#[test]
+	fn valid() {
+		let raw = elf();
+		assert!(super::Elf::is_elf(&raw));
+
+		let elf = super::Elf::parse(&raw).unwrap();
+		assert_eq!(elf.entry, 0x1000);
+
+		let segments = elf.load_segments().unwrap();
+		assert_eq!(segments.len(), 1);
+		assert_eq!(segments[0].vaddr, 0x1000);
+		assert_eq!(segments[0].data, [0x13, 0, 0, 0]);
+		assert_eq!(segments[0].memsz, 8);
+
+		assert_eq!(elf.symbols().unwrap(), [(0x1000, 4, "main")]);
+	}
+
+	#[test]
+	fn invalid() {
+		static TESTS: &[(usize, &[u8], &str)] = &[
+			(0, b"\x7fELG", "not an ELF file"),
+			(4, &[1], "ELF file is for RV32 but the simulator is RV64"),
+			(4, &[3], "ELF file has unknown class 3"),
+			(5, &[2], "ELF file is big-endian"),
+			(16, &[3, 0], "ELF file has type 3 but only executables are supported"),
+			(18, &[62, 0], "ELF file has machine 62 but only RISC-V is supported"),
+		];
+
+		for &(offset, value, expected) in TESTS {
+			let mut raw = elf();
+			put(&mut raw, offset, value);
+			let actual = super::Elf::parse(&raw).err().unwrap().to_string();
+			assert_eq!(actual, expected, "{offset} {value:?}");
+		}
+
+		assert_eq!(super::Elf::parse(&elf()[..61]).err().unwrap().to_string(), "ELF file is truncated");
+	}
+
+	#[test]
+	fn invalid_segments() {
+		static TESTS: &[(usize, u64, &str)] = &[
+			(64 + 32, 9, "ELF segment at 0x0000000000001000 has more bytes in the file than in memory"),
+			(64 + 8, 406, "ELF file is truncated"),
+			(64 + 8, u64::MAX, "ELF file is truncated"),
+			(32, 406, "ELF file is truncated"),
+			(32, 0xffff_ffff_ffff_fffc, "ELF file is truncated"),
+			(56, 0xffff, "ELF file is truncated"),
+		];
+
+		for &(offset, value, expected) in TESTS {
+			let mut raw = elf();
+			let value = value.to_le_bytes();
+			put(&mut raw, offset, if offset == 56 { &value[..2] } else { &value });
+			let actual = super::Elf::parse(&raw).unwrap().load_segments().err().unwrap().to_string();
+			assert_eq!(actual, expected, "{offset} {value:?}");
+		}
+	}
+
+	#[test]
+	fn invalid_symbols() {
+		static TESTS: &[(usize, u64)] = &[
+			(40, 400),
+			(40, 0xffff_ffff_ffff_fffc),
+			(216 + 64 + 24, u64::MAX),
+			(216 + 64 + 32, 0x1000),
+			(216 + 64 + 40, 0xffff_ffff),
+			(216 + 128 + 24, 0xffff_ffff_ffff_fffc),
+		];
+
+		for &(offset, value) in TESTS {
+			let mut raw = elf();
+			let value = value.to_le_bytes();
+			put(&mut raw, offset, if offset == 216 + 64 + 40 { &value[..4] } else { &value });
+			let actual = super::Elf::parse(&raw).unwrap().symbols().err().unwrap().to_string();
+			assert_eq!(actual, "ELF file is truncated", "{offset} {value:?}");
+		}
+
+		let mut raw = elf();
+		put(&mut raw, 40, &0xffff_ffff_ffff_fffc_u64.to_le_bytes());
+		put(&mut raw, 60, &1_u16.to_le_bytes());
+		assert_eq!(super::Elf::parse(&raw).unwrap().symbols().err().unwrap().to_string(), "ELF file is truncated");
+	}
+}
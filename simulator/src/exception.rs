@@ -0,0 +1,64 @@
+// Ref: Privileged spec 3.1.15 Machine Cause Register (mcause)
+//
+// Interrupts are trapped the same way as exceptions, so they are also represented here.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Exception {
+	InstructionAccessFault { address: i64 },
+	IllegalInstruction { inst: u32 },
+	LoadAddressMisaligned { address: i64 },
+	LoadAccessFault { address: i64 },
+	StoreAddressMisaligned { address: i64 },
+	StoreAccessFault { address: i64 },
+	EnvironmentCallFromMMode,
+	MachineSoftwareInterrupt,
+	MachineTimerInterrupt,
+}
+
+impl Exception {
+	pub(crate) fn cause(self) -> i64 {
+		match self {
+			Self::InstructionAccessFault { .. } => 1,
+			Self::IllegalInstruction { .. } => 2,
+			Self::LoadAddressMisaligned { .. } => 4,
+			Self::LoadAccessFault { .. } => 5,
+			Self::StoreAddressMisaligned { .. } => 6,
+			Self::StoreAccessFault { .. } => 7,
+			Self::EnvironmentCallFromMMode => 11,
+			Self::MachineSoftwareInterrupt => i64::MIN | 3,
+			Self::MachineTimerInterrupt => i64::MIN | 7,
+		}
+	}
+
+	pub(crate) fn tval(self) -> i64 {
+		match self {
+			Self::IllegalInstruction { inst } => inst.into(),
+
+			Self::InstructionAccessFault { address } |
+			Self::LoadAddressMisaligned { address } |
+			Self::LoadAccessFault { address } |
+			Self::StoreAddressMisaligned { address } |
+			Self::StoreAccessFault { address } => address,
+
+			Self::EnvironmentCallFromMMode |
+			Self::MachineSoftwareInterrupt |
+			Self::MachineTimerInterrupt => 0,
+		}
+	}
+}
+
+impl std::fmt::Display for Exception {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match *self {
+			Self::InstructionAccessFault { address } => write!(f, "instruction access fault: 0x{address:016x}"),
+			Self::IllegalInstruction { inst } => write!(f, "illegal instruction: 0x{inst:08x}"),
+			Self::LoadAddressMisaligned { address } => write!(f, "load address misaligned: 0x{address:016x}"),
+			Self::LoadAccessFault { address } => write!(f, "load access fault: 0x{address:016x}"),
+			Self::StoreAddressMisaligned { address } => write!(f, "store address misaligned: 0x{address:016x}"),
+			Self::StoreAccessFault { address } => write!(f, "store access fault: 0x{address:016x}"),
+			Self::EnvironmentCallFromMMode => f.write_str("environment call from M-mode"),
+			Self::MachineSoftwareInterrupt => f.write_str("machine software interrupt"),
+			Self::MachineTimerInterrupt => f.write_str("machine timer interrupt"),
+		}
+	}
+}
@@ -0,0 +1,150 @@
+use crate::{
+	exception::Exception,
+	fusion,
+	instruction::Instruction,
+	memory::Memory,
+};
+
+/// The instruction fetch buffer of the out-of-order core.
+///
+/// Every cycle the rest of the aligned fetch block that follows the buffer's contents is fetched into it if there is room,
+/// and then instructions are decoded from its front. So a 32-bit instruction that straddles two fetch blocks
+/// can only be decoded once the second block has been fetched, and an instruction is only fused with the next one
+/// if the next one has also been fetched. A fetch block that misses in the instruction cache is only fetched
+/// once the miss latency has passed.
+#[derive(Debug)]
+pub(crate) struct FetchBuffer {
+	/// The address of the first halfword in `halfwords`, ie of the next instruction to decode.
+	pc: i64,
+	halfwords: std::collections::VecDeque<u16>,
+	/// The exception raised by fetching the halfword after the last one in `halfwords`.
+	/// Nothing more is fetched until the buffer is redirected.
+	fault: Option<Exception>,
+	/// The number of cycles left before the block that is being fetched arrives, once its fetch has started.
+	miss_wait: Option<u8>,
+	block_size: u64,
+	capacity: usize,
+}
+
+impl FetchBuffer {
+	/// `block_size` is a power of two and `size` is at least `block_size`, both in bytes.
+	pub(crate) fn new(pc: i64, block_size: usize, size: usize) -> Self {
+		Self {
+			pc,
+			halfwords: std::collections::VecDeque::with_capacity(size / 2),
+			fault: None,
+			miss_wait: None,
+			block_size: block_size.try_into().expect("usize fits in u64"),
+			capacity: size / 2,
+		}
+	}
+
+	pub(crate) fn pc(&self) -> i64 {
+		self.pc
+	}
+
+	/// Discards the buffer's contents and continues fetching from `pc`.
+	pub(crate) fn redirect(&mut self, pc: i64) {
+		self.pc = pc;
+		self.halfwords.clear();
+		self.fault = None;
+		self.miss_wait = None;
+	}
+
+	pub(crate) fn fill(&mut self, memory: &Memory) {
+		if self.fault.is_some() {
+			return;
+		}
+
+		let mut fill_pc = self.pc.wrapping_add((2 * self.halfwords.len()).try_into().expect("buffer size fits in i64"));
+		let rest_of_block = self.block_size - fill_pc.cast_unsigned() % self.block_size;
+		let num_halfwords = usize::try_from(rest_of_block.div_ceil(2)).expect("block size fits in usize");
+		if self.halfwords.len() + num_halfwords > self.capacity {
+			return;
+		}
+
+		let miss_wait = self.miss_wait.get_or_insert_with(|| memory.fetch_latency(fill_pc, 2 * num_halfwords));
+		if let Some(remaining) = miss_wait.checked_sub(1) {
+			*miss_wait = remaining;
+			return;
+		}
+		self.miss_wait = None;
+
+		for _ in 0..num_halfwords {
+			match memory.fetch(fill_pc) {
+				Ok(halfword) => self.halfwords.push_back(halfword),
+				Err(exception) => {
+					self.fault = Some(exception);
+					break;
+				},
+			}
+			fill_pc = fill_pc.wrapping_add(2);
+		}
+	}
+
+	/// Decodes the instruction at the front of the buffer, fused with the one after it if possible,
+	/// and returns it with its length and the number of instructions it retires.
+	/// Returns `None` if all of the instruction has not been fetched yet.
+	///
+	/// The instruction stays in the buffer until it is consumed with [`FetchBuffer::advance`].
+	pub(crate) fn decode(&self, fusions: &fusion::Config, statistics: &mut fusion::Statistics) -> Option<Result<(Instruction, i64, i64), Exception>> {
+		let raw_a = match self.raw_inst(0)? {
+			Ok(raw_a) => raw_a,
+			Err(exception) => return Some(Err(exception)),
+		};
+		let Ok((inst_a, inst_a_len)) = Instruction::decode(raw_a) else {
+			return Some(Err(Exception::IllegalInstruction { inst: raw_a }));
+		};
+
+		// An instruction that has not been fetched yet, or that faulted, just means there is nothing to fuse with.
+		let result =
+			if
+				let Some(Ok(raw_b)) = self.raw_inst(inst_a_len.unsigned_abs().try_into().expect("instruction length fits in usize")) &&
+				let Ok((inst_b, inst_b_len)) = Instruction::decode(raw_b)
+			{
+				fusion::fuse(inst_a, inst_a_len, inst_b, inst_b_len, fusions, statistics)
+			}
+			else {
+				(inst_a, inst_a_len, 1)
+			};
+		Some(Ok(result))
+	}
+
+	/// Consumes `len` bytes of decoded instructions from the front of the buffer.
+	pub(crate) fn advance(&mut self, len: i64) {
+		self.pc = self.pc.wrapping_add(len);
+		let num_halfwords: usize = (len.unsigned_abs() / 2).try_into().expect("instruction length fits in usize");
+		self.halfwords.drain(..num_halfwords);
+	}
+
+	/// Returns the raw instruction at `offset` bytes from the front of the buffer,
+	/// or `None` if all of it has not been fetched yet.
+	fn raw_inst(&self, offset: usize) -> Option<Result<u32, Exception>> {
+		let lo = match self.halfword(offset / 2)? {
+			Ok(lo) => u32::from(lo),
+			Err(exception) => return Some(Err(exception)),
+		};
+		if lo & 0b11 != 0b11 {
+			// Compressed
+			return Some(Ok(lo));
+		}
+
+		let hi = match self.halfword(offset / 2 + 1)? {
+			Ok(hi) => u32::from(hi),
+			Err(exception) => return Some(Err(exception)),
+		};
+		Some(Ok(lo | (hi << 16)))
+	}
+
+	fn halfword(&self, i: usize) -> Option<Result<u16, Exception>> {
+		if let Some(&halfword) = self.halfwords.get(i) {
+			Some(Ok(halfword))
+		}
+		else if i == self.halfwords.len() {
+			self.fault.map(Err)
+		}
+		else {
+			None
+		}
+	}
+}
@@ -0,0 +1,539 @@
+use crate::{
+	config,
+	instruction::{
+		BranchOp,
+		Instruction,
+		OpOp, Op32Op,
+		OpImmOp, OpImm32Op,
+		MemoryBase, MemoryOffset,
+	},
+	x_regs::XReg,
+};
+
+// Macro-op fusion of adjacent instructions into one, that executes as a single instruction and retires both.
+//
+// Every rule can be enabled or disabled on the command line with comma-separated `rule=true|false` pairs, where `all`
+// stands for every rule, like `all=false,load-pair=true`. All rules are enabled by default. Adjacent instructions that
+// a disabled rule would have fused are counted as fusion opportunities.
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Fusion {
+	AuipcAddi,
+	LuiAdd,
+	LuiAddw,
+	LuiAddi,
+	LuiAddiw,
+	AuipcJalr,
+	AuipcLoad,
+	LuiLoad,
+	AddLoad,
+	Sh1addLoad,
+	Sh2addLoad,
+	Sh3addLoad,
+	SubMax,
+	SlliSrli,
+	LuiLd,
+	LoadPair,
+	SltBranch,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Config {
+	enabled: std::collections::BTreeSet<Fusion>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Statistics {
+	/// The number of instructions fused by each rule.
+	pub(crate) fused: std::collections::BTreeMap<&'static str, usize>,
+	/// The number of instructions that each disabled rule would have fused.
+	pub(crate) opportunities: std::collections::BTreeMap<&'static str, usize>,
+}
+
+impl Fusion {
+	const ALL: [Self; 17] = [
+		Self::AuipcAddi,
+		Self::LuiAdd,
+		Self::LuiAddw,
+		Self::LuiAddi,
+		Self::LuiAddiw,
+		Self::AuipcJalr,
+		Self::AuipcLoad,
+		Self::LuiLoad,
+		Self::AddLoad,
+		Self::Sh1addLoad,
+		Self::Sh2addLoad,
+		Self::Sh3addLoad,
+		Self::SubMax,
+		Self::SlliSrli,
+		Self::LuiLd,
+		Self::LoadPair,
+		Self::SltBranch,
+	];
+
+	/// The name of the rule on the command line.
+	fn name(self) -> &'static str {
+		match self {
+			Self::AuipcAddi => "auipc-addi",
+			Self::LuiAdd => "lui-add",
+			Self::LuiAddw => "lui-addw",
+			Self::LuiAddi => "lui-addi",
+			Self::LuiAddiw => "lui-addiw",
+			Self::AuipcJalr => "auipc-jalr",
+			Self::AuipcLoad => "auipc-load",
+			Self::LuiLoad => "lui-load",
+			Self::AddLoad => "add-load",
+			Self::Sh1addLoad => "sh1add-load",
+			Self::Sh2addLoad => "sh2add-load",
+			Self::Sh3addLoad => "sh3add-load",
+			Self::SubMax => "sub-max",
+			Self::SlliSrli => "slli-srli",
+			Self::LuiLd => "lui-ld",
+			Self::LoadPair => "load-pair",
+			Self::SltBranch => "slt-branch",
+		}
+	}
+
+	/// The name of the rule in the statistics.
+	fn description(self) -> &'static str {
+		match self {
+			Self::AuipcAddi => "auipc; addi -> auipc",
+			Self::LuiAdd => "lui; add -> addi",
+			Self::LuiAddw => "lui; addw -> addiw",
+			Self::LuiAddi => "lui; addi -> lui",
+			Self::LuiAddiw => "lui; addiw -> lui",
+			Self::AuipcJalr => "auipc; jalr -> jal",
+			Self::AuipcLoad => "auipc; load -> load.pc",
+			Self::LuiLoad => "lui; load -> load",
+			Self::AddLoad => "add; load -> load.add",
+			Self::Sh1addLoad => "sh1add; load -> load.sh1add",
+			Self::Sh2addLoad => "sh2add; load -> load.sh2add",
+			Self::Sh3addLoad => "sh3add; load -> load.sh3add",
+			Self::SubMax => "sub; max -> abs",
+			Self::SlliSrli => "slli; srli -> zext",
+			Self::LuiLd => "lui; load -> lui.load",
+			Self::LoadPair => "load; load -> load.pair",
+			Self::SltBranch => "slt; branch -> branch.slt",
+		}
+	}
+}
+
+impl Config {
+	/// Sets the keys given on the command line.
+	pub(crate) fn set(&mut self, arg: &str) -> Result<(), config::Error> {
+		for pair in config::pairs(arg) {
+			let pair = pair?;
+			let enable = pair.bool()?;
+
+			let fusions: &[Fusion] =
+				if pair.key == "all" { &Fusion::ALL }
+				else if let Some(fusion) = Fusion::ALL.iter().find(|fusion| fusion.name() == pair.key) { std::slice::from_ref(fusion) }
+				else { return Err(pair.unknown_key()); };
+			for &fusion in fusions {
+				if enable {
+					self.enabled.insert(fusion);
+				}
+				else {
+					self.enabled.remove(&fusion);
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			enabled: Fusion::ALL.into_iter().collect(),
+		}
+	}
+}
+
+impl Statistics {
+	pub(crate) fn merge(&mut self, other: Self) {
+		for (fusion, count) in other.fused {
+			*self.fused.entry(fusion).or_default() += count;
+		}
+		for (fusion, count) in other.opportunities {
+			*self.opportunities.entry(fusion).or_default() += count;
+		}
+	}
+}
+
+/// Fuses two adjacent instructions if an enabled rule allows it,
+/// and returns the resulting instruction with its length and the number of instructions it retires.
+pub(crate) fn fuse(
+	inst_a: Instruction, inst_a_len: i64,
+	inst_b: Instruction, inst_b_len: i64,
+	config: &Config,
+	statistics: &mut Statistics,
+) -> (Instruction, i64, i64) {
+	match find(inst_a, inst_a_len, inst_b) {
+		Some((inst, fusion)) if config.enabled.contains(&fusion) => {
+			*statistics.fused.entry(fusion.description()).or_default() += 1;
+			(inst, inst_a_len + inst_b_len, 2)
+		},
+
+		Some((_, fusion)) => {
+			*statistics.opportunities.entry(fusion.description()).or_default() += 1;
+			(inst_a, inst_a_len, 1)
+		},
+
+		None => (inst_a, inst_a_len, 1),
+	}
+}
+
+/// Returns the instruction that two adjacent instructions fuse into, and the rule that fuses them.
+///
+/// Rules whose second instruction reads the first one's rd require it to not be x0,
+/// since x0 reads as 0 rather than as the first instruction's result.
+fn find(inst_a: Instruction, inst_a_len: i64, inst_b: Instruction) -> Option<(Instruction, Fusion)> {
+	Some(match (inst_a, inst_b) {
+		(
+			Instruction::Auipc { rd: rd_a, imm: imm_a },
+			Instruction::OpImm { op: OpImmOp::Addi, rd: rd_b, rs1: rs1_b, imm: imm_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Auipc { rd: rd_a, imm: imm_a.wrapping_add(imm_b) },
+			Fusion::AuipcAddi,
+		),
+
+		(
+			Instruction::Lui { rd: rd_a, imm: imm_a },
+			Instruction::Op { op: OpOp::Add, rd: rd_b, rs1: rs1_b, rs2: rs2_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b &&
+			rs1_b != rs2_b
+		=> (
+			Instruction::OpImm { op: OpImmOp::Addi, rd: rd_a, rs1: rs2_b, imm: imm_a },
+			Fusion::LuiAdd,
+		),
+
+		(
+			Instruction::Lui { rd: rd_a, imm: imm_a },
+			Instruction::Op32 { op: Op32Op::Addw, rd: rd_b, rs1: rs1_b, rs2: rs2_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b &&
+			rs1_b != rs2_b
+		=> (
+			Instruction::OpImm32 { op: OpImm32Op::Addiw, rd: rd_a, rs1: rs2_b, imm: imm_a },
+			Fusion::LuiAddw,
+		),
+
+		(
+			Instruction::Lui { rd: rd_a, imm: imm_a },
+			Instruction::OpImm { op: OpImmOp::Addi, rd: rd_b, rs1: rs1_b, imm: imm_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Lui { rd: rd_a, imm: imm_a.wrapping_add(imm_b) },
+			Fusion::LuiAddi,
+		),
+
+		(
+			Instruction::Lui { rd: rd_a, imm: imm_a },
+			Instruction::OpImm32 { op: OpImm32Op::Addiw, rd: rd_b, rs1: rs1_b, imm: imm_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Lui { rd: rd_a, imm: (imm_a.wrapping_add(imm_b) << 32) >> 32 },
+			Fusion::LuiAddiw,
+		),
+
+		(
+			Instruction::Auipc { rd: rd_a, imm: imm_a },
+			Instruction::Jalr { rd: rd_b, rs1: rs1_b, imm: imm_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Jal { rd: rd_a, imm: imm_a.wrapping_add(imm_b) },
+			Fusion::AuipcJalr,
+		),
+
+		(
+			Instruction::Auipc { rd: rd_a, imm: imm_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(offset_b) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::Pc, offset: MemoryOffset::Imm(imm_a.wrapping_add(offset_b)) },
+			Fusion::AuipcLoad,
+		),
+
+		(
+			Instruction::Lui { rd: rd_a, imm: imm_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(offset_b) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XReg(XReg::X0), offset: MemoryOffset::Imm(imm_a.wrapping_add(offset_b)) },
+			Fusion::LuiLoad,
+		),
+
+		(
+			Instruction::Op { op: OpOp::Add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XReg(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
+			Fusion::AddLoad,
+		),
+
+		(
+			Instruction::Op { op: OpOp::Sh1add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XRegSh1(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
+			Fusion::Sh1addLoad,
+		),
+
+		(
+			Instruction::Op { op: OpOp::Sh2add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XRegSh2(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
+			Fusion::Sh2addLoad,
+		),
+
+		(
+			Instruction::Op { op: OpOp::Sh3add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XRegSh3(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
+			Fusion::Sh3addLoad,
+		),
+
+		(
+			Instruction::Op { op: OpOp::Sub, rd: rd_a, rs1: XReg::X0, rs2: rs2_a },
+			Instruction::Op { op: OpOp::Max, rd: rd_b, rs1: rs1_b, rs2: rs2_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b &&
+			rs2_a == rs2_b
+		=> (
+			Instruction::Abs { rd: rd_a, rs: rs2_a },
+			Fusion::SubMax,
+		),
+
+		(
+			Instruction::OpImm { op: OpImmOp::Slli, rd: rd_a, rs1: rs1_a, imm: imm_a },
+			Instruction::OpImm { op: OpImmOp::Srli, rd: rd_b, rs1: rs1_b, imm: imm_b },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a == rd_b &&
+			rd_a == rs1_b &&
+			imm_a == imm_b &&
+			matches!(imm_a, 32 | 48 | 56)
+		=> (
+			match imm_a {
+				32 => Instruction::Op32 { op: Op32Op::AddUw, rd: rd_a, rs1: rs1_a, rs2: XReg::X0 },
+				48 => Instruction::Op32 { op: Op32Op::ZextH, rd: rd_a, rs1: rs1_a, rs2: XReg::X0 },
+				_ => Instruction::OpImm { op: OpImmOp::Andi, rd: rd_a, rs1: rs1_a, imm: 0xff },
+			},
+			Fusion::SlliSrli,
+		),
+
+		(
+			Instruction::Lui { rd: rd_a, imm: imm_a },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(offset_b) },
+		) if
+			rd_a != XReg::X0 &&
+			rd_a != rd_b &&
+			rd_a == rs1_b
+		=> (
+			Instruction::LuiLoad { op: op_b, rd: rd_b, lui_rd: rd_a, lui_imm: imm_a, offset: offset_b, lui_len: inst_a_len },
+			Fusion::LuiLd,
+		),
+
+		(
+			Instruction::Load { op: op_a, rd: rd_a, base: MemoryBase::XReg(rs1_a), offset: MemoryOffset::Imm(offset_a) },
+			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(offset_b) },
+		) if
+			op_a == op_b &&
+			rs1_a == rs1_b &&
+			rd_a != rs1_a &&
+			rd_a != rd_b &&
+			offset_b == offset_a.wrapping_add(i64::try_from(op_a.size()).expect("load size fits in i64"))
+		=> (
+			Instruction::LoadPair { op: op_a, rd1: rd_a, rd2: rd_b, base: rs1_a, imm: offset_a, len1: inst_a_len },
+			Fusion::LoadPair,
+		),
+
+		(
+			Instruction::Op { op: op_a @ (OpOp::Slt | OpOp::Sltu), rd: rd_a, rs1: rs1_a, rs2: rs2_a },
+			Instruction::Branch { op: op_b @ (BranchOp::Equal | BranchOp::NotEqual), rs1: rs1_b, rs2: rs2_b, imm: imm_b },
+		) if
+			rd_a != XReg::X0 &&
+			((rs1_b == rd_a && rs2_b == XReg::X0) || (rs1_b == XReg::X0 && rs2_b == rd_a))
+		=> (
+			Instruction::SltBranch {
+				op: match (op_a, op_b) {
+					(OpOp::Slt, BranchOp::NotEqual) => BranchOp::LessThan,
+					(OpOp::Slt, _) => BranchOp::GreaterThanOrEqual,
+					(_, BranchOp::NotEqual) => BranchOp::LessThanUnsigned,
+					(_, _) => BranchOp::GreaterThanOrEqualUnsigned,
+				},
+				rd: rd_a,
+				rs1: rs1_a,
+				rs2: rs2_a,
+				// The branch is relative to the second instruction.
+				imm: imm_b.wrapping_add(inst_a_len),
+			},
+			Fusion::SltBranch,
+		),
+
+		(_, _) => return None,
+	})
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{Config, Fusion, Statistics};
+	use crate::instruction::Instruction;
+
+	fn decode(line: &str) -> (Instruction, i64) {
+		let supported_extensions = riscv::SupportedExtensions::RV64I | riscv::SupportedExtensions::ZBA | riscv::SupportedExtensions::ZBB;
+		let inst = riscv::parse_program(std::iter::once(line.as_bytes()), supported_extensions).next().unwrap().unwrap();
+		let (lo, hi) = inst.encode(supported_extensions).unwrap();
+		Instruction::decode(u32::from(lo) | (u32::from(hi.unwrap_or_default()) << 16)).unwrap()
+	}
+
+	#[test]
+	fn find() {
+		static TESTS: &[(&str, &str, Option<(Fusion, &str)>)] = &[
+			("auipc a0, 1", "addi a0, a0, 4", Some((Fusion::AuipcAddi, "Auipc { rd: X10, imm: 4100 }"))),
+			("auipc a0, 1", "addi a1, a0, 4", None),
+			("auipc a0, 1", "addi a0, a1, 4", None),
+			("auipc zero, 1", "addi zero, zero, 4", None),
+			("lui a0, 1", "add a0, a0, a1", Some((Fusion::LuiAdd, "OpImm { op: Addi, rd: X10, rs1: X11, imm: 4096 }"))),
+			("lui a0, 1", "add a0, a1, a0", None),
+			("lui a0, 1", "add a0, a0, a0", None),
+			("lui zero, 1", "add zero, zero, a1", None),
+			("lui a0, 1", "addw a0, a0, a1", Some((Fusion::LuiAddw, "OpImm32 { op: Addiw, rd: X10, rs1: X11, imm: 4096 }"))),
+			("lui a0, 1", "addw a1, a0, a1", None),
+			("lui a0, 1", "addw a0, a0, a0", None),
+			("lui a0, 1", "addi a0, a0, -4", Some((Fusion::LuiAddi, "Lui { rd: X10, imm: 4092 }"))),
+			("lui a0, 1", "addi a1, a0, -4", None),
+			("lui zero, 1", "addi zero, zero, -4", None),
+			("lui a0, 0x80000", "addiw a0, a0, -1", Some((Fusion::LuiAddiw, "Lui { rd: X10, imm: 2147483647 }"))),
+			("lui a0, 1", "addiw a0, a1, -1", None),
+			("auipc ra, 1", "jalr ra, 8(ra)", Some((Fusion::AuipcJalr, "Jal { rd: X1, imm: 4104 }"))),
+			("auipc t0, 1", "jalr zero, 8(t0)", None),
+			("auipc ra, 1", "jalr ra, 8(t0)", None),
+			("auipc zero, 1", "jalr zero, 8(zero)", None),
+			("auipc a0, 1", "ld a0, 8(a0)", Some((Fusion::AuipcLoad, "Load { op: DoubleWord, rd: X10, base: Pc, offset: Imm(4104) }"))),
+			("auipc a0, 1", "ld a1, 8(a1)", None),
+			("auipc zero, 1", "ld zero, 8(zero)", None),
+			("lui a0, 1", "lw a0, 8(a0)", Some((Fusion::LuiLoad, "Load { op: Word, rd: X10, base: XReg(X0), offset: Imm(4104) }"))),
+			("lui zero, 1", "lw zero, 8(zero)", None),
+			("lui a0, 1", "lw a1, 8(a0)", Some((Fusion::LuiLd, "LuiLoad { op: Word, rd: X11, lui_rd: X10, lui_imm: 4096, offset: 8, lui_len: 4 }"))),
+			("lui a0, 1", "lw a1, 8(a1)", None),
+			("lui zero, 1", "lw a1, 8(zero)", None),
+			("add a0, a1, a2", "ld a0, 0(a0)", Some((Fusion::AddLoad, "Load { op: DoubleWord, rd: X10, base: XReg(X11), offset: XReg(X12) }"))),
+			("add a0, a1, a2", "ld a0, 8(a0)", None),
+			("add a0, a1, a2", "ld a3, 0(a0)", None),
+			("add zero, a1, a2", "ld zero, 0(zero)", None),
+			("sh1add a0, a1, a2", "lh a0, 0(a0)", Some((Fusion::Sh1addLoad, "Load { op: HalfWord, rd: X10, base: XRegSh1(X11), offset: XReg(X12) }"))),
+			("sh1add a0, a1, a2", "lh a0, 0(a1)", None),
+			("sh2add a0, a1, a2", "lw a0, 0(a0)", Some((Fusion::Sh2addLoad, "Load { op: Word, rd: X10, base: XRegSh2(X11), offset: XReg(X12) }"))),
+			("sh2add a0, a1, a2", "lw a0, 4(a0)", None),
+			("sh3add a0, a1, a2", "ld a0, 0(a0)", Some((Fusion::Sh3addLoad, "Load { op: DoubleWord, rd: X10, base: XRegSh3(X11), offset: XReg(X12) }"))),
+			("sh3add zero, a1, a2", "ld zero, 0(zero)", None),
+			("sub a0, zero, a1", "max a0, a0, a1", Some((Fusion::SubMax, "Abs { rd: X10, rs: X11 }"))),
+			("sub a0, zero, a1", "max a0, a0, a2", None),
+			("sub a0, a2, a1", "max a0, a0, a1", None),
+			("sub a0, zero, a1", "max a0, a1, a0", None),
+			("slli a0, a1, 32", "srli a0, a0, 32", Some((Fusion::SlliSrli, "Op32 { op: AddUw, rd: X10, rs1: X11, rs2: X0 }"))),
+			("slli a0, a1, 48", "srli a0, a0, 48", Some((Fusion::SlliSrli, "Op32 { op: ZextH, rd: X10, rs1: X11, rs2: X0 }"))),
+			("slli a0, a1, 56", "srli a0, a0, 56", Some((Fusion::SlliSrli, "OpImm { op: Andi, rd: X10, rs1: X11, imm: 255 }"))),
+			("slli a0, a1, 40", "srli a0, a0, 40", None),
+			("slli a0, a1, 32", "srli a0, a0, 31", None),
+			("slli a0, a1, 32", "srli a2, a0, 32", None),
+			("ld a0, 8(a2)", "ld a1, 16(a2)", Some((Fusion::LoadPair, "LoadPair { op: DoubleWord, rd1: X10, rd2: X11, base: X12, imm: 8, len1: 4 }"))),
+			("lw a0, 8(a2)", "lw a1, 12(a2)", Some((Fusion::LoadPair, "LoadPair { op: Word, rd1: X10, rd2: X11, base: X12, imm: 8, len1: 4 }"))),
+			("lw a0, 8(a2)", "lw a1, 16(a2)", None),
+			("lw a0, 8(a2)", "lwu a1, 12(a2)", None),
+			("ld a0, 8(a2)", "ld a1, 16(a3)", None),
+			("ld a2, 8(a2)", "ld a1, 16(a2)", None),
+			("ld a0, 8(a2)", "ld a0, 16(a2)", None),
+			("ld a0, 8(a2)", "ld a1, 0(a2)", None),
+			("slt a0, a1, a2", "bne a0, zero, 16", Some((Fusion::SltBranch, "SltBranch { op: LessThan, rd: X10, rs1: X11, rs2: X12, imm: 20 }"))),
+			("slt a0, a1, a2", "beq zero, a0, 16", Some((Fusion::SltBranch, "SltBranch { op: GreaterThanOrEqual, rd: X10, rs1: X11, rs2: X12, imm: 20 }"))),
+			("sltu a0, a1, a2", "bne zero, a0, 16", Some((Fusion::SltBranch, "SltBranch { op: LessThanUnsigned, rd: X10, rs1: X11, rs2: X12, imm: 20 }"))),
+			("sltu a0, a1, a2", "beq a0, zero, 16", Some((Fusion::SltBranch, "SltBranch { op: GreaterThanOrEqualUnsigned, rd: X10, rs1: X11, rs2: X12, imm: 20 }"))),
+			("slt a0, a1, a2", "bne a0, a1, 16", None),
+			("slt zero, a1, a2", "bne zero, zero, 16", None),
+			("slt a0, a1, a2", "blt a0, zero, 16", None),
+		];
+
+		for &(inst_a, inst_b, expected) in TESTS {
+			let (inst_a_decoded, inst_a_len) = decode(inst_a);
+			let (inst_b_decoded, _) = decode(inst_b);
+			let actual = super::find(inst_a_decoded, inst_a_len, inst_b_decoded).map(|(inst, fusion)| (fusion, format!("{inst:?}")));
+			let expected = expected.map(|(fusion, inst)| (fusion, inst.to_owned()));
+			assert_eq!(actual, expected, "{inst_a}; {inst_b}");
+		}
+	}
+
+	#[test]
+	fn fuse() {
+		let (inst_a, inst_a_len) = decode("ld a0, 8(a2)");
+		let (inst_b, inst_b_len) = decode("ld a1, 16(a2)");
+
+		let mut config: Config = Default::default();
+		let mut statistics: Statistics = Default::default();
+		let (inst, len, instret) = super::fuse(inst_a, inst_a_len, inst_b, inst_b_len, &config, &mut statistics);
+		assert!(matches!(inst, Instruction::LoadPair { .. }));
+		assert_eq!((len, instret), (8, 2));
+
+		config.set("all=false,load-pair=false,lui-ld=true").unwrap();
+		let (inst, len, instret) = super::fuse(inst_a, inst_a_len, inst_b, inst_b_len, &config, &mut statistics);
+		assert!(matches!(inst, Instruction::Load { .. }));
+		assert_eq!((len, instret), (4, 1));
+
+		let (inst_c, inst_c_len) = decode("addi a0, a0, 1");
+		_ = super::fuse(inst_b, inst_b_len, inst_c, inst_c_len, &config, &mut statistics);
+
+		assert_eq!(statistics.fused, [("load; load -> load.pair", 1)].into());
+		assert_eq!(statistics.opportunities, [("load; load -> load.pair", 1)].into());
+
+		assert_eq!(config.set("load-pair=yes").unwrap_err().to_string(), "load-pair has an invalid value");
+		assert_eq!(config.set("load-triple=true").unwrap_err().to_string(), "unknown key load-triple");
+	}
+}
@@ -0,0 +1,744 @@
+//! A stub for the GDB remote serial protocol, so that a program can be debugged while it runs on any of the cores.
+//!
+//! Ref: <https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html>
+//!
+//! The program stops before its first instruction. Breakpoints are not implemented by patching an `ebreak` into memory,
+//! so software and hardware breakpoints are the same, and neither is visible to the program.
+
+use std::io::{BufRead, Read, Write};
+
+use crate::{
+	csrs::{Csr, Csrs},
+	memory::Memory,
+	tag::EMPTY_TAG,
+	x_regs::{XReg, XRegs},
+};
+
+pub(crate) struct Stub {
+	connection: std::io::BufReader<Connection>,
+	no_ack: bool,
+	breakpoints: std::collections::BTreeSet<i64>,
+	state: State,
+	/// Whether GDB sent an interrupt while the program was running.
+	interrupted: bool,
+	/// Whether the program has executed an instruction or taken a trap since it last stopped,
+	/// so that resuming at a breakpoint does not stop at it again.
+	advanced: bool,
+	/// The number of instructions since GDB was last polled for an interrupt.
+	unpolled: u32,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State {
+	/// GDB is inspecting the program, or is about to once the program is started.
+	Stopped,
+	/// GDB is waiting for a stop reply to a `c`.
+	Continuing,
+	/// GDB is waiting for a stop reply to an `s`.
+	Stepping,
+	/// GDB has gone away, and the program runs to completion.
+	Detached,
+}
+
+enum Connection {
+	Tcp(std::net::TcpStream),
+	#[cfg(unix)]
+	Unix(std::os::unix::net::UnixStream),
+}
+
+enum Command {
+	Reply(Vec<u8>),
+	Resume { step: bool },
+	Detach,
+	Kill,
+}
+
+// Ref: gdb/features/riscv/64bit-cpu.xml and gdb/riscv-tdep.h
+//
+// GDB numbers x0-x31 from 0, then pc, then f0-f31, then the CSRs by their addresses.
+const PC_REGNUM: u64 = 32;
+const FIRST_CSR_REGNUM: u64 = 65;
+
+const X_REG_NAMES: [&str; 32] = [
+	"zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+	"fp", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+	"a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+	"s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+const CSRS: [(&str, u32); 14] = [
+	("mstatus", 0x300),
+	("misa", 0x301),
+	("mie", 0x304),
+	("mtvec", 0x305),
+	("mcountinhibit", 0x320),
+	("mscratch", 0x340),
+	("mepc", 0x341),
+	("mcause", 0x342),
+	("mtval", 0x343),
+	("mip", 0x344),
+	("cycle", 0xc00),
+	("time", 0xc01),
+	("instret", 0xc02),
+	("mhartid", 0xf14),
+];
+
+/// The largest packet that GDB may send, in bytes.
+const PACKET_SIZE: usize = 0x4000;
+
+/// How many instructions run between checks for an interrupt from GDB, since each check is a syscall.
+const POLL_INTERVAL: u32 = 4096;
+
+/// The signal reported for every stop.
+const SIGTRAP: u8 = 5;
+
+impl Stub {
+	/// Waits for GDB to connect to the given TCP port on localhost, or to the Unix socket at the given path.
+	pub(crate) fn listen(arg: &std::ffi::OsStr) -> std::io::Result<Self> {
+		eprintln!("waiting for gdb to connect to {}", arg.display());
+
+		let connection =
+			if let Some(port) = arg.to_str().and_then(|arg| arg.parse::<u16>().ok()) {
+				let (stream, _) = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port))?.accept()?;
+				stream.set_nodelay(true)?;
+				Connection::Tcp(stream)
+			}
+			else {
+				#[cfg(unix)]
+				{
+					let listener = std::os::unix::net::UnixListener::bind(arg)?;
+					let accepted = listener.accept();
+					// Only one connection is accepted, so the socket is not needed any more.
+					_ = std::fs::remove_file(arg);
+					Connection::Unix(accepted?.0)
+				}
+				#[cfg(not(unix))]
+				return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a port number"));
+			};
+
+		Ok(Self {
+			connection: std::io::BufReader::new(connection),
+			no_ack: false,
+			breakpoints: Default::default(),
+			state: State::Stopped,
+			interrupted: false,
+			advanced: false,
+			unpolled: 0,
+		})
+	}
+
+	/// Returns whether the program should stop before the instruction at `pc`.
+	///
+	/// This only changes when [`Self::advance`] is called, so it can be asked repeatedly about the same instruction.
+	pub(crate) fn stops_at(&self, pc: i64) -> bool {
+		match self.state {
+			State::Stopped => true,
+			State::Continuing => self.advanced && (self.interrupted || self.breakpoints.contains(&pc)),
+			State::Stepping => self.advanced,
+			State::Detached => false,
+		}
+	}
+
+	/// Records that the program has executed an instruction or taken a trap.
+	pub(crate) fn advance(&mut self) {
+		self.advanced = true;
+
+		self.unpolled += 1;
+		if self.unpolled >= POLL_INTERVAL && self.state != State::Detached {
+			self.unpolled = 0;
+			if let Err(err) = self.poll_interrupt() {
+				self.disconnected(&err);
+			}
+		}
+	}
+
+	/// Stops before the instruction at `pc` if GDB wants to, and then records that it is executed.
+	///
+	/// Returns the exit status if GDB killed the program.
+	pub(crate) fn before_instruction(&mut self, pc: &mut i64, x_regs: &mut XRegs, csrs: &mut Csrs, memory: &mut Memory) -> Option<i32> {
+		if self.stops_at(*pc) && let Some(status) = self.stop(pc, x_regs, csrs, memory) {
+			return Some(status);
+		}
+		self.advance();
+		None
+	}
+
+	/// Serves GDB's requests until it resumes the program. The architectural state must be precise, and no register renamed.
+	///
+	/// Returns the exit status if GDB killed the program.
+	pub(crate) fn stop(&mut self, pc: &mut i64, x_regs: &mut XRegs, csrs: &mut Csrs, memory: &mut Memory) -> Option<i32> {
+		match self.serve(pc, x_regs, csrs, memory) {
+			Ok(status) => status,
+			Err(err) => {
+				self.disconnected(&err);
+				None
+			},
+		}
+	}
+
+	/// Tells GDB that the program has exited.
+	pub(crate) fn exited(&mut self, status: i32) {
+		if !matches!(self.state, State::Continuing | State::Stepping) {
+			return;
+		}
+		if let Err(err) = self.send(format!("W{:02x}", status & 0xff).as_bytes()) {
+			self.disconnected(&err);
+		}
+	}
+
+	fn serve(&mut self, pc: &mut i64, x_regs: &mut XRegs, csrs: &mut Csrs, memory: &mut Memory) -> std::io::Result<Option<i32>> {
+		if self.state != State::Stopped {
+			self.send(format!("S{SIGTRAP:02x}").as_bytes())?;
+			self.state = State::Stopped;
+		}
+		self.interrupted = false;
+
+		loop {
+			let packet = self.receive()?;
+			match self.handle(&packet, pc, x_regs, csrs, memory) {
+				Command::Reply(reply) => self.send(&reply)?,
+
+				Command::Resume { step } => {
+					self.state = if step { State::Stepping } else { State::Continuing };
+					self.advanced = false;
+					return Ok(None);
+				},
+
+				Command::Detach => {
+					self.send(b"OK")?;
+					self.state = State::Detached;
+					return Ok(None);
+				},
+
+				// A killed program exits as if it had failed.
+				Command::Kill => {
+					if packet.starts_with(b"vKill") {
+						self.send(b"OK")?;
+					}
+					return Ok(Some(1));
+				},
+			}
+
+			if packet == b"QStartNoAckMode" {
+				self.no_ack = true;
+			}
+		}
+	}
+
+	fn handle(&mut self, packet: &[u8], pc: &mut i64, x_regs: &mut XRegs, csrs: &mut Csrs, memory: &mut Memory) -> Command {
+		let Ok(packet) = std::str::from_utf8(packet) else { return Command::Reply(vec![]); };
+
+		let reply = match packet.split_at_checked(1).unwrap_or((packet, "")) {
+			("?", _) => format!("S{SIGTRAP:02x}").into_bytes(),
+
+			("q", query) if query.starts_with("Supported") =>
+				format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+").into_bytes(),
+
+			("q", query) if let Some(annex) = query.strip_prefix("Xfer:features:read:target.xml:") => {
+				let Some((offset, length)) = annex.split_once(',') else { return error(); };
+				let (Some(offset), Some(length)) = (parse_hex(offset), parse_hex(length)) else { return error(); };
+				let xml = target_xml();
+				let start = usize::try_from(offset).unwrap_or(usize::MAX).min(xml.len());
+				let end = usize::try_from(length).map_or(xml.len(), |length| start.saturating_add(length).min(xml.len()));
+				let mut reply = vec![if end == xml.len() { b'l' } else { b'm' }];
+				reply.extend_from_slice(&xml.as_bytes()[start..end]);
+				reply
+			},
+
+			("Q", "StartNoAckMode") | ("H", _) => b"OK".to_vec(),
+
+			("g", "") => {
+				let mut reply: Vec<_> = (0..32).flat_map(|i| to_hex(&read_register(i, *pc, x_regs, csrs).unwrap_or_default().to_le_bytes())).collect();
+				reply.extend(to_hex(&pc.to_le_bytes()));
+				reply
+			},
+
+			("G", values) => {
+				let Some(values) = from_hex(values) else { return error(); };
+				for (regnum, value) in (0..=PC_REGNUM).zip(values.chunks_exact(8)) {
+					let value = i64::from_le_bytes(value.try_into().expect("chunk has 8 bytes"));
+					write_register(regnum, value, pc, x_regs, csrs);
+				}
+				b"OK".to_vec()
+			},
+
+			("p", regnum) => {
+				let Some(value) = parse_hex(regnum).and_then(|regnum| read_register(regnum, *pc, x_regs, csrs)) else { return error(); };
+				to_hex(&value.to_le_bytes())
+			},
+
+			("P", assignment) => {
+				let Some((regnum, value)) = assignment.split_once('=') else { return error(); };
+				let (Some(regnum), Some(value)) = (parse_hex(regnum), from_hex(value)) else { return error(); };
+				let Ok(value) = <[u8; 8]>::try_from(value) else { return error(); };
+				if !write_register(regnum, i64::from_le_bytes(value), pc, x_regs, csrs) {
+					return error();
+				}
+				b"OK".to_vec()
+			},
+
+			("m", range) => {
+				let Some((address, length)) = parse_range(range) else { return error(); };
+				// Read as many bytes as possible, in case the range runs off the end of a region.
+				let data: Vec<_> =
+					(0..length.min(PACKET_SIZE / 2))
+					.map_while(|i| {
+						let mut b = 0;
+						memory.debug_read(address.wrapping_add(i.try_into().ok()?), std::slice::from_mut(&mut b)).map(|()| b)
+					})
+					.collect();
+				if data.is_empty() && length > 0 {
+					return error();
+				}
+				to_hex(&data)
+			},
+
+			("M", write) => {
+				let Some((range, data)) = write.split_once(':') else { return error(); };
+				let (Some((address, length)), Some(data)) = (parse_range(range), from_hex(data)) else { return error(); };
+				if data.len() != length || memory.debug_write(address, &data).is_none() {
+					return error();
+				}
+				b"OK".to_vec()
+			},
+
+			("Z" | "z", breakpoint) if let Some(address) = breakpoint.strip_prefix(['0', '1']).and_then(|rest| rest.strip_prefix(',')) => {
+				let Some(address) = address.split(',').next().and_then(parse_hex) else { return error(); };
+				if packet.starts_with('Z') {
+					self.breakpoints.insert(address.cast_signed());
+				}
+				else {
+					self.breakpoints.remove(&address.cast_signed());
+				}
+				b"OK".to_vec()
+			},
+
+			("c" | "s", address) => {
+				if !address.is_empty() {
+					let Some(address) = parse_hex(address) else { return error(); };
+					*pc = address.cast_signed();
+				}
+				return Command::Resume { step: packet.starts_with('s') };
+			},
+
+			("D", _) => return Command::Detach,
+
+			("k", _) => return Command::Kill,
+
+			("v", request) if request.starts_with("Kill") => return Command::Kill,
+
+			// Watchpoints, binary writes, vCont and everything else that is optional.
+			_ => vec![],
+		};
+
+		Command::Reply(reply)
+	}
+
+	/// Returns the data of the next packet from GDB, and acknowledges it.
+	fn receive(&mut self) -> std::io::Result<Vec<u8>> {
+		loop {
+			// Skip acknowledgements and interrupts that arrived after the program stopped by itself.
+			if self.read_byte()? != b'$' {
+				continue;
+			}
+
+			let mut data = vec![];
+			loop {
+				match self.read_byte()? {
+					b'#' => break,
+					b => data.push(b),
+				}
+			}
+			let checksum = [self.read_byte()?, self.read_byte()?];
+
+			if self.no_ack {
+				return Ok(data);
+			}
+			if from_hex(std::str::from_utf8(&checksum).unwrap_or_default()) == Some(vec![checksum_of(&data)]) {
+				self.connection.get_mut().write_all(b"+")?;
+				return Ok(data);
+			}
+			self.connection.get_mut().write_all(b"-")?;
+		}
+	}
+
+	/// Sends a packet to GDB, and resends it until GDB acknowledges it.
+	fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+		let mut packet = vec![b'$'];
+		for &b in data {
+			if matches!(b, b'#' | b'$' | b'}' | b'*') {
+				packet.extend([b'}', b ^ 0x20]);
+			}
+			else {
+				packet.push(b);
+			}
+		}
+		let checksum = checksum_of(&packet[1..]);
+		packet.push(b'#');
+		packet.extend(to_hex(&[checksum]));
+
+		loop {
+			self.connection.get_mut().write_all(&packet)?;
+			if self.no_ack {
+				return Ok(());
+			}
+			loop {
+				match self.read_byte()? {
+					b'+' => return Ok(()),
+					b'-' => break,
+					_ => (),
+				}
+			}
+		}
+	}
+
+	/// Checks whether GDB has sent an interrupt, without waiting for it to.
+	fn poll_interrupt(&mut self) -> std::io::Result<()> {
+		if self.connection.buffer().is_empty() {
+			self.connection.get_ref().set_nonblocking(true)?;
+			let filled = self.connection.fill_buf().map(<[u8]>::len);
+			self.connection.get_ref().set_nonblocking(false)?;
+			match filled {
+				Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+				Ok(_) => (),
+				Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+				Err(err) => return Err(err),
+			}
+		}
+
+		if self.read_byte()? == 0x03 {
+			self.interrupted = true;
+		}
+		Ok(())
+	}
+
+	fn read_byte(&mut self) -> std::io::Result<u8> {
+		let mut b = 0;
+		self.connection.read_exact(std::slice::from_mut(&mut b))?;
+		Ok(b)
+	}
+
+	/// Lets the program run to completion once GDB has gone away.
+	fn disconnected(&mut self, err: &std::io::Error) {
+		if err.kind() == std::io::ErrorKind::UnexpectedEof {
+			eprintln!("gdb disconnected");
+		}
+		else {
+			eprintln!("gdb disconnected: {err}");
+		}
+		self.state = State::Detached;
+	}
+}
+
+impl Connection {
+	fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+		match self {
+			Self::Tcp(stream) => stream.set_nonblocking(nonblocking),
+			#[cfg(unix)]
+			Self::Unix(stream) => stream.set_nonblocking(nonblocking),
+		}
+	}
+}
+
+impl Read for Connection {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		match self {
+			Self::Tcp(stream) => stream.read(buf),
+			#[cfg(unix)]
+			Self::Unix(stream) => stream.read(buf),
+		}
+	}
+}
+
+impl Write for Connection {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self {
+			Self::Tcp(stream) => stream.write(buf),
+			#[cfg(unix)]
+			Self::Unix(stream) => stream.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		match self {
+			Self::Tcp(stream) => stream.flush(),
+			#[cfg(unix)]
+			Self::Unix(stream) => stream.flush(),
+		}
+	}
+}
+
+fn read_register(regnum: u64, pc: i64, x_regs: &XRegs, csrs: &Csrs) -> Option<i64> {
+	if let Ok(x_reg) = XReg::try_from(u32::try_from(regnum).ok()?) {
+		Some(x_regs.load_committed(x_reg))
+	}
+	else if regnum == PC_REGNUM {
+		Some(pc)
+	}
+	else {
+		Some(csrs.load(csr(regnum)?).in_order())
+	}
+}
+
+/// Returns false if there is no such register. Writes to read-only CSRs are ignored.
+fn write_register(regnum: u64, value: i64, pc: &mut i64, x_regs: &mut XRegs, csrs: &mut Csrs) -> bool {
+	let Ok(regnum_) = u32::try_from(regnum) else { return false; };
+	if let Ok(x_reg) = XReg::try_from(regnum_) {
+		x_regs.store(x_reg, EMPTY_TAG, value);
+	}
+	else if regnum == PC_REGNUM {
+		*pc = value;
+	}
+	else if let Some(csr) = csr(regnum) {
+		csrs.store(csr, EMPTY_TAG, value);
+	}
+	else {
+		return false;
+	}
+	true
+}
+
+fn csr(regnum: u64) -> Option<Csr> {
+	Csr::try_from(u32::try_from(regnum.checked_sub(FIRST_CSR_REGNUM)?).ok()?).ok()
+}
+
+fn target_xml() -> String {
+	use std::fmt::Write;
+
+	let mut xml = String::new();
+	xml.push_str(r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0">"#);
+	xml.push_str("<architecture>riscv:rv64</architecture>");
+	xml.push_str(r#"<feature name="org.gnu.gdb.riscv.cpu">"#);
+	for (regnum, name) in X_REG_NAMES.iter().enumerate() {
+		let type_ = match *name {
+			"ra" => "code_ptr",
+			"sp" | "fp" => "data_ptr",
+			_ => "int",
+		};
+		_ = write!(xml, r#"<reg name="{name}" bitsize="64" type="{type_}" regnum="{regnum}"/>"#);
+	}
+	_ = write!(xml, r#"<reg name="pc" bitsize="64" type="code_ptr" regnum="{PC_REGNUM}"/>"#);
+	xml.push_str("</feature>");
+	xml.push_str(r#"<feature name="org.gnu.gdb.riscv.csr">"#);
+	for (name, number) in CSRS {
+		_ = write!(xml, r#"<reg name="{name}" bitsize="64" type="int" regnum="{}"/>"#, FIRST_CSR_REGNUM + u64::from(number));
+	}
+	xml.push_str("</feature>");
+	xml.push_str("</target>");
+	xml
+}
+
+fn error() -> Command {
+	Command::Reply(b"E01".to_vec())
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+	data.iter().fold(0, |checksum, &b| checksum.wrapping_add(b))
+}
+
+fn to_hex(data: &[u8]) -> Vec<u8> {
+	data.iter().flat_map(|b| format!("{b:02x}").into_bytes()).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+	if !hex.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+	u64::from_str_radix(s, 16).ok()
+}
+
+/// Parses `address,length`.
+fn parse_range(s: &str) -> Option<(i64, usize)> {
+	let (address, length) = s.split_once(',')?;
+	Some((parse_hex(address)?.cast_signed(), parse_hex(length)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read, Write};
+
+	use super::{Command, Connection, State, Stub, target_xml};
+	use crate::{
+		cache::Caches,
+		csrs::Csrs,
+		memory::{Memory, Misaligned},
+		memory_map::MemoryMap,
+		x_regs::XRegs,
+	};
+
+	/// Returns a stub connected to a socket that plays the part of GDB.
+	fn stub() -> (Stub, std::net::TcpStream) {
+		let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+		let gdb = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (stream, _) = listener.accept().unwrap();
+		gdb.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+		let stub = Stub {
+			connection: std::io::BufReader::new(Connection::Tcp(stream)),
+			no_ack: false,
+			breakpoints: Default::default(),
+			state: State::Stopped,
+			interrupted: false,
+			advanced: false,
+			unpolled: 0,
+		};
+		(stub, gdb)
+	}
+
+	fn memory() -> Memory {
+		let in_file_path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+		Memory::new(&MemoryMap::default(), Misaligned::Trap, vec![], in_file_path, Default::default(), Caches::default()).unwrap()
+	}
+
+	fn read_exactly(gdb: &mut std::net::TcpStream, len: usize) -> String {
+		let mut buf = vec![0; len];
+		gdb.read_exact(&mut buf).unwrap();
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn handle() {
+		static TESTS: &[(&str, &str)] = &[
+			("?", "S05"),
+			("qSupported:multiprocess+;swbreak+;xmlRegisters=i386", "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+"),
+			("QStartNoAckMode", "OK"),
+			("Hg0", "OK"),
+			("vMustReplyEmpty", ""),
+			("qAttached", ""),
+
+			// a0
+			("Pa=8877665544332211", "OK"),
+			("pa", "8877665544332211"),
+			("Pa=88", "E01"),
+			("Pa", "E01"),
+			// Writes to x0 are ignored.
+			("P0=0100000000000000", "OK"),
+			("p0", "0000000000000000"),
+
+			// pc
+			("P20=0000000000000080", "OK"),
+			("p20", "0000000000000080"),
+
+			// mscratch
+			("P381=efbeadde00000000", "OK"),
+			("p381", "efbeadde00000000"),
+
+			// Neither an integer register, pc nor a CSR.
+			("p21", "E01"),
+			("P21=0000000000000000", "E01"),
+			("px", "E01"),
+
+			("M100,4:deadbeef", "OK"),
+			("m100,4", "deadbeef"),
+			("m102,4", "beef0000"),
+			("M100,4:dead", "E01"),
+			("M100,2:dea", "E01"),
+			("M100,2", "E01"),
+			// Reads stop at the end of the region.
+			("m4001ffe,4", "0000"),
+			("m5000000,4", "E01"),
+			("m5000000,0", ""),
+			("m100", "E01"),
+
+			("Z0,100,4", "OK"),
+			("z0,100,4", "OK"),
+			("Z1,100,4", "OK"),
+			("Z0,x,4", "E01"),
+			// Watchpoints
+			("Z2,100,8", ""),
+
+			("c", "resume"),
+			("s", "step"),
+			("s104", "step"),
+			("p20", "0401000000000000"),
+			("cx", "E01"),
+			("D", "detach"),
+			("k", "kill"),
+			("vKill;1", "kill"),
+		];
+
+		let (mut stub, _gdb) = stub();
+		let mut pc = 0;
+		let mut x_regs: XRegs = Default::default();
+		let mut csrs = Csrs::new(riscv::SupportedExtensions::RV64I);
+		let mut memory = memory();
+
+		for &(packet, expected) in TESTS {
+			let actual = match stub.handle(packet.as_bytes(), &mut pc, &mut x_regs, &mut csrs, &mut memory) {
+				Command::Reply(reply) => String::from_utf8(reply).unwrap(),
+				Command::Resume { step: false } => "resume".to_owned(),
+				Command::Resume { step: true } => "step".to_owned(),
+				Command::Detach => "detach".to_owned(),
+				Command::Kill => "kill".to_owned(),
+			};
+			assert_eq!(actual, expected, "{packet:?}");
+		}
+
+		assert_eq!(stub.breakpoints.into_iter().collect::<Vec<_>>(), [0x100]);
+	}
+
+	#[test]
+	fn target_xml_paging() {
+		let (mut stub, _gdb) = stub();
+		let mut pc = 0;
+		let mut x_regs: XRegs = Default::default();
+		let mut csrs = Csrs::new(riscv::SupportedExtensions::RV64I);
+		let mut memory = memory();
+		let mut read = |annex: &str| match stub.handle(format!("qXfer:features:read:target.xml:{annex}").as_bytes(), &mut pc, &mut x_regs, &mut csrs, &mut memory) {
+			Command::Reply(reply) => String::from_utf8(reply).unwrap(),
+			_ => panic!("qXfer did not reply"),
+		};
+
+		let expected = target_xml();
+
+		let mut actual = String::new();
+		loop {
+			let reply = read(&format!("{:x},100", actual.len()));
+			let (more, data) = reply.split_at(1);
+			assert!(data.len() <= 0x100);
+			actual.push_str(data);
+			match more {
+				"m" => assert_eq!(data.len(), 0x100),
+				"l" => break,
+				_ => panic!("{reply:?}"),
+			}
+		}
+		assert_eq!(actual, expected);
+
+		assert_eq!(read(&format!("0,{:x}", expected.len())), format!("l{expected}"));
+		assert_eq!(read(&format!("0,{:x}", expected.len() - 1)), format!("m{}", &expected[..expected.len() - 1]));
+		assert_eq!(read(&format!("{:x},100", expected.len())), "l");
+		assert_eq!(read("ffffffffffffffff,ffffffffffffffff"), "l");
+		assert_eq!(read("0"), "E01");
+		assert_eq!(read("x,100"), "E01");
+	}
+
+	#[test]
+	fn framing() {
+		let (mut stub, mut gdb) = stub();
+
+		// An interrupt and an acknowledgement before the packet are skipped, and a packet with a bad checksum is nacked.
+		gdb.write_all(b"\x03+$m0,4#00$m0,4#fd").unwrap();
+		assert_eq!(stub.receive().unwrap(), b"m0,4");
+		assert_eq!(read_exactly(&mut gdb, 2), "-+");
+
+		// A packet is resent until it is acked, and `#`, `$`, `}` and `*` are escaped.
+		gdb.write_all(b"-+").unwrap();
+		stub.send(b"S05").unwrap();
+		assert_eq!(read_exactly(&mut gdb, 14), "$S05#b8$S05#b8");
+
+		gdb.write_all(b"+").unwrap();
+		stub.send(b"a#$}*").unwrap();
+		assert_eq!(read_exactly(&mut gdb, 13), "$a}\x03}\x04}]}\x0a#c3");
+
+		// Without acks, checksums are not checked.
+		stub.no_ack = true;
+		gdb.write_all(b"$?#00").unwrap();
+		assert_eq!(stub.receive().unwrap(), b"?");
+		stub.send(b"OK").unwrap();
+		assert_eq!(read_exactly(&mut gdb, 6), "$OK#9a");
+	}
+}
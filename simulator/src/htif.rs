@@ -0,0 +1,181 @@
+use crate::{
+	memory::{LoadOp, Memory, StoreOp},
+	memory_map::MemoryMap,
+	program::Segment,
+	symbols::Symbols,
+	syscalls::{SyscallResult, Syscalls},
+};
+
+// Ref: riscv-isa-sim fesvr/htif.cc, fesvr/device.cc
+//
+// The program writes a command to `tohost`, and the host clears `tohost` once it has read the command.
+// If the command has a response, the host writes it to `fromhost`.
+//
+// +--------+---------+------------------------------------------------------------------------------+
+// | Device | Command | Payload                                                                      |
+// +========+=========+==============================================================================+
+// | 0      | 0       | `(exit code << 1) | 1`: Exit with the given code. 0 means the test passed.     |
+// | 0      | 0       | Otherwise, the address of `[n, a0, a1, a2, ...]`: Do syscall `n`.            |
+// |        |         | The result is written back to `n`, and `fromhost` is set to 1.               |
+// | 1      | 0       | Read a character from the console, and write it to `fromhost`.               |
+// | 1      | 1       | Write the character in the low byte to the console.                          |
+// +--------+---------+------------------------------------------------------------------------------+
+pub(crate) struct Htif {
+	tohost: i64,
+	fromhost: Option<i64>,
+	syscalls: Syscalls,
+}
+
+impl Htif {
+	/// Returns `None` if the program does not have a `tohost` symbol.
+	pub(crate) fn new(symbols: &Symbols, segments: &[Segment], memory_map: &MemoryMap) -> Option<Self> {
+		let tohost = symbols.address_of("tohost")?.cast_signed();
+		let fromhost = symbols.address_of("fromhost").map(u64::cast_signed);
+		Some(Self {
+			tohost,
+			fromhost,
+			syscalls: Syscalls::new(segments, memory_map),
+		})
+	}
+
+	/// Services the command in `tohost`, if any.
+	///
+	/// Returns the exit status if the program has exited.
+	pub(crate) fn poll(&mut self, memory: &mut Memory) -> Option<i32> {
+		let command = LoadOp::DoubleWord.exec(memory, self.tohost).ok().filter(|&command| command != 0)?;
+		_ = StoreOp::DoubleWord.exec(memory, self.tohost, 0);
+
+		let device = (command >> 56) & 0xff;
+		let cmd = (command >> 48) & 0xff;
+		let payload = command & 0xffff_ffff_ffff;
+
+		let response = match (device, cmd) {
+			(0, 0) if payload & 0b1 != 0 => {
+				let code = payload >> 1;
+				// Don't let a failing test look like a passing one when the OS truncates the status to 8 bits.
+				return Some(if code == 0 { 0 } else { u8::try_from(code).map_or(1, i32::from) });
+			},
+
+			(0, 0) => {
+				let magic_mem: Option<Vec<_>> =
+					(0..4)
+					.map(|i| LoadOp::DoubleWord.exec(memory, payload.wrapping_add(i * 8)).ok())
+					.collect();
+				let Some(&[n, a0, a1, a2]) = magic_mem.as_deref() else { return None; };
+				match self.syscalls.syscall(n, [a0, a1, a2], memory) {
+					SyscallResult::Return(value) => _ = StoreOp::DoubleWord.exec(memory, payload, value),
+					SyscallResult::Exit(status) => return Some(status),
+				}
+				1
+			},
+
+			(1, 0) => {
+				use std::io::Read;
+
+				let mut c = [0_u8];
+				let c = match std::io::stdin().read(&mut c) {
+					Ok(1) => i64::from(c[0]),
+					_ => 0xffff_ffff_ffff,
+				};
+				(1 << 56) | c
+			},
+
+			(1, 1) => {
+				use std::io::Write;
+
+				let mut stdout = std::io::stdout().lock();
+				_ = stdout.write_all(&payload.to_le_bytes()[..1]);
+				_ = stdout.flush();
+				(1 << 56) | (1 << 48)
+			},
+
+			_ => return None,
+		};
+
+		if let Some(fromhost) = self.fromhost {
+			_ = StoreOp::DoubleWord.exec(memory, fromhost, response);
+		}
+
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Htif;
+	use crate::{
+		cache::Caches,
+		memory::{LoadOp, Memory, Misaligned, StoreOp},
+		memory_map::MemoryMap,
+		symbols::Symbols,
+	};
+
+	const TOHOST: i64 = 0x1000;
+	const FROMHOST: i64 = 0x1008;
+	const MAGIC_MEM: i64 = 0x2000;
+
+	fn htif() -> (Htif, Memory) {
+		let memory_map = MemoryMap::default();
+		let symbols = Symbols::new([(0x1000, 8, "tohost".to_owned()), (0x1008, 8, "fromhost".to_owned())]);
+		let in_file_path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+		let memory = Memory::new(&memory_map, Misaligned::Trap, vec![], in_file_path, Default::default(), Caches::default()).unwrap();
+		(Htif::new(&symbols, &[], &memory_map).unwrap(), memory)
+	}
+
+	#[test]
+	fn no_tohost() {
+		assert!(Htif::new(&Default::default(), &[], &MemoryMap::default()).is_none());
+	}
+
+	#[test]
+	fn exit() {
+		static TESTS: &[(i64, i32)] = &[
+			(0, 0),
+			(3, 3),
+			(255, 255),
+			(256, 1),
+			(0x7fff_ffff_ffff, 1),
+		];
+
+		for &(code, expected) in TESTS {
+			let (mut htif, mut memory) = htif();
+			assert_eq!(htif.poll(&mut memory), None);
+
+			StoreOp::DoubleWord.exec(&mut memory, TOHOST, (code << 1) | 1).unwrap();
+			assert_eq!(htif.poll(&mut memory), Some(expected), "{code}");
+			assert_eq!(LoadOp::DoubleWord.exec(&memory, TOHOST).unwrap(), 0);
+		}
+	}
+
+	#[test]
+	fn syscall() {
+		let (mut htif, mut memory) = htif();
+
+		// brk(0) returns the initial program break, which is the first page since there are no segments.
+		for (i, value) in (0..).zip([214, 0, 0, 0]) {
+			StoreOp::DoubleWord.exec(&mut memory, MAGIC_MEM + i * 8, value).unwrap();
+		}
+		StoreOp::DoubleWord.exec(&mut memory, TOHOST, MAGIC_MEM).unwrap();
+		assert_eq!(htif.poll(&mut memory), None);
+		assert_eq!(LoadOp::DoubleWord.exec(&memory, TOHOST).unwrap(), 0);
+		assert_eq!(LoadOp::DoubleWord.exec(&memory, MAGIC_MEM).unwrap(), 4096);
+		assert_eq!(LoadOp::DoubleWord.exec(&memory, FROMHOST).unwrap(), 1);
+
+		// exit(7)
+		for (i, value) in (0..).zip([93, 7, 0, 0]) {
+			StoreOp::DoubleWord.exec(&mut memory, MAGIC_MEM + i * 8, value).unwrap();
+		}
+		StoreOp::DoubleWord.exec(&mut memory, TOHOST, MAGIC_MEM).unwrap();
+		assert_eq!(htif.poll(&mut memory), Some(7));
+	}
+
+	#[test]
+	fn console_putchar() {
+		let (mut htif, mut memory) = htif();
+
+		StoreOp::DoubleWord.exec(&mut memory, TOHOST, (1 << 56) | (1 << 48) | i64::from(b'\n')).unwrap();
+		assert_eq!(htif.poll(&mut memory), None);
+		assert_eq!(LoadOp::DoubleWord.exec(&memory, TOHOST).unwrap(), 0);
+		assert_eq!(LoadOp::DoubleWord.exec(&memory, FROMHOST).unwrap(), (1 << 56) | (1 << 48));
+	}
+}
@@ -1,34 +1,51 @@
 use crate::{
-	csrs::Csrs,
+	csrs::{Csr, Csrs, MSTATUS_MPIE},
+	divider,
+	exception::Exception,
+	fusion,
+	gdb,
+	htif::Htif,
 	instruction::{
 		Instruction,
+		BranchOp,
 		OpOp, OpImmOp, Op32Op, OpImm32Op,
 		MemoryBase, MemoryOffset,
 	},
 	memory::Memory,
+	symbols::Symbols,
+	syscalls::{SyscallResult, Syscalls},
 	tag::EMPTY_TAG,
+	ucode::DivOp,
 	x_regs::{XReg, XRegs},
 	LogLevel,
 	Statistics,
 	load_inst,
 };
 
+/// The divider of the in-order implementations selects one quotient bit per round.
+pub(crate) const DIV_RADIX: divider::Radix = divider::Radix::Two;
+
 pub(crate) fn run(
 	memory: &mut Memory,
 	x_regs: &mut XRegs,
 	csrs: &mut Csrs,
 	statistics: &mut Statistics,
-	mut pc: i64,
+	pc: &mut i64,
+	symbols: &Symbols,
+	mut syscalls: Option<&mut Syscalls>,
+	mut htif: Option<&mut Htif>,
+	mut gdb: Option<&mut gdb::Stub>,
+	fusions: &fusion::Config,
 	log_level: LogLevel,
-) {
+) -> Result<i32, Exception> {
 	loop {
 		{
-			let tick = csrs.cycle();
+			let tick = csrs.ticks();
 			if log_level == LogLevel::Debug {
 				if tick % 100 == 0 {
 					eprintln!();
 					eprintln!("===== {tick} =====");
-					eprintln!("0x{pc:016x}");
+					eprintln!("{}", symbols.display(*pc));
 				}
 			}
 			else if log_level >= LogLevel::Trace {
@@ -39,44 +56,80 @@ pub(crate) fn run(
 			}
 		}
 
-		let (inst, inst_len, instret) = match load_inst(memory, pc, statistics) {
+		if let Some(gdb) = gdb.as_deref_mut() && let Some(status) = gdb.before_instruction(pc, x_regs, csrs, memory) {
+			return Ok(status);
+		}
+
+		if let Some(interrupt) = csrs.pending_interrupt() {
+			take_trap(interrupt, pc, csrs, memory, statistics, log_level)?;
+			continue;
+		}
+
+		let (inst, inst_len, mut instret) = match load_inst(memory, *pc, fusions, statistics) {
 			Ok(inst) => inst,
-			Err(inst) => panic!("SIGILL: 0x{inst:08x}"),
+			Err(exception) => {
+				take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+				continue;
+			},
 		};
 
+		let fetch_latency = memory.fetch_latency(*pc, inst_len.unsigned_abs().try_into().expect("instruction length fits in usize"));
+
 		if log_level >= LogLevel::Trace {
-			eprintln!("+ 0x{pc:016x} : {inst:?}");
+			eprintln!("+ {} : {inst:?}", symbols.display(*pc));
 		}
 
-		if let Instruction::Ebreak = inst {
-			break;
+		// The interrupt that wakes up the wfi is taken after it retires, so that it returns to the instruction after the wfi.
+		if let Instruction::Wfi = inst {
+			while !csrs.wfi_done() {
+				statistics.num_ticks_where_instructions_not_retired += 1;
+				csrs.tick(1, 0, memory);
+			}
 		}
 
-		let mut next_pc = pc.wrapping_add(inst_len);
+		if let Instruction::Ebreak = inst {
+			return Ok(0);
+		}
 
-		execute(inst, pc, &mut next_pc, x_regs, csrs, memory);
+		if let Instruction::Ecall = inst {
+			let Some(syscalls) = syscalls.as_deref_mut() else {
+				take_trap(Exception::EnvironmentCallFromMMode, pc, csrs, memory, statistics, log_level)?;
+				continue;
+			};
+			match syscalls.ecall(x_regs, memory) {
+				SyscallResult::Return(value) => x_regs.store(XReg::X10, EMPTY_TAG, value),
+				SyscallResult::Exit(status) => return Ok(status),
+			}
+		}
 
-		pc = next_pc;
+		let mut next_pc = pc.wrapping_add(inst_len);
 
-		let cycles: u8 = match inst {
-			Instruction::Op { op: OpOp::Mul | OpOp::Mulh | OpOp::Mulhsu | OpOp::Mulhu, .. }
-				=> 34,
+		let execute_cycles = execute_cycles(inst, x_regs);
 
-			Instruction::Op32 { op: Op32Op::Mulw, .. }
-				=> 17,
+		let memory_latency = match execute(inst, *pc, &mut next_pc, &mut instret, x_regs, csrs, memory) {
+			Ok(memory_latency) => memory_latency,
+			Err(exception) => {
+				take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+				continue;
+			},
+		};
 
-			Instruction::OpImm { op: OpImmOp::Clz | OpImmOp::Ctz | OpImmOp::Cpop, .. } |
-			Instruction::OpImm32 { op: OpImm32Op::Clzw | OpImm32Op::Ctzw | OpImm32Op::Cpopw, .. }
-				=> 3,
+		*pc = next_pc;
 
-			_
-				=> 1,
-		};
+		let cycles = u16::from(execute_cycles) + u16::from(memory_latency) + u16::from(fetch_latency);
 
 		statistics.num_ticks_where_instructions_retired += 1;
 		statistics.num_ticks_where_instructions_not_retired += usize::from(cycles) - 1;
 
-		csrs.tick(cycles.into(), instret);
+		csrs.tick(cycles.into(), instret, memory);
+
+		if let Some(status) = htif.as_deref_mut().and_then(|htif| htif.poll(memory)) {
+			return Ok(status);
+		}
+
+		if let Some(status) = memory.exit_status() {
+			return Ok(status);
+		}
 
 		if log_level >= LogLevel::Trace {
 			eprintln!("->");
@@ -86,14 +139,72 @@ pub(crate) fn run(
 	}
 }
 
-fn execute(
+/// Returns the cycles taken by executing the instruction with the operands in `x_regs`, not counting its memory access.
+pub(crate) fn execute_cycles(inst: Instruction, x_regs: &XRegs) -> u8 {
+	let div_rounds = |op: DivOp, rs1, rs2| op.run(x_regs.load(rs1).in_order(), x_regs.load(rs2).in_order(), DIV_RADIX).1;
+
+	match inst {
+		Instruction::Op { op: OpOp::Mul | OpOp::Mulh | OpOp::Mulhsu | OpOp::Mulhu, .. }
+			=> 34,
+
+		Instruction::Op32 { op: Op32Op::Mulw, .. }
+			=> 17,
+
+		// A division takes a cycle per round of the divider.
+		Instruction::Op { op: OpOp::Div, rs1, rs2, .. } => div_rounds(DivOp::Div, rs1, rs2),
+		Instruction::Op { op: OpOp::Divu, rs1, rs2, .. } => div_rounds(DivOp::Divu, rs1, rs2),
+		Instruction::Op { op: OpOp::Rem, rs1, rs2, .. } => div_rounds(DivOp::Rem, rs1, rs2),
+		Instruction::Op { op: OpOp::Remu, rs1, rs2, .. } => div_rounds(DivOp::Remu, rs1, rs2),
+		Instruction::Op32 { op: Op32Op::Divw, rs1, rs2, .. } => div_rounds(DivOp::Divw, rs1, rs2),
+		Instruction::Op32 { op: Op32Op::Divuw, rs1, rs2, .. } => div_rounds(DivOp::Divuw, rs1, rs2),
+		Instruction::Op32 { op: Op32Op::Remw, rs1, rs2, .. } => div_rounds(DivOp::Remw, rs1, rs2),
+		Instruction::Op32 { op: Op32Op::Remuw, rs1, rs2, .. } => div_rounds(DivOp::Remuw, rs1, rs2),
+
+		Instruction::OpImm { op: OpImmOp::Clz | OpImmOp::Ctz | OpImmOp::Cpop, .. } |
+		Instruction::OpImm32 { op: OpImm32Op::Clzw | OpImm32Op::Ctzw | OpImm32Op::Cpopw, .. }
+			=> 3,
+
+		_
+			=> 1,
+	}
+}
+
+pub(crate) fn take_trap(
+	exception: Exception,
+	pc: &mut i64,
+	csrs: &mut Csrs,
+	memory: &mut Memory,
+	statistics: &mut Statistics,
+	log_level: LogLevel,
+) -> Result<(), Exception> {
+	if log_level >= LogLevel::Trace {
+		eprintln!("trap: {exception}");
+	}
+
+	*pc = csrs.take_trap(exception, *pc).ok_or(exception)?;
+
+	statistics.num_ticks_where_instructions_not_retired += 1;
+
+	csrs.tick(1, 0, memory);
+
+	Ok(())
+}
+
+/// Executes the instruction and returns the extra cycles taken by its memory access.
+///
+/// If the second half of a fused instruction would fault, only the first half is retired, by setting `next_pc`
+/// to the second instruction and `instret` to 1. The second instruction is then executed by itself and takes the trap.
+pub(crate) fn execute(
 	inst: Instruction,
 	pc: i64,
 	next_pc: &mut i64,
+	instret: &mut i64,
 	x_regs: &mut XRegs,
 	csrs: &mut Csrs,
 	memory: &mut Memory,
-) {
+) -> Result<u8, Exception> {
+	let mut memory_latency = 0;
+
 	match inst {
 		Instruction::Abs { rd, rs } => {
 			let arg = x_regs.load(rs).in_order();
@@ -112,6 +223,19 @@ fn execute(
 			}
 		},
 
+		Instruction::SltBranch { op, rd, rs1, rs2, imm } => {
+			let arg1 = x_regs.load(rs1).in_order();
+			let arg2 = x_regs.load(rs2).in_order();
+			let value = match op {
+				BranchOp::LessThanUnsigned | BranchOp::GreaterThanOrEqualUnsigned => arg1.cast_unsigned() < arg2.cast_unsigned(),
+				_ => arg1 < arg2,
+			};
+			x_regs.store(rd, EMPTY_TAG, value.into());
+			if op.exec(arg1, arg2) {
+				*next_pc = pc.wrapping_add(imm);
+			}
+		},
+
 		Instruction::Csrrw { rd, csr, rs1 } =>
 			if rd == XReg::X0 {
 				let new = x_regs.load(rs1).in_order();
@@ -184,7 +308,8 @@ fn execute(
 
 		Instruction::Ebreak => panic!("EBREAK"),
 
-		Instruction::Fence => (),
+		// run() has already serviced the syscall in the case of ecall, and waited for an interrupt in the case of wfi
+		Instruction::Ecall | Instruction::Fence | Instruction::Wfi => (),
 
 		Instruction::Jal { rd, imm } => {
 			x_regs.store(rd, EMPTY_TAG, *next_pc);
@@ -210,14 +335,50 @@ fn execute(
 				MemoryOffset::XReg(rs2) => x_regs.load(rs2).in_order(),
 			};
 			let address = base.wrapping_add(offset);
-			let value = op.exec(memory, address);
+			let value = op.exec(memory, address)?;
 			x_regs.store(rd, EMPTY_TAG, value);
+			memory_latency = op.latency(memory, address);
+		},
+
+		Instruction::LoadPair { op, rd1, rd2, base, imm, len1 } => {
+			let address1 = x_regs.load(base).in_order().wrapping_add(imm);
+			let address2 = address1.wrapping_add(i64::try_from(op.size()).expect("load size fits in i64"));
+			let value1 = op.exec(memory, address1)?;
+			x_regs.store(rd1, EMPTY_TAG, value1);
+			memory_latency = op.latency(memory, address1);
+			if let Ok(value2) = op.exec(memory, address2) {
+				x_regs.store(rd2, EMPTY_TAG, value2);
+				memory_latency = memory_latency.saturating_add(op.latency(memory, address2));
+			}
+			else {
+				*next_pc = pc.wrapping_add(len1);
+				*instret = 1;
+			}
 		},
 
 		Instruction::Lui { rd, imm } => {
 			x_regs.store(rd, EMPTY_TAG, imm);
 		},
 
+		Instruction::LuiLoad { op, rd, lui_rd, lui_imm, offset, lui_len } => {
+			x_regs.store(lui_rd, EMPTY_TAG, lui_imm);
+			let address = lui_imm.wrapping_add(offset);
+			if let Ok(value) = op.exec(memory, address) {
+				x_regs.store(rd, EMPTY_TAG, value);
+				memory_latency = op.latency(memory, address);
+			}
+			else {
+				*next_pc = pc.wrapping_add(lui_len);
+				*instret = 1;
+			}
+		},
+
+		Instruction::Mret => {
+			let mstatus = csrs.load(Csr::Mstatus).in_order();
+			csrs.store(Csr::Mstatus, EMPTY_TAG, ((mstatus & MSTATUS_MPIE) >> 4) | MSTATUS_MPIE);
+			*next_pc = csrs.load(Csr::Mepc).in_order();
+		},
+
 		Instruction::Op { op, rd, rs1, rs2 } => {
 			let arg1 = x_regs.load(rs1).in_order();
 			let arg2 = x_regs.load(rs2).in_order();
@@ -231,6 +392,12 @@ fn execute(
 				OpOp::Bset => arg1 | (1 << (arg2 & 0x3f)),
 				OpOp::CzeroEqz => if arg2 == 0 { 0 } else { arg1 },
 				OpOp::CzeroNez => if arg2 == 0 { arg1 } else { 0 },
+				// Ref: Unprivileged spec 13.2 Division Operations
+				//
+				// Division by zero does not trap, and the overflowing division of the most negative number by -1
+				// returns the dividend with a remainder of 0, ie the wrapping result.
+				OpOp::Div => if arg2 == 0 { -1 } else { arg1.wrapping_div(arg2) },
+				OpOp::Divu => if arg2 == 0 { -1 } else { (arg1.cast_unsigned() / arg2.cast_unsigned()).cast_signed() },
 				OpOp::Max => arg1.max(arg2),
 				OpOp::Maxu => arg1.cast_unsigned().max(arg2.cast_unsigned()).cast_signed(),
 				OpOp::Min => arg1.min(arg2),
@@ -241,6 +408,8 @@ fn execute(
 				OpOp::Mulhu => ((u128::from(arg1.cast_unsigned()) * u128::from(arg2.cast_unsigned())).cast_signed() >> 64).try_into().unwrap(),
 				OpOp::Or => arg1 | arg2,
 				OpOp::Orn => arg1 | !arg2,
+				OpOp::Rem => if arg2 == 0 { arg1 } else { arg1.wrapping_rem(arg2) },
+				OpOp::Remu => if arg2 == 0 { arg1 } else { (arg1.cast_unsigned() % arg2.cast_unsigned()).cast_signed() },
 				OpOp::Rol => arg1.rotate_left((arg2 & 0x3f).try_into().unwrap()),
 				OpOp::Ror => arg1.rotate_right((arg2 & 0x3f).try_into().unwrap()),
 				OpOp::Sh1add => (arg1 << 1).wrapping_add(arg2),
@@ -268,11 +437,16 @@ fn execute(
 
 			#[allow(clippy::cast_possible_truncation)]
 			let arg2w = arg2 as i32;
+			let arg2uw = arg2w.cast_unsigned();
 
 			let value = match op {
 				Op32Op::AddUw => i64::from(arg1uw).wrapping_add(arg2),
 				Op32Op::Addw => arg1w.wrapping_add(arg2w).into(),
+				Op32Op::Divuw => if arg2w == 0 { -1 } else { (arg1uw / arg2uw).cast_signed().into() },
+				Op32Op::Divw => if arg2w == 0 { -1 } else { arg1w.wrapping_div(arg2w).into() },
 				Op32Op::Mulw => arg1w.wrapping_mul(arg2w).into(),
+				Op32Op::Remuw => if arg2w == 0 { arg1w.into() } else { (arg1uw % arg2uw).cast_signed().into() },
+				Op32Op::Remw => if arg2w == 0 { arg1w.into() } else { arg1w.wrapping_rem(arg2w).into() },
 				Op32Op::Rolw => arg1w.rotate_left((arg2w & 0x1f).try_into().unwrap()).into(),
 				Op32Op::Rorw => arg1w.rotate_right((arg2w & 0x1f).try_into().unwrap()).into(),
 				Op32Op::Sh1addUw => (i64::from(arg1uw) << 1).wrapping_add(arg2),
@@ -340,7 +514,10 @@ fn execute(
 		Instruction::Store { op, rs1, rs2, imm } => {
 			let address = x_regs.load(rs1).in_order().wrapping_add(imm);
 			let value = x_regs.load(rs2).in_order();
-			op.exec(memory, address, value);
+			op.exec(memory, address, value)?;
+			memory_latency = op.latency(memory, address);
 		},
 	}
+
+	Ok(memory_latency)
 }
@@ -1,8 +1,15 @@
 use crate::{
 	csrs::Csrs,
+	exception::Exception,
+	fusion,
+	gdb,
+	htif::Htif,
+	in_order::DIV_RADIX,
 	instruction::Instruction,
 	memory::Memory,
 	multiplier::{self, State},
+	symbols::Symbols,
+	syscalls::{SyscallResult, Syscalls},
 	tag::TagAllocator,
 	ucode::{Ucode, BinaryOp, MulOp, UnaryOp},
 	x_regs::XRegs,
@@ -17,17 +24,22 @@ pub(crate) fn run(
 	x_regs: &mut XRegs,
 	csrs: &mut Csrs,
 	statistics: &mut Statistics,
-	mut pc: i64,
+	pc: &mut i64,
+	symbols: &Symbols,
+	mut syscalls: Option<&mut Syscalls>,
+	mut htif: Option<&mut Htif>,
+	mut gdb: Option<&mut gdb::Stub>,
+	fusions: &fusion::Config,
 	log_level: LogLevel,
-) {
+) -> Result<i32, Exception> {
 	loop {
 		{
-			let tick = csrs.cycle();
+			let tick = csrs.ticks();
 			if log_level == LogLevel::Debug {
 				if tick % 100 == 0 {
 					eprintln!();
 					eprintln!("===== {tick} =====");
-					eprintln!("0x{pc:016x}");
+					eprintln!("{}", symbols.display(*pc));
 				}
 			}
 			else if log_level >= LogLevel::Trace {
@@ -38,13 +50,35 @@ pub(crate) fn run(
 			}
 		}
 
-		let (inst, inst_len, instret) = match load_inst(memory, pc, statistics) {
+		if let Some(gdb) = gdb.as_deref_mut() && let Some(status) = gdb.before_instruction(pc, x_regs, csrs, memory) {
+			return Ok(status);
+		}
+
+		if let Some(interrupt) = csrs.pending_interrupt() {
+			take_trap(interrupt, pc, csrs, memory, statistics, log_level)?;
+			continue;
+		}
+
+		let (inst, inst_len, mut instret) = match load_inst(memory, *pc, fusions, statistics) {
 			Ok(inst) => inst,
-			Err(inst) => panic!("SIGILL: 0x{inst:08x}"),
+			Err(exception) => {
+				take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+				continue;
+			},
 		};
 
+		let fetch_latency = memory.fetch_latency(*pc, inst_len.unsigned_abs().try_into().expect("instruction length fits in usize"));
+
 		if log_level >= LogLevel::Trace {
-			eprintln!("+ 0x{pc:016x} : {inst:?}");
+			eprintln!("+ {} : {inst:?}", symbols.display(*pc));
+		}
+
+		// The interrupt that wakes up the wfi is taken after it retires, so that it returns to the instruction after the wfi.
+		if let Instruction::Wfi = inst {
+			while !csrs.wfi_done() {
+				statistics.num_ticks_where_instructions_not_retired += 1;
+				csrs.tick(1, 0, memory);
+			}
 		}
 
 		let next_inst_pc = pc.wrapping_add(inst_len);
@@ -58,7 +92,7 @@ pub(crate) fn run(
 		let mut cycles = 0;
 		let ucode_1234 = Ucode::new(
 			inst,
-			pc,
+			*pc,
 			next_inst_pc,
 			predicted_next_pc,
 			x_regs,
@@ -79,14 +113,45 @@ pub(crate) fn run(
 			}
 		}
 
-		pc = next_inst_pc;
+		let mut next_pc = next_inst_pc;
+		let mut exception = None;
+		let first_half = Ucode::first_half(inst);
+		let mut executed = 0;
 
 		while let Some(mut ucode) = ucodes.pop_front() {
 			if let Ucode::Ebreak = ucode {
-				return;
+				return Ok(0);
+			}
+
+			if let Ucode::Ecall { rd } = &mut ucode {
+				let Some(syscalls) = syscalls.as_deref_mut() else {
+					exception = Some(Exception::EnvironmentCallFromMMode);
+					break;
+				};
+				match syscalls.ecall(x_regs, memory) {
+					SyscallResult::Return(value) => rd.2 = Some(value),
+					SyscallResult::Exit(status) => return Ok(status),
+				}
 			}
 
-			cycles += execute(&mut ucode, memory);
+			match execute(&mut ucode, memory) {
+				Ok(ucode_cycles) => cycles += u16::from(ucode_cycles),
+
+				Err(exception_) => {
+					// If the second instruction of a fused pair faults, only the first one retires,
+					// and the second one is executed again by itself to take the trap.
+					if let Some((first_half_ucodes, first_half_len)) = first_half && executed >= first_half_ucodes {
+						x_regs.reset_all_tags(std::iter::empty());
+						next_pc = pc.wrapping_add(first_half_len);
+						instret = 1;
+					}
+					else {
+						exception = Some(exception_);
+					}
+					break;
+				},
+			}
+			executed += 1;
 
 			if let Some((rd, tag, value)) = ucode.done_rd() {
 				x_regs.store(rd, tag, value);
@@ -101,15 +166,38 @@ pub(crate) fn run(
 				}
 			}
 
-			if let Ucode::Jump { pc: RegisterValue::Value(next_pc), predicted_next_pc: _ } = ucode {
-				pc = next_pc;
+			if let Ucode::Jump { pc: RegisterValue::Value(jump_pc), predicted_next_pc: _ } = ucode {
+				next_pc = jump_pc;
 			}
 		}
 
+		if let Some(exception) = exception {
+			// Only the last ucode of an instruction can fault, and the ucodes before it only write temporaries,
+			// so undoing the renames of the instruction is enough to make the trap precise.
+			x_regs.reset_all_tags(std::iter::empty());
+			csrs.reset_all_tags(std::iter::empty());
+			take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+			continue;
+		}
+
+		*pc = next_pc;
+
+		// Instructions with no architectural effect, like `nop` or writes to read-only fields of CSRs, have no ucodes
+		// but still take a cycle to issue.
+		let cycles = cycles.max(1) + u16::from(fetch_latency);
+
 		statistics.num_ticks_where_instructions_retired += 1;
 		statistics.num_ticks_where_instructions_not_retired += usize::from(cycles) - 1;
 
-		csrs.tick(cycles.into(), instret);
+		csrs.tick(cycles.into(), instret, memory);
+
+		if let Some(status) = htif.as_deref_mut().and_then(|htif| htif.poll(memory)) {
+			return Ok(status);
+		}
+
+		if let Some(status) = memory.exit_status() {
+			return Ok(status);
+		}
 
 		if log_level >= LogLevel::Trace {
 			eprintln!("->");
@@ -119,9 +207,30 @@ pub(crate) fn run(
 	}
 }
 
-fn execute(inst: &mut Ucode, memory: &mut Memory) -> u8 {
+fn take_trap(
+	exception: Exception,
+	pc: &mut i64,
+	csrs: &mut Csrs,
+	memory: &mut Memory,
+	statistics: &mut Statistics,
+	log_level: LogLevel,
+) -> Result<(), Exception> {
+	if log_level >= LogLevel::Trace {
+		eprintln!("trap: {exception}");
+	}
+
+	*pc = csrs.take_trap(exception, *pc).ok_or(exception)?;
+
+	statistics.num_ticks_where_instructions_not_retired += 1;
+
+	csrs.tick(1, 0, memory);
+
+	Ok(())
+}
+
+fn execute(inst: &mut Ucode, memory: &mut Memory) -> Result<u8, Exception> {
 	#[allow(clippy::match_same_arms)]
-	match inst {
+	Ok(match inst {
 		Ucode::BinaryOp {
 			op,
 			rd,
@@ -205,6 +314,18 @@ fn execute(inst: &mut Ucode, memory: &mut Memory) -> u8 {
 			1
 		},
 
+		Ucode::Div {
+			op,
+			rd,
+			rs1: RegisterValue::Value(arg1),
+			rs2: RegisterValue::Value(arg2),
+			state: _,
+		} => {
+			let (value, rounds) = op.run(*arg1, *arg2, DIV_RADIX);
+			rd.2 = Some(value);
+			rounds
+		},
+
 		Ucode::Ebreak => panic!("EBREAK"),
 
 		Ucode::Fence => 1,
@@ -279,9 +400,9 @@ fn execute(inst: &mut Ucode, memory: &mut Memory) -> u8 {
 		Ucode::MvCsr { csr: _, value: RegisterValue::Value(_) } => 1,
 
 		Ucode::Load { op, rd, addr: RegisterValue::Value(addr) } => {
-			let result = op.exec(memory, *addr);
+			let result = op.exec(memory, *addr)?;
 			rd.2 = Some(result);
-			1
+			op.latency(memory, *addr).saturating_add(1)
 		},
 
 		Ucode::Store {
@@ -289,8 +410,8 @@ fn execute(inst: &mut Ucode, memory: &mut Memory) -> u8 {
 			addr: RegisterValue::Value(addr),
 			value: RegisterValue::Value(value),
 		} => {
-			op.exec(memory, *addr, *value);
-			1
+			op.exec(memory, *addr, *value)?;
+			op.latency(memory, *addr).saturating_add(1)
 		},
 
 		Ucode::UnaryOp {
@@ -323,6 +444,12 @@ fn execute(inst: &mut Ucode, memory: &mut Memory) -> u8 {
 			cycles
 		},
 
+		// run() has already serviced the syscall
+		Ucode::Ecall { rd: (_, _, Some(_)) } => 1,
+
+		// run() has already waited for an interrupt
+		Ucode::Wfi => 1,
+
 		_ => unreachable!("{inst:?}")
-	}
+	})
 }
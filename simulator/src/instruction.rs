@@ -18,16 +18,38 @@ pub(crate) enum Instruction {
 	Csrrc { rd: XReg, csr: Csr, rs1: XReg },
 	Csrrci { rd: XReg, csr: Csr, imm: i64 },
 	Ebreak,
+	Ecall,
 	Fence,
 	Jal { rd: XReg, imm: i64 },
 	Jalr { rd: XReg, rs1: XReg, imm: i64 },
 	Load { op: LoadOp, rd: XReg, base: MemoryBase, offset: MemoryOffset },
+	/// Two loads of the same width from adjacent addresses. Only created by macro-op fusion.
+	/// `len1` is the length of the first load, where the second one starts.
+	LoadPair { op: LoadOp, rd1: XReg, rd2: XReg, base: XReg, imm: i64, len1: i64 },
 	Lui { rd: XReg, imm: i64 },
+	/// `lui` followed by a load based on its result. Only created by macro-op fusion.
+	/// `lui_len` is the length of the `lui`, where the load starts.
+	LuiLoad { op: LoadOp, rd: XReg, lui_rd: XReg, lui_imm: i64, offset: i64, lui_len: i64 },
+	Mret,
 	Op { op: OpOp, rd: XReg, rs1: XReg, rs2: XReg },
+	/// `slt` / `sltu` into `rd` followed by a branch on `rd`. Only created by macro-op fusion.
+	SltBranch { op: BranchOp, rd: XReg, rs1: XReg, rs2: XReg, imm: i64 },
 	Op32 { op: Op32Op, rd: XReg, rs1: XReg, rs2: XReg },
 	OpImm { op: OpImmOp, rd: XReg, rs1: XReg, imm: i64 },
 	OpImm32 { op: OpImm32Op, rd: XReg, rs1: XReg, imm: i64 },
 	Store { op: StoreOp, rs1: XReg, rs2: XReg, imm: i64 },
+	Wfi,
+}
+
+// Ref: Privileged spec 2.1 CSR Address Mapping Conventions
+//
+// Writes to read-only CSRs, ie those with the top two bits set, are illegal instructions.
+fn decode_csr(inst: u32, writes: bool) -> Result<Csr, ()> {
+	let raw = (inst >> 20) & 0xfff;
+	if writes && raw >> 10 == 0b11 {
+		return Err(());
+	}
+	raw.try_into()
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -573,41 +595,47 @@ impl Instruction {
 					0b11100 => {
 						let funct3 = (inst >> 12) & 0x7;
 						match funct3 {
-							0b000 => Instruction::Ebreak,
+							0b000 => match inst {
+								0x00000073 => Instruction::Ecall,
+								0x00100073 => Instruction::Ebreak,
+								0x10500073 => Instruction::Wfi,
+								0x30200073 => Instruction::Mret,
+								_ => return Err(()),
+							},
 
 							0b001 => Instruction::Csrrw {
 								rd: ((inst >> 7) & 0x1f).try_into().expect("guaranteed to be in range"),
-								csr: ((inst >> 20) & 0xfff).try_into().expect("unimplemented CSR"),
+								csr: decode_csr(inst, true)?,
 								rs1: ((inst >> 15) & 0x1f).try_into().expect("guaranteed to be in range"),
 							},
 
 							0b010 => Instruction::Csrrs {
 								rd: ((inst >> 7) & 0x1f).try_into().expect("guaranteed to be in range"),
-								csr: ((inst >> 20) & 0xfff).try_into().expect("unimplemented CSR"),
+								csr: decode_csr(inst, (inst >> 15) & 0x1f != 0)?,
 								rs1: ((inst >> 15) & 0x1f).try_into().expect("guaranteed to be in range"),
 							},
 
 							0b011 => Instruction::Csrrc {
 								rd: ((inst >> 7) & 0x1f).try_into().expect("guaranteed to be in range"),
-								csr: ((inst >> 20) & 0xfff).try_into().expect("unimplemented CSR"),
+								csr: decode_csr(inst, (inst >> 15) & 0x1f != 0)?,
 								rs1: ((inst >> 15) & 0x1f).try_into().expect("guaranteed to be in range"),
 							},
 
 							0b101 => Instruction::Csrrwi {
 								rd: ((inst >> 7) & 0x1f).try_into().expect("guaranteed to be in range"),
-								csr: ((inst >> 20) & 0xfff).try_into().expect("unimplemented CSR"),
+								csr: decode_csr(inst, true)?,
 								imm: ((inst >> 15) & 0x1f).into(),
 							},
 
 							0b110 => Instruction::Csrrsi {
 								rd: ((inst >> 7) & 0x1f).try_into().expect("guaranteed to be in range"),
-								csr: ((inst >> 20) & 0xfff).try_into().expect("unimplemented CSR"),
+								csr: decode_csr(inst, (inst >> 15) & 0x1f != 0)?,
 								imm: ((inst >> 15) & 0x1f).into(),
 							},
 
 							0b111 => Instruction::Csrrci {
 								rd: ((inst >> 7) & 0x1f).try_into().expect("guaranteed to be in range"),
-								csr: ((inst >> 20) & 0xfff).try_into().expect("unimplemented CSR"),
+								csr: decode_csr(inst, (inst >> 15) & 0x1f != 0)?,
 								imm: ((inst >> 15) & 0x1f).into(),
 							},
 
@@ -629,6 +657,88 @@ impl Instruction {
 			_ => unreachable!(),
 		})
 	}
+
+	/// Returns the integer registers that the instruction reads, other than `x0`.
+	pub(crate) fn sources(&self) -> [Option<XReg>; 2] {
+		let (rs1, rs2) = match *self {
+			Self::Abs { rs, .. } => (Some(rs), None),
+
+			Self::Branch { rs1, rs2, .. } |
+			Self::Op { rs1, rs2, .. } |
+			Self::SltBranch { rs1, rs2, .. } |
+			Self::Op32 { rs1, rs2, .. } |
+			Self::Store { rs1, rs2, .. } => (Some(rs1), Some(rs2)),
+
+			Self::Csrrw { rs1, .. } |
+			Self::Csrrs { rs1, .. } |
+			Self::Csrrc { rs1, .. } |
+			Self::Jalr { rs1, .. } |
+			Self::LoadPair { base: rs1, .. } |
+			Self::OpImm { rs1, .. } |
+			Self::OpImm32 { rs1, .. } => (Some(rs1), None),
+
+			Self::Load { base, offset, .. } => (
+				match base {
+					MemoryBase::XReg(rs1) | MemoryBase::XRegSh1(rs1) | MemoryBase::XRegSh2(rs1) | MemoryBase::XRegSh3(rs1) => Some(rs1),
+					MemoryBase::Pc => None,
+				},
+				match offset {
+					MemoryOffset::Imm(_) => None,
+					MemoryOffset::XReg(rs2) => Some(rs2),
+				},
+			),
+
+			Self::Auipc { .. } |
+			Self::Csrrwi { .. } |
+			Self::Csrrsi { .. } |
+			Self::Csrrci { .. } |
+			Self::Ebreak |
+			Self::Ecall |
+			Self::Fence |
+			Self::Jal { .. } |
+			Self::Lui { .. } |
+			Self::LuiLoad { .. } |
+			Self::Mret |
+			Self::Wfi => (None, None),
+		};
+		[rs1.filter(|&rs1| rs1 != XReg::X0), rs2.filter(|&rs2| rs2 != XReg::X0)]
+	}
+
+	/// Returns the integer registers that the instruction writes, other than `x0`.
+	pub(crate) fn destinations(&self) -> [Option<XReg>; 2] {
+		let (rd1, rd2) = match *self {
+			Self::Abs { rd, .. } |
+			Self::Auipc { rd, .. } |
+			Self::Csrrw { rd, .. } |
+			Self::Csrrwi { rd, .. } |
+			Self::Csrrs { rd, .. } |
+			Self::Csrrsi { rd, .. } |
+			Self::Csrrc { rd, .. } |
+			Self::Csrrci { rd, .. } |
+			Self::Jal { rd, .. } |
+			Self::Jalr { rd, .. } |
+			Self::Load { rd, .. } |
+			Self::Lui { rd, .. } |
+			Self::Op { rd, .. } |
+			Self::Op32 { rd, .. } |
+			Self::OpImm { rd, .. } |
+			Self::OpImm32 { rd, .. } |
+			Self::SltBranch { rd, .. } => (Some(rd), None),
+
+			Self::LoadPair { rd1, rd2, .. } => (Some(rd1), Some(rd2)),
+
+			Self::LuiLoad { rd, lui_rd, .. } => (Some(lui_rd), Some(rd)),
+
+			Self::Branch { .. } |
+			Self::Ebreak |
+			Self::Ecall |
+			Self::Fence |
+			Self::Mret |
+			Self::Store { .. } |
+			Self::Wfi => (None, None),
+		};
+		[rd1.filter(|&rd1| rd1 != XReg::X0), rd2.filter(|&rd2| rd2 != XReg::X0)]
+	}
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -642,6 +752,8 @@ pub(crate) enum OpOp {
 	Bset,
 	CzeroEqz,
 	CzeroNez,
+	Div,
+	Divu,
 	Max,
 	Maxu,
 	Min,
@@ -652,6 +764,8 @@ pub(crate) enum OpOp {
 	Mulhu,
 	Or,
 	Orn,
+	Rem,
+	Remu,
 	Rol,
 	Ror,
 	Sh1add,
@@ -687,20 +801,24 @@ impl TryFrom<(u8, u8)> for OpOp {
 			(0b011, 0b0000000) => Self::Sltu,
 			(0b011, 0b0000001) => Self::Mulhu,
 			(0b100, 0b0000000) => Self::Xor,
+			(0b100, 0b0000001) => Self::Div,
 			(0b100, 0b0000101) => Self::Min,
 			(0b100, 0b0010000) => Self::Sh2add,
 			(0b100, 0b0100000) => Self::Xnor,
 			(0b101, 0b0000000) => Self::Srl,
+			(0b101, 0b0000001) => Self::Divu,
 			(0b101, 0b0000101) => Self::Minu,
 			(0b101, 0b0000111) => Self::CzeroEqz,
 			(0b101, 0b0100000) => Self::Sra,
 			(0b101, 0b0100100) => Self::Bext,
 			(0b101, 0b0110000) => Self::Ror,
 			(0b110, 0b0000000) => Self::Or,
+			(0b110, 0b0000001) => Self::Rem,
 			(0b110, 0b0000101) => Self::Max,
 			(0b110, 0b0010000) => Self::Sh3add,
 			(0b110, 0b0100000) => Self::Orn,
 			(0b111, 0b0000000) => Self::And,
+			(0b111, 0b0000001) => Self::Remu,
 			(0b111, 0b0000101) => Self::Maxu,
 			(0b111, 0b0000111) => Self::CzeroNez,
 			(0b111, 0b0100000) => Self::Andn,
@@ -713,7 +831,11 @@ impl TryFrom<(u8, u8)> for OpOp {
 pub(crate) enum Op32Op {
 	AddUw,
 	Addw,
+	Divuw,
+	Divw,
 	Mulw,
+	Remuw,
+	Remw,
 	Rolw,
 	Rorw,
 	Sh1addUw,
@@ -738,15 +860,19 @@ impl TryFrom<(u8, u8, u8)> for Op32Op {
 			(0b001, 0b0000000) => Self::Sllw,
 			(0b001, 0b0110000) => Self::Rolw,
 			(0b010, 0b0010000) => Self::Sh1addUw,
+			(0b100, 0b0000001) => Self::Divw,
 			(0b100, 0b0000100) => match funct5 {
 				0b00000 => Self::ZextH,
 				_ => return Err(()),
 			},
 			(0b100, 0b0010000) => Self::Sh2addUw,
 			(0b101, 0b0000000) => Self::Srlw,
+			(0b101, 0b0000001) => Self::Divuw,
 			(0b101, 0b0100000) => Self::Sraw,
 			(0b101, 0b0110000) => Self::Rorw,
+			(0b110, 0b0000001) => Self::Remw,
 			(0b110, 0b0010000) => Self::Sh3addUw,
+			(0b111, 0b0000001) => Self::Remuw,
 			_ => return Err(()),
 		})
 	}
@@ -0,0 +1,134 @@
+// The Level Input and Level Output of a Turing Complete level, at `2^xlen - 8`.
+//
+// Each load pops the next value of the input script, truncated to the width of the load.
+// Reading past the end of the input script is an access fault.
+//
+// Each store is checked against the next value of the expected output script, truncated to the width of the store.
+// The program passes once every expected output has been stored, and fails at the first mismatch.
+// Without an expected output script, stores are only recorded.
+//
+// Scripts are integers like `5`, `-1` or `0xff`, separated by whitespace or commas, with `#` comments.
+#[derive(Debug, Default)]
+pub(crate) struct LevelIo {
+	input: Vec<u64>,
+	next_input: std::cell::Cell<usize>,
+	expected_output: Option<Vec<u64>>,
+	output: Vec<u64>,
+	mismatch: Option<Mismatch>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Mismatch {
+	pub(crate) position: usize,
+	pub(crate) expected: u64,
+	pub(crate) actual: u64,
+}
+
+#[derive(Debug)]
+pub(crate) enum Outcome {
+	Passed,
+	Failed(Mismatch),
+	/// The program stopped before storing every expected output.
+	Incomplete { expected: usize },
+	/// There is no expected output script.
+	Unchecked,
+}
+
+impl LevelIo {
+	pub(crate) fn new(input: Vec<u64>, expected_output: Option<Vec<u64>>) -> Self {
+		Self {
+			input,
+			expected_output,
+			..Default::default()
+		}
+	}
+
+	/// Parses a script, or returns the line number of the first malformed value.
+	pub(crate) fn parse_script(s: &str) -> Result<Vec<u64>, usize> {
+		let mut result = vec![];
+		for (line_number, line) in (1_usize..).zip(s.lines()) {
+			let line = line.split_once('#').map_or(line, |(line, _)| line);
+			for value in line.split(|c: char| c.is_ascii_whitespace() || c == ',').filter(|value| !value.is_empty()) {
+				let value = match value.strip_prefix('-') {
+					Some(value) => crate::memory_map::parse_integer(value).map(u64::wrapping_neg),
+					None => crate::memory_map::parse_integer(value),
+				};
+				result.push(value.ok_or(line_number)?);
+			}
+		}
+		Ok(result)
+	}
+
+	/// Returns `None` if the input script has been exhausted.
+	pub(crate) fn load(&self, offset: u64, buf: &mut [u8]) -> Option<()> {
+		if offset != 0 {
+			buf.fill(0);
+			return Some(());
+		}
+
+		let next_input = self.next_input.get();
+		let value = self.input.get(next_input)?;
+		self.next_input.set(next_input + 1);
+		buf.copy_from_slice(value.to_le_bytes().get(..buf.len())?);
+		Some(())
+	}
+
+	pub(crate) fn store(&mut self, offset: u64, data: &[u8]) {
+		if offset != 0 || self.mismatch.is_some() {
+			return;
+		}
+
+		let mut actual = [0_u8; 8];
+		actual[..data.len()].copy_from_slice(data);
+		let actual = u64::from_le_bytes(actual);
+
+		let position = self.output.len();
+		self.output.push(actual);
+
+		if let Some(&expected) = self.expected_output.as_ref().and_then(|expected_output| expected_output.get(position)) {
+			let mask = u64::MAX >> (64 - 8 * data.len());
+			if expected & mask != actual {
+				self.mismatch = Some(Mismatch { position, expected, actual });
+			}
+		}
+	}
+
+	/// Returns the exit status once every expected output has been stored, or at the first mismatch.
+	pub(crate) fn exit_status(&self) -> Option<i32> {
+		let expected_output = self.expected_output.as_ref()?;
+		if self.mismatch.is_some() {
+			Some(1)
+		}
+		else if self.output.len() >= expected_output.len() {
+			Some(0)
+		}
+		else {
+			None
+		}
+	}
+
+	pub(crate) fn outcome(&self) -> Outcome {
+		match (&self.expected_output, self.mismatch) {
+			(_, Some(mismatch)) => Outcome::Failed(mismatch),
+			(Some(expected_output), None) if self.output.len() < expected_output.len() => Outcome::Incomplete { expected: expected_output.len() },
+			(Some(_), None) => Outcome::Passed,
+			(None, None) => Outcome::Unchecked,
+		}
+	}
+
+	pub(crate) fn output(&self) -> &[u64] {
+		&self.output
+	}
+}
+
+impl std::fmt::Display for Outcome {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Passed => f.write_str("passed"),
+			Self::Failed(Mismatch { position, expected, actual }) =>
+				write!(f, "failed at output {position}: expected {expected}, got {actual}"),
+			Self::Incomplete { expected } => write!(f, "failed: program stopped before storing all {expected} outputs"),
+			Self::Unchecked => f.write_str("no expected output"),
+		}
+	}
+}
@@ -1,28 +1,72 @@
+mod branch_predictor;
+
+mod cache;
+use cache::Caches;
+
+mod clint;
+
+mod config;
+
 mod csrs;
 use csrs::Csrs;
 
+mod divider;
+
+mod dual_issue;
+
+mod elf;
+
+mod exception;
+use exception::Exception;
+
+mod fetch_buffer;
+
+mod fusion;
+
+mod gdb;
+
+mod htif;
+use htif::Htif;
+
 mod in_order;
 
 mod in_order_ucode;
 
+mod level_io;
+use level_io::LevelIo;
+
 mod instruction;
-use instruction::{
-	Instruction,
-	OpOp, Op32Op,
-	OpImmOp, OpImm32Op,
-	MemoryBase, MemoryOffset,
-};
+use instruction::Instruction;
 
 mod memory;
-use memory::{Memory, LoadOp};
+use memory::{Memory, Misaligned};
+
+mod memory_map;
+use memory_map::MemoryMap;
 
 mod multiplier;
 
 mod out_of_order;
 
+mod pages;
+
+mod pipelined;
+
+mod program;
+
+mod return_address_stack;
+
+mod symbols;
+use symbols::Symbols;
+
+mod syscalls;
+use syscalls::Syscalls;
+
 mod tag;
 use tag::Tag;
 
+mod uart;
+
 mod x_regs;
 use x_regs::{XReg, XRegs};
 
@@ -37,25 +81,105 @@ fn main() {
 
 	let mut args = std::env::args_os();
 	let argv0 = args.next().unwrap_or_else(|| env!("CARGO_BIN_NAME").into());
-	let (mode, program_path, in_file_path) = parse_args(args, &argv0);
+	let Args {
+		mode,
+		program_path,
+		in_file_path,
+		supported_extensions,
+		symbols_path,
+		user_mode,
+		memory_map,
+		misaligned,
+		level_io,
+		caches,
+		fusions,
+		gdb,
+	} = parse_args(args, &argv0);
+
+	let program = match program::load(&program_path, supported_extensions) {
+		Ok(program) => program,
+		Err(err) => {
+			eprintln!("could not load {}:", program_path.display());
+			eprintln!("{err}");
+			std::process::exit(1);
+		},
+	};
+
+	let symbols =
+		if let Some(symbols_path) = symbols_path {
+			let symbols = match std::fs::read_to_string(&symbols_path) {
+				Ok(symbols) => symbols,
+				Err(err) => {
+					eprintln!("could not load {}:", symbols_path.display());
+					eprintln!("{err}");
+					std::process::exit(1);
+				},
+			};
+			match Symbols::parse_map(&symbols) {
+				Ok(symbols) => symbols,
+				Err(line_number) => {
+					eprintln!("could not load {}:", symbols_path.display());
+					eprintln!("line {line_number}: malformed symbol");
+					std::process::exit(1);
+				},
+			}
+		}
+		else {
+			program.symbols
+		};
 
-	let mut memory = Memory::new(program_path, in_file_path);
+	let mut pc = program.entry;
+
+	if user_mode && memory_map.region("ram").is_none() {
+		eprintln!("--user-mode requires a region named ram");
+		std::process::exit(1);
+	}
+
+	let mut syscalls = user_mode.then(|| Syscalls::new(&program.segments, &memory_map));
+
+	let mut htif = Htif::new(&symbols, &program.segments, &memory_map);
+
+	let report_level_io = level_io.is_some();
+	let mut memory = match Memory::new(&memory_map, misaligned, program.segments, in_file_path, level_io.unwrap_or_default(), caches) {
+		Ok(memory) => memory,
+		Err(err) => {
+			eprintln!("could not load {}:", program_path.display());
+			eprintln!("{err}");
+			std::process::exit(1);
+		},
+	};
 
 	let mut x_regs: XRegs = Default::default();
 
-	let mut csrs: Csrs = Default::default();
+	if let Some(syscalls) = &mut syscalls {
+		syscalls.init_stack(program_path.as_os_str(), &mut memory, &mut x_regs);
+	}
+
+	let mut csrs = Csrs::new(supported_extensions);
 
 	let mut statistics: Statistics = Default::default();
 
-	let pc = 0x8000_0000_0000_0000_u64.cast_signed();
+	let mut gdb = gdb.map(|arg| match gdb::Stub::listen(&arg) {
+		Ok(gdb) => gdb,
+		Err(err) => {
+			eprintln!("could not listen for gdb on {}:", arg.display());
+			eprintln!("{err}");
+			std::process::exit(1);
+		},
+	});
 
-	match mode {
+	let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match mode {
 		Mode::InOrder => in_order::run(
 			&mut memory,
 			&mut x_regs,
 			&mut csrs,
 			&mut statistics,
-			pc,
+			&mut pc,
+			&symbols,
+			syscalls.as_mut(),
+			htif.as_mut(),
+			gdb.as_mut(),
+			&fusions,
 			log_level,
 		),
 
@@ -64,210 +188,153 @@ fn main() {
 			&mut x_regs,
 			&mut csrs,
 			&mut statistics,
-			pc,
+			&mut pc,
+			&symbols,
+			syscalls.as_mut(),
+			htif.as_mut(),
+			gdb.as_mut(),
+			&fusions,
 			log_level,
 		),
 
-		Mode::OutOfOrder { max_retire_per_cycle } => out_of_order::run(
+		Mode::Pipelined(config) => pipelined::run(
 			&mut memory,
 			&mut x_regs,
 			&mut csrs,
 			&mut statistics,
-			pc,
-			max_retire_per_cycle,
+			&mut pc,
+			&symbols,
+			syscalls.as_mut(),
+			htif.as_mut(),
+			gdb.as_mut(),
+			&fusions,
+			config,
 			log_level,
 		),
-	}
-
-	memory.dump_console();
 
-	println!("{statistics}");
+		Mode::DualIssue(ref config) => dual_issue::run(
+			&mut memory,
+			&mut x_regs,
+			&mut csrs,
+			&mut statistics,
+			&mut pc,
+			&symbols,
+			syscalls.as_mut(),
+			htif.as_mut(),
+			gdb.as_mut(),
+			&fusions,
+			config,
+			log_level,
+		),
 
-	println!("{x_regs}");
+		Mode::OutOfOrder(ref config) => out_of_order::run(
+			&mut memory,
+			&mut x_regs,
+			&mut csrs,
+			&mut statistics,
+			&mut pc,
+			&symbols,
+			syscalls.as_mut(),
+			htif.as_mut(),
+			gdb.as_mut(),
+			&fusions,
+			config,
+			log_level,
+		),
+	}));
+	let mut exit_code = match result {
+		Ok(Ok(status)) => status,
+
+		Ok(Err(exception)) => {
+			eprintln!("unhandled exception: {exception}");
+			eprintln!("{}", symbols.backtrace(pc, &x_regs, &memory));
+			1
+		},
+
+		Err(err) => {
+			eprintln!("{}", symbols.backtrace(pc, &x_regs, &memory));
+			std::panic::resume_unwind(err);
+		},
+	};
 
-	println!("{csrs}");
-}
+	statistics.caches = memory.cache_statistics();
 
-fn load_inst(memory: &Memory, pc: i64, statistics: &mut Statistics) -> Result<(Instruction, i64, i64), u32> {
-	let inst1 = LoadOp::HalfWordUnsigned.exec(memory, pc).cast_unsigned();
-	let inst2 = LoadOp::HalfWordUnsigned.exec(memory, pc + 2).cast_unsigned();
-	let inst3 = LoadOp::HalfWordUnsigned.exec(memory, pc + 4).cast_unsigned();
-	let inst4 = LoadOp::HalfWordUnsigned.exec(memory, pc + 6).cast_unsigned();
-	let inst = inst1 | (inst2 << 16) | (inst3 << 32) | (inst4 << 48);
+	let level_io_report = report_level_io.then(|| {
+		use std::fmt::Write;
 
-	#[allow(clippy::cast_possible_truncation)]
-	let Ok((inst_a, inst_a_len)) = Instruction::decode(inst as u32) else {
-		return Err(inst as u32);
-	};
+		let level_io = memory.level_io();
+		let outcome = level_io.map_or(level_io::Outcome::Unchecked, LevelIo::outcome);
+		if matches!(outcome, level_io::Outcome::Failed(_) | level_io::Outcome::Incomplete { .. }) {
+			exit_code = 1;
+		}
+		let mut report = format!("level: {outcome} in {} cycles\nlevel output:", csrs.ticks());
+		for value in level_io.map_or(&[][..], LevelIo::output) {
+			_ = write!(report, " {value}");
+		}
+		report
+	});
 
-	let inst = inst >> (inst_a_len * 8);
-	#[allow(clippy::cast_possible_truncation)]
-	let result =
-		if let Ok((inst_b, inst_b_len)) = Instruction::decode(inst as u32) {
-			macro_op_fuse(inst_a, inst_a_len, inst_b, inst_b_len, &mut statistics.fusions)
+	// In user mode or with HTIF, stdout belongs to the program.
+	if user_mode || htif.is_some() {
+		if let Some(level_io_report) = level_io_report {
+			eprintln!("{level_io_report}");
 		}
-		else {
-			(inst_a, inst_a_len, 1)
-		};
-	Ok(result)
-}
 
-fn macro_op_fuse(
-	inst_a: Instruction, inst_a_len: i64,
-	inst_b: Instruction, inst_b_len: i64,
-	fusions: &mut std::collections::BTreeMap<&'static str, usize>,
-) -> (Instruction, i64, i64) {
-	let (inst, entry) = match (inst_a, inst_b) {
-		(
-			Instruction::Auipc { rd: rd_a, imm: imm_a },
-			Instruction::OpImm { op: OpImmOp::Addi, rd: rd_b, rs1: rs1_b, imm: imm_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Auipc { rd: rd_a, imm: imm_a.wrapping_add(imm_b) },
-			"auipc; addi -> auipc",
-		),
+		eprintln!("{statistics}");
 
-		(
-			Instruction::Lui { rd: rd_a, imm: imm_a },
-			Instruction::Op { op: OpOp::Add, rd: rd_b, rs1: rs1_b, rs2: rs2_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b &&
-			rs1_b != rs2_b
-		=> (
-			Instruction::OpImm { op: OpImmOp::Addi, rd: rd_a, rs1: rs2_b, imm: imm_a },
-			"lui; add -> addi",
-		),
+		eprintln!("{x_regs}");
 
-		(
-			Instruction::Lui { rd: rd_a, imm: imm_a },
-			Instruction::Op32 { op: Op32Op::Addw, rd: rd_b, rs1: rs1_b, rs2: rs2_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b &&
-			rs1_b != rs2_b
-		=> (
-			Instruction::OpImm32 { op: OpImm32Op::Addiw, rd: rd_a, rs1: rs2_b, imm: imm_a },
-			"lui; addw -> addiw",
-		),
+		eprintln!("{csrs}");
+	}
+	else {
+		memory.dump_console();
 
-		(
-			Instruction::Lui { rd: rd_a, imm: imm_a },
-			Instruction::OpImm { op: OpImmOp::Addi, rd: rd_b, rs1: rs1_b, imm: imm_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Lui { rd: rd_a, imm: imm_a.wrapping_add(imm_b) },
-			"lui; addi -> lui",
-		),
+		if let Some(level_io_report) = level_io_report {
+			println!("{level_io_report}");
+		}
 
-		(
-			Instruction::Lui { rd: rd_a, imm: imm_a },
-			Instruction::OpImm32 { op: OpImm32Op::Addiw, rd: rd_b, rs1: rs1_b, imm: imm_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Lui { rd: rd_a, imm: ((imm_a << 31).wrapping_add(imm_b << 31)) >> 31 },
-			"lui; addiw -> lui",
-		),
+		println!("{statistics}");
 
-		(
-			Instruction::Auipc { rd: rd_a, imm: imm_a },
-			Instruction::Jalr { rd: rd_b, rs1: rs1_b, imm: imm_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Jal { rd: rd_a, imm: imm_a.wrapping_add(imm_b) },
-			"auipc; jalr -> jal",
-		),
+		println!("{x_regs}");
 
-		(
-			Instruction::Auipc { rd: rd_a, imm: imm_a },
-			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(offset_b) },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::Pc, offset: MemoryOffset::Imm(imm_a.wrapping_add(offset_b)) },
-			"auipc; load -> load.pc",
-		),
+		println!("{csrs}");
+	}
 
-		(
-			Instruction::Lui { rd: rd_a, imm: imm_a },
-			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(offset_b) },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XReg(XReg::X0), offset: MemoryOffset::Imm(imm_a.wrapping_add(offset_b)) },
-			"lui; load -> load",
-		),
+	if let Some(gdb) = &mut gdb {
+		gdb.exited(exit_code);
+	}
 
-		(
-			Instruction::Op { op: OpOp::Add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
-			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XReg(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
-			"add; load -> load.add",
-		),
+	std::process::exit(exit_code);
+}
 
-		(
-			Instruction::Op { op: OpOp::Sh1add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
-			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XRegSh1(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
-			"sh1add; load -> load.sh1add",
-		),
+fn load_inst(memory: &Memory, pc: i64, fusions: &fusion::Config, statistics: &mut Statistics) -> Result<(Instruction, i64, i64), Exception> {
+	let (inst_a, inst_a_len) = decode_inst(memory, pc)?;
 
-		(
-			Instruction::Op { op: OpOp::Sh2add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
-			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XRegSh2(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
-			"sh2add; load -> load.sh2add",
-		),
+	// A fault while fetching the second instruction just means there is nothing to fuse with.
+	let result =
+		if let Ok((inst_b, inst_b_len)) = decode_inst(memory, pc.wrapping_add(inst_a_len)) {
+			fusion::fuse(inst_a, inst_a_len, inst_b, inst_b_len, fusions, &mut statistics.fusions)
+		}
+		else {
+			(inst_a, inst_a_len, 1)
+		};
+	Ok(result)
+}
 
-		(
-			Instruction::Op { op: OpOp::Sh3add, rd: rd_a, rs1: rs1_a, rs2: rs2_a },
-			Instruction::Load { op: op_b, rd: rd_b, base: MemoryBase::XReg(rs1_b), offset: MemoryOffset::Imm(0) },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b
-		=> (
-			Instruction::Load { op: op_b, rd: rd_a, base: MemoryBase::XRegSh3(rs1_a), offset: MemoryOffset::XReg(rs2_a) },
-			"sh3add; load -> load.sh3add",
-		),
+fn decode_inst(memory: &Memory, pc: i64) -> Result<(Instruction, i64), Exception> {
+	let raw = fetch_raw_inst(memory, pc)?;
+	Instruction::decode(raw).map_err(|()| Exception::IllegalInstruction { inst: raw })
+}
 
-		(
-			Instruction::Op { op: OpOp::Sub, rd: rd_a, rs1: XReg::X0, rs2: rs2_a },
-			Instruction::Op { op: OpOp::Max, rd: rd_b, rs1: rs1_b, rs2: rs2_b },
-		) if
-			rd_a == rd_b &&
-			rd_a == rs1_b &&
-			rs2_a == rs2_b
-		=> (
-			Instruction::Abs { rd: rd_a, rs: rs2_a },
-			"sub; max -> abs",
-		),
+fn fetch_raw_inst(memory: &Memory, pc: i64) -> Result<u32, Exception> {
+	let lo = u32::from(memory.fetch(pc)?);
+	if lo & 0b11 != 0b11 {
+		// Compressed
+		return Ok(lo);
+	}
 
-		(_, _) => return (inst_a, inst_a_len, 1),
-	};
-	*fusions.entry(entry).or_default() += 1;
-	(inst, inst_a_len + inst_b_len, 2)
+	let hi = u32::from(memory.fetch(pc.wrapping_add(2))?);
+	Ok(lo | (hi << 16))
 }
 
 #[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
@@ -279,12 +346,25 @@ enum LogLevel {
 
 #[derive(Default)]
 struct Statistics {
-	fusions: std::collections::BTreeMap<&'static str, usize>,
-	fu_utilization: std::collections::BTreeMap<&'static str, usize>,
+	fusions: fusion::Statistics,
+	fu_utilization: std::collections::BTreeMap<String, usize>,
 	num_ticks_where_instructions_retired: usize,
 	num_ticks_where_instructions_not_retired: usize,
 	jump_predictions: usize,
 	jump_mispredictions: usize,
+	jump_mispredictions_by_predictor: std::collections::BTreeMap<&'static str, usize>,
+	jump_mispredictions_by_pc: std::collections::BTreeMap<i64, usize>,
+	return_address_stack_hits: usize,
+	return_address_stack_misses: usize,
+	return_address_stack_overflows: usize,
+	speculative_loads: usize,
+	store_to_load_forwards: usize,
+	memory_ordering_violations: usize,
+	data_hazard_stall_cycles: usize,
+	pipeline_flushes: usize,
+	dual_issues: usize,
+	dual_issue_blockers: std::collections::BTreeMap<&'static str, usize>,
+	caches: std::collections::BTreeMap<&'static str, cache::Statistics>,
 }
 
 impl std::fmt::Display for Statistics {
@@ -292,20 +372,43 @@ impl std::fmt::Display for Statistics {
 		#[allow(clippy::cast_precision_loss)]
 		let fu_utilization: std::collections::BTreeMap<_, _> =
 			self.fu_utilization.iter()
-			.map(|(&key, &value)| (
-				key,
+			.map(|(key, &value)| (
+				&**key,
 				format!(
 					"{:.9}",
 					(value as f64) * 100. / ((self.num_ticks_where_instructions_retired + self.num_ticks_where_instructions_not_retired) as f64),
 				),
 			))
 			.collect();
-		writeln!(f, "fusions: {:#?}", self.fusions)?;
+		writeln!(f, "fusions: {:#?}", self.fusions.fused)?;
+		writeln!(f, "fusion opportunities: {:#?}", self.fusions.opportunities)?;
 		writeln!(f, "FU utilization: {fu_utilization:#?}")?;
 		writeln!(f, "num ticks where instructions retired: {}", self.num_ticks_where_instructions_retired)?;
 		writeln!(f, "num ticks where instructions not retired: {}", self.num_ticks_where_instructions_not_retired)?;
 		writeln!(f, "jump predictions: {}", self.jump_predictions)?;
 		writeln!(f, "jump mispredictions: {}", self.jump_mispredictions)?;
+		writeln!(f, "jump mispredictions by predictor: {:#?}", self.jump_mispredictions_by_predictor)?;
+		if self.jump_mispredictions_by_pc.is_empty() {
+			writeln!(f, "jump mispredictions by pc: {{}}")?;
+		}
+		else {
+			writeln!(f, "jump mispredictions by pc: {{")?;
+			for (pc, mispredictions) in &self.jump_mispredictions_by_pc {
+				writeln!(f, "    0x{pc:016x}: {mispredictions},")?;
+			}
+			writeln!(f, "}}")?;
+		}
+		writeln!(f, "return address stack hits: {}", self.return_address_stack_hits)?;
+		writeln!(f, "return address stack misses: {}", self.return_address_stack_misses)?;
+		writeln!(f, "return address stack overflows: {}", self.return_address_stack_overflows)?;
+		writeln!(f, "speculative loads: {}", self.speculative_loads)?;
+		writeln!(f, "store-to-load forwards: {}", self.store_to_load_forwards)?;
+		writeln!(f, "memory ordering violations: {}", self.memory_ordering_violations)?;
+		writeln!(f, "data hazard stall cycles: {}", self.data_hazard_stall_cycles)?;
+		writeln!(f, "pipeline flushes: {}", self.pipeline_flushes)?;
+		writeln!(f, "dual issues: {}", self.dual_issues)?;
+		writeln!(f, "dual issue blockers: {:#?}", self.dual_issue_blockers)?;
+		writeln!(f, "caches: {:#?}", self.caches)?;
 		Ok(())
 	}
 }
@@ -334,18 +437,51 @@ impl RegisterValue {
 enum Mode {
 	InOrder,
 	InOrderUcode,
-	OutOfOrder { max_retire_per_cycle: std::num::NonZero<usize> },
+	Pipelined(pipelined::Config),
+	DualIssue(dual_issue::Config),
+	OutOfOrder(Box<out_of_order::Config>),
 }
 
-fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::ffi::OsStr) -> (
-	Mode,
-	std::path::PathBuf,
-	std::path::PathBuf,
-) {
+struct Args {
+	mode: Mode,
+	program_path: std::path::PathBuf,
+	in_file_path: std::path::PathBuf,
+	supported_extensions: riscv::SupportedExtensions,
+	symbols_path: Option<std::path::PathBuf>,
+	user_mode: bool,
+	memory_map: MemoryMap,
+	misaligned: Misaligned,
+	level_io: Option<LevelIo>,
+	caches: Caches,
+	fusions: fusion::Config,
+	/// The port or Unix socket path to listen for GDB on.
+	gdb: Option<std::ffi::OsString>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::ffi::OsStr) -> Args {
 	let mut mode = None;
 	let mut out_of_order_max_retire_per_cycle = None;
+	let mut out_of_order_return_address_stack_depth = None;
+	let mut out_of_order_branch_predictor = None;
+	let mut out_of_order_branch_target_buffer_entries = None;
+	let mut out_of_order_config = vec![];
+	let mut pipeline_config = vec![];
+	let mut dual_issue_config = vec![];
 	let mut program_path = None;
 	let mut in_file_path = None;
+	let mut supported_extensions = riscv::SupportedExtensions::RV64I;
+	let mut symbols_path = None;
+	let mut user_mode = false;
+	let mut memory_map_path = None;
+	let mut regions = vec![];
+	let mut print_memory_map_ld = false;
+	let mut console_dump = true;
+	let mut misaligned = None;
+	let mut level_input_path = None;
+	let mut level_output_path = None;
+	let mut caches = vec![];
+	let mut fusions = vec![];
+	let mut gdb = None;
 
 	while let Some(opt) = args.next() {
 		match opt.to_str() {
@@ -364,6 +500,101 @@ fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::f
 				out_of_order_max_retire_per_cycle = Some(arg);
 			},
 
+			Some("--ooo-ras-depth") if out_of_order_return_address_stack_depth.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				out_of_order_return_address_stack_depth = Some(arg);
+			},
+
+			Some("--ooo-branch-predictor") if out_of_order_branch_predictor.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				out_of_order_branch_predictor = Some(arg);
+			},
+
+			Some("--ooo-btb-entries") if out_of_order_branch_target_buffer_entries.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				out_of_order_branch_target_buffer_entries = Some(arg);
+			},
+
+			Some("--ooo-config") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				out_of_order_config.push(arg);
+			},
+
+			Some("--pipeline-config") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				pipeline_config.push(arg);
+			},
+
+			Some("--dual-issue-config") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				dual_issue_config.push(arg);
+			},
+
+			Some("--symbols") if symbols_path.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				symbols_path = Some(arg.into());
+			},
+
+			Some("--user-mode") => user_mode = true,
+
+			Some("--memory-map") if memory_map_path.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				memory_map_path = Some(std::path::PathBuf::from(arg));
+			},
+
+			Some("--region") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				regions.push(arg);
+			},
+
+			Some("--print-memory-map-ld") => print_memory_map_ld = true,
+
+			Some("--no-console-dump") => console_dump = false,
+
+			Some("--misaligned") if misaligned.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				misaligned = Some(arg);
+			},
+
+			Some("--level-input") if level_input_path.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				level_input_path = Some(std::path::PathBuf::from(arg));
+			},
+
+			Some("--level-output") if level_output_path.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				level_output_path = Some(std::path::PathBuf::from(arg));
+			},
+
+			Some("--cache") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				caches.push(arg);
+			},
+
+			Some("--fusion") => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				fusions.push(arg);
+			},
+
+			Some("--gdb") if gdb.is_none() => {
+				let Some(arg) = args.next() else { write_usage_and_crash(argv0); };
+				gdb = Some(arg);
+			},
+
+			Some("-c" | "--compressed" | "--compressed=true") => supported_extensions |= riscv::SupportedExtensions::RVC,
+
+			Some("--compressed=false") => supported_extensions &= !riscv::SupportedExtensions::ZCB,
+
+			Some("--compressed=Zcb") => supported_extensions |= riscv::SupportedExtensions::ZCB,
+
+			Some("--zba") => supported_extensions |= riscv::SupportedExtensions::ZBA,
+
+			Some("--zbb") => supported_extensions |= riscv::SupportedExtensions::ZBB,
+
+			Some("--32") => supported_extensions &= !riscv::SupportedExtensions::RV64I,
+
+			Some("--64") => supported_extensions |= riscv::SupportedExtensions::RV64I,
+
 			Some("--") => {
 				program_path = args.next();
 				in_file_path = args.next();
@@ -380,21 +611,76 @@ fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::f
 
 	let None = args.next() else { write_usage_and_crash(argv0); };
 
-	let out_of_order_max_retire_per_cycle =
-		if let Some(out_of_order_max_retire_per_cycle) = out_of_order_max_retire_per_cycle {
-			let Some(out_of_order_max_retire_per_cycle) = out_of_order_max_retire_per_cycle.to_str() else { write_usage_and_crash(argv0); };
-			let Ok(out_of_order_max_retire_per_cycle) = out_of_order_max_retire_per_cycle.parse() else { write_usage_and_crash(argv0); };
-			let Some(out_of_order_max_retire_per_cycle) = std::num::NonZero::new(out_of_order_max_retire_per_cycle) else { write_usage_and_crash(argv0); };
-			out_of_order_max_retire_per_cycle
-		}
-		else {
-			std::num::NonZero::new(32).expect("hard-coded value is not 0")
-		};
+	let mut memory_map = load_memory_map(memory_map_path.as_deref(), &regions, argv0);
+	if !console_dump {
+		memory_map.console = None;
+	}
+	if print_memory_map_ld {
+		print!("{}", memory_map.linker_script());
+		std::process::exit(0);
+	}
+
+	let pipeline_config = load_pipeline_config(&pipeline_config, argv0);
+
+	let dual_issue_config = load_dual_issue_config(&dual_issue_config, argv0);
+
+	let mut out_of_order_config = load_out_of_order_config(&out_of_order_config, argv0);
+
+	if let Some(out_of_order_max_retire_per_cycle) = out_of_order_max_retire_per_cycle {
+		let Some(out_of_order_max_retire_per_cycle) = out_of_order_max_retire_per_cycle.to_str() else { write_usage_and_crash(argv0); };
+		let Ok(out_of_order_max_retire_per_cycle) = out_of_order_max_retire_per_cycle.parse() else { write_usage_and_crash(argv0); };
+		let Some(out_of_order_max_retire_per_cycle) = std::num::NonZero::new(out_of_order_max_retire_per_cycle) else { write_usage_and_crash(argv0); };
+		out_of_order_config.retire_width = out_of_order_max_retire_per_cycle;
+	}
+
+	if let Some(out_of_order_return_address_stack_depth) = out_of_order_return_address_stack_depth {
+		let Some(out_of_order_return_address_stack_depth) = out_of_order_return_address_stack_depth.to_str() else { write_usage_and_crash(argv0); };
+		let Ok(out_of_order_return_address_stack_depth) = out_of_order_return_address_stack_depth.parse() else { write_usage_and_crash(argv0); };
+		out_of_order_config.return_address_stack_depth = out_of_order_return_address_stack_depth;
+	}
+
+	if let Some(out_of_order_branch_predictor) = out_of_order_branch_predictor {
+		let Some(out_of_order_branch_predictor) = out_of_order_branch_predictor.to_str() else { write_usage_and_crash(argv0); };
+		let Ok(out_of_order_branch_predictor) = out_of_order_branch_predictor.parse() else { write_usage_and_crash(argv0); };
+		out_of_order_config.branch_predictor = out_of_order_branch_predictor;
+	}
+
+	if let Some(out_of_order_branch_target_buffer_entries) = out_of_order_branch_target_buffer_entries {
+		let Some(out_of_order_branch_target_buffer_entries) = out_of_order_branch_target_buffer_entries.to_str() else { write_usage_and_crash(argv0); };
+		let Ok(out_of_order_branch_target_buffer_entries) = out_of_order_branch_target_buffer_entries.parse() else { write_usage_and_crash(argv0); };
+		out_of_order_config.branch_target_buffer_entries = out_of_order_branch_target_buffer_entries;
+	}
+
+	let misaligned = match misaligned.as_ref().map(|arg| arg.to_str()) {
+		None | Some(Some("trap")) => Misaligned::Trap,
+		Some(Some("hardware")) => Misaligned::Hardware { extra_cycles: 1 },
+		Some(Some("split")) => Misaligned::Split,
+		Some(Some(arg)) if let Some(Ok(extra_cycles)) = arg.strip_prefix("hardware=").map(str::parse) => Misaligned::Hardware { extra_cycles },
+		_ => write_usage_and_crash(argv0),
+	};
+
+	let level_io =
+		(level_input_path.is_some() || level_output_path.is_some())
+		.then(|| {
+			let input = level_input_path.map_or_else(Vec::new, |path| load_level_script(&path));
+			let expected_output = level_output_path.map(|path| load_level_script(&path));
+			LevelIo::new(input, expected_output)
+		});
+
+	let caches = load_caches(&caches, argv0);
+
+	// A fused pair is executed as one instruction, so a debugger could neither step into it nor stop at its second instruction.
+	if gdb.is_some() {
+		fusions.push("all=false".into());
+	}
+	let fusions = load_fusions(&fusions, argv0);
 
 	let mode = match mode {
 		Some(arg) if arg.to_str() == Some("in-order") => Mode::InOrder,
 		Some(arg) if arg.to_str() == Some("in-order-ucode") => Mode::InOrderUcode,
-		Some(arg) if arg.to_str() == Some("out-of-order") => Mode::OutOfOrder { max_retire_per_cycle: out_of_order_max_retire_per_cycle },
+		Some(arg) if arg.to_str() == Some("pipelined") => Mode::Pipelined(pipeline_config),
+		Some(arg) if arg.to_str() == Some("dual-issue") => Mode::DualIssue(dual_issue_config),
+		Some(arg) if arg.to_str() == Some("out-of-order") => Mode::OutOfOrder(Box::new(out_of_order_config)),
 		_ => write_usage_and_crash(argv0),
 	};
 
@@ -402,7 +688,145 @@ fn parse_args(mut args: impl Iterator<Item = std::ffi::OsString>, argv0: &std::f
 
 	let Some(in_file_path) = in_file_path else { write_usage_and_crash(argv0); };
 
-	(mode, program_path.into(), in_file_path.into())
+	Args {
+		mode,
+		program_path: program_path.into(),
+		in_file_path: in_file_path.into(),
+		supported_extensions,
+		symbols_path,
+		user_mode,
+		memory_map,
+		misaligned,
+		level_io,
+		caches,
+		fusions,
+		gdb,
+	}
+}
+
+fn load_memory_map(path: Option<&std::path::Path>, regions: &[std::ffi::OsString], argv0: &std::ffi::OsStr) -> MemoryMap {
+	let mut memory_map =
+		if let Some(path) = path {
+			let memory_map = match std::fs::read_to_string(path) {
+				Ok(memory_map) => memory_map,
+				Err(err) => {
+					eprintln!("could not load {}:", path.display());
+					eprintln!("{err}");
+					std::process::exit(1);
+				},
+			};
+			match MemoryMap::parse_toml(&memory_map) {
+				Ok(memory_map) => memory_map,
+				Err(err) => {
+					eprintln!("could not load {}:", path.display());
+					eprintln!("{err}");
+					std::process::exit(1);
+				},
+			}
+		}
+		else {
+			Default::default()
+		};
+
+	for region in regions {
+		let Some(region) = region.to_str() else { write_usage_and_crash(argv0); };
+		if let Err(err) = memory_map.set_region(region) {
+			eprintln!("invalid region {region}:");
+			eprintln!("{err}");
+			std::process::exit(1);
+		}
+	}
+
+	memory_map
+}
+
+fn load_pipeline_config(args: &[std::ffi::OsString], argv0: &std::ffi::OsStr) -> pipelined::Config {
+	let mut config: pipelined::Config = Default::default();
+	for arg in args {
+		let Some(arg) = arg.to_str() else { write_usage_and_crash(argv0); };
+		if let Err(err) = config.set(arg) {
+			eprintln!("invalid pipeline config {arg}:");
+			eprintln!("{err}");
+			std::process::exit(1);
+		}
+	}
+	config
+}
+
+fn load_dual_issue_config(args: &[std::ffi::OsString], argv0: &std::ffi::OsStr) -> dual_issue::Config {
+	let mut config: dual_issue::Config = Default::default();
+	for arg in args {
+		let Some(arg) = arg.to_str() else { write_usage_and_crash(argv0); };
+		if let Err(err) = config.set(arg) {
+			eprintln!("invalid dual-issue config {arg}:");
+			eprintln!("{err}");
+			std::process::exit(1);
+		}
+	}
+	config
+}
+
+fn load_out_of_order_config(args: &[std::ffi::OsString], argv0: &std::ffi::OsStr) -> out_of_order::Config {
+	let mut config: out_of_order::Config = Default::default();
+	for arg in args {
+		let Some(arg) = arg.to_str() else { write_usage_and_crash(argv0); };
+		if let Err(err) = config.set(arg) {
+			eprintln!("invalid out-of-order config {arg}:");
+			eprintln!("{err}");
+			std::process::exit(1);
+		}
+	}
+	if let Err(err) = config.check() {
+		eprintln!("invalid out-of-order config:");
+		eprintln!("{err}");
+		std::process::exit(1);
+	}
+	config
+}
+
+fn load_caches(args: &[std::ffi::OsString], argv0: &std::ffi::OsStr) -> Caches {
+	let mut caches: Caches = Default::default();
+	for arg in args {
+		let Some(arg) = arg.to_str() else { write_usage_and_crash(argv0); };
+		if let Err(err) = caches.set(arg) {
+			eprintln!("invalid cache {arg}:");
+			eprintln!("{err}");
+			std::process::exit(1);
+		}
+	}
+	caches
+}
+
+fn load_fusions(args: &[std::ffi::OsString], argv0: &std::ffi::OsStr) -> fusion::Config {
+	let mut config: fusion::Config = Default::default();
+	for arg in args {
+		let Some(arg) = arg.to_str() else { write_usage_and_crash(argv0); };
+		if let Err(err) = config.set(arg) {
+			eprintln!("invalid fusion config {arg}:");
+			eprintln!("{err}");
+			std::process::exit(1);
+		}
+	}
+	config
+}
+
+fn load_level_script(path: &std::path::Path) -> Vec<u64> {
+	let script = match std::fs::read_to_string(path) {
+		Ok(script) => script,
+		Err(err) => {
+			eprintln!("could not load {}:", path.display());
+			eprintln!("{err}");
+			std::process::exit(1);
+		},
+	};
+	match LevelIo::parse_script(&script) {
+		Ok(script) => script,
+		Err(line_number) => {
+			eprintln!("could not load {}:", path.display());
+			eprintln!("line {line_number}: malformed value");
+			std::process::exit(1);
+		},
+	}
 }
 
 fn write_usage_and_crash(argv0: &std::ffi::OsStr) -> ! {
@@ -411,5 +835,5 @@ fn write_usage_and_crash(argv0: &std::ffi::OsStr) -> ! {
 }
 
 fn write_usage(mut w: impl std::io::Write, argv0: &std::ffi::OsStr) {
-	_ = writeln!(w, "Usage: {} [--ooo <max retire per cycle>] [ -- ] <program.bin> <in_file.S>", argv0.to_string_lossy());
+	_ = writeln!(w, "Usage: {} --mode [in-order|in-order-ucode|pipelined|dual-issue|out-of-order] [ --pipeline-config <key=value,...> ]... [ --dual-issue-config <key=value,...> ]... [ --ooo-config <key=value,...> ]... [ --ooo-max-retire-per-cycle <max retire per cycle> ] [ --ooo-ras-depth <return address stack depth> ] [ --ooo-branch-predictor [btfnt|always-taken|bimodal|gshare|tage] ] [ --ooo-btb-entries <branch target buffer entries> ] [ --32 | --64 ] [ -c | --compressed | --compressed=[true|false|Zcb] ] [ --zba ] [ --zbb ] [ --symbols <symbols.map> ] [ --user-mode ] [ --memory-map <memory_map.toml> ] [ --region <key=value,...> ]... [ --print-memory-map-ld ] [ --no-console-dump ] [ --misaligned [trap|hardware[=<extra cycles>]|split] ] [ --level-input <input script> ] [ --level-output <expected output script> ] [ --cache <key=value,...> ]... [ --fusion <rule=true|false,...> ]... [ --gdb <port | unix socket> ] [ -- ] <program.bin | program.S> <in_file.S>", argv0.to_string_lossy());
 }
@@ -1,10 +1,52 @@
+use crate::{
+	cache::{Access, Caches},
+	clint::Clint,
+	exception::Exception,
+	level_io::LevelIo,
+	memory_map::{Backing, Console, Device, MemoryMap, Permissions},
+	pages::Pages,
+	program::Segment,
+	uart::Uart,
+};
+
 pub(crate) struct Memory {
-	ram: Vec<u8>,
-	program: Vec<u8>,
-	in_file: Vec<u8>,
+	/// Sorted by base address.
+	regions: Vec<MappedRegion>,
+	console: Option<Console>,
+	misaligned: Misaligned,
+	/// The number of stores that have written to memory, by the program or anything else.
+	num_writes: u64,
+	caches: std::cell::RefCell<Caches>,
 }
 
+/// How loads and stores that are not naturally aligned are handled.
 #[derive(Clone, Copy, Debug)]
+pub(crate) enum Misaligned {
+	/// Raise an address-misaligned exception, like the `tc/sv/load_store32.sv` and `tc/sv/load_store64.sv` load-store units.
+	Trap,
+	/// Access all the bytes at once, taking the given number of extra cycles.
+	Hardware { extra_cycles: u8 },
+	/// Access each byte separately, like a trap handler that emulates the access would.
+	/// Each byte takes a cycle, and a fault on one byte happens after the lower bytes of a store have been written.
+	Split,
+}
+
+struct MappedRegion {
+	base: u64,
+	last: u64,
+	permissions: Permissions,
+	latency: u8,
+	storage: Storage,
+}
+
+enum Storage {
+	Memory(Pages),
+	Clint(Clint),
+	LevelIo(LevelIo),
+	Uart(Uart),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub(crate) enum LoadOp {
 	Byte,
 	ByteUnsigned,
@@ -25,83 +67,282 @@ pub(crate) enum StoreOp {
 
 impl Memory {
 	pub(crate) fn new(
-		program_path: impl AsRef<std::path::Path>,
+		memory_map: &MemoryMap,
+		misaligned: Misaligned,
+		segments: Vec<Segment>,
 		in_file_path: impl AsRef<std::path::Path>,
-	) -> Self {
-		let program = std::fs::read(program_path).unwrap();
-		let in_file = std::fs::read(in_file_path).unwrap();
-		Self {
-			ram: vec![],
-			program,
-			in_file,
+		level_io: LevelIo,
+		caches: Caches,
+	) -> Result<Self, Error> {
+		let mut level_io = Some(level_io);
+		let regions =
+			memory_map.regions.iter()
+			.map(|region| Ok(MappedRegion {
+				base: region.base,
+				last: region.last(),
+				permissions: region.permissions,
+				latency: region.latency,
+				storage: match &region.backing {
+					Backing::Zero => Storage::Memory(Default::default()),
+					Backing::File(path) => {
+						let path = path.as_deref().unwrap_or(in_file_path.as_ref());
+						let mut data = std::fs::read(path).map_err(|err| Error::Io(path.to_owned(), err))?;
+						data.truncate(usize::try_from(region.size).unwrap_or(usize::MAX));
+						let mut pages: Pages = Default::default();
+						pages.write(0, &data);
+						Storage::Memory(pages)
+					},
+					Backing::Device(Device::Clint) => Storage::Clint(Default::default()),
+					Backing::Device(Device::LevelIo) => Storage::LevelIo(level_io.take().unwrap_or_default()),
+					Backing::Device(Device::Uart) => Storage::Uart(Default::default()),
+				},
+			}))
+			.collect::<Result<_, _>>()?;
+		let mut result = Self {
+			regions,
+			console: memory_map.console,
+			misaligned,
+			num_writes: 0,
+			caches: caches.into(),
+		};
+
+		for Segment { address, data, size } in segments {
+			let len = usize::try_from(size).unwrap_or(usize::MAX);
+			let Some(MappedRegion { base, storage: Storage::Memory(pages), .. }) = result.region_mut(address, len) else {
+				return Err(Error::UnmappedSegment { address, size });
+			};
+			let offset = address - *base;
+			pages.write(offset, &data);
+			// The rest of the segment is not backed by the file. Only pages that the region's own backing has already
+			// written to need to be zeroed, so that a large .bss does not allocate pages until the program touches them.
+			pages.zero(offset + u64::try_from(data.len()).unwrap(), len - data.len());
+		}
+
+		Ok(result)
+	}
+
+	/// Reads a doubleword without faulting, for use by diagnostics.
+	///
+	/// Devices are not read, since reading them can have side effects.
+	pub(crate) fn peek(&self, address: i64) -> Option<i64> {
+		if !self.is_memory_backed(address, 8) {
+			return None;
+		}
+		LoadOp::DoubleWord.exec(self, address).ok()
+	}
+
+	/// Reads bytes for a debugger, regardless of the region's permissions.
+	///
+	/// Devices are not read and the caches are not accessed, so the program cannot tell that the bytes were read.
+	pub(crate) fn debug_read(&self, address: i64, buf: &mut [u8]) -> Option<()> {
+		if !self.is_memory_backed(address, buf.len()) {
+			return None;
 		}
+		self.load_raw(address.cast_unsigned(), buf, |_| true)
+	}
+
+	/// Writes bytes for a debugger, regardless of the region's permissions so that code can be patched.
+	///
+	/// Devices are not written and the caches are not accessed.
+	pub(crate) fn debug_write(&mut self, address: i64, buf: &[u8]) -> Option<()> {
+		let Some(MappedRegion { base, storage: Storage::Memory(pages), .. }) = self.region_mut(address.cast_unsigned(), buf.len()) else {
+			return None;
+		};
+		pages.write(address.cast_unsigned() - *base, buf);
+		self.num_writes += 1;
+		Some(())
+	}
+
+	/// Returns whether all of the `len` bytes starting at `address` are in a single region that is backed by memory,
+	/// so that loading them has no side effects.
+	pub(crate) fn is_memory_backed(&self, address: i64, len: usize) -> bool {
+		self.region(address.cast_unsigned(), len).is_some_and(|region| matches!(region.storage, Storage::Memory(_)))
+	}
+
+	/// Returns the number of bytes from `address` to the end of its region if the region is backed by memory and writable,
+	/// or 0 otherwise.
+	pub(crate) fn writable_len(&self, address: i64) -> u64 {
+		let address = address.cast_unsigned();
+		self.region(address, 1)
+			.filter(|region| region.permissions.write && matches!(region.storage, Storage::Memory(_)))
+			.map_or(0, |region| (region.last - address).saturating_add(1))
+	}
+
+	pub(crate) fn num_writes(&self) -> u64 {
+		self.num_writes
+	}
+
+	/// Returns the extra cycles taken by fetching the `len` bytes of instructions at the given address.
+	///
+	/// This accesses the caches, so it must only be called once per fetch.
+	pub(crate) fn fetch_latency(&self, address: i64, len: usize) -> u8 {
+		self.cache_latency(address, len, Access::Fetch)
+	}
+
+	pub(crate) fn cache_statistics(&self) -> std::collections::BTreeMap<&'static str, crate::cache::Statistics> {
+		self.caches.borrow().statistics()
+	}
+
+	/// Reads the halfword of an instruction at the given address, which must be in an executable region.
+	pub(crate) fn fetch(&self, address: i64) -> Result<u16, Exception> {
+		let mut data = [0_u8; 2];
+		address.cast_unsigned().is_multiple_of(2)
+			.then(|| self.load_raw(address.cast_unsigned(), &mut data, |permissions| permissions.execute))
+			.flatten()
+			.ok_or(Exception::InstructionAccessFault { address })?;
+		Ok(u16::from_le_bytes(data))
+	}
+
+
+	pub(crate) fn tick(&mut self, cycles: i64) {
+		for region in &mut self.regions {
+			if let Storage::Clint(clint) = &mut region.storage {
+				clint.tick(cycles);
+			}
+		}
+	}
+
+	/// Returns the CLINT's `mtime`, if a CLINT is mapped.
+	pub(crate) fn mtime(&self) -> Option<i64> {
+		self.regions.iter().find_map(|region| match &region.storage {
+			Storage::Clint(clint) => Some(clint.mtime().cast_signed()),
+			Storage::Memory(_) | Storage::LevelIo(_) | Storage::Uart(_) => None,
+		})
+	}
+
+	/// Returns the bits of `mip` that are driven by devices.
+	pub(crate) fn interrupts_pending(&self) -> i64 {
+		self.regions.iter()
+			.map(|region| match &region.storage {
+				Storage::Memory(_) | Storage::LevelIo(_) | Storage::Uart(_) => 0,
+				Storage::Clint(clint) => clint.interrupts_pending(),
+			})
+			.fold(0, |acc, pending| acc | pending)
+	}
+
+	/// Returns the exit status if a device has ended the program.
+	pub(crate) fn exit_status(&self) -> Option<i32> {
+		self.level_io().and_then(LevelIo::exit_status)
+	}
+
+	pub(crate) fn level_io(&self) -> Option<&LevelIo> {
+		self.regions.iter().find_map(|region| match &region.storage {
+			Storage::LevelIo(level_io) => Some(level_io),
+			Storage::Memory(_) | Storage::Clint(_) | Storage::Uart(_) => None,
+		})
 	}
 
 	pub(crate) fn dump_console(&self) {
-		println!("{}", Console(self.ram.get(0x400_000..).unwrap_or_default()));
+		let Some(Console { base, size }) = self.console else { return; };
+		let mut console = vec![0_u8; usize::try_from(size).unwrap()];
+		_ = self.load_raw(base, &mut console, |permissions| permissions.read);
+		// Don't print the part of the console that was never written to.
+		let len = console.iter().rposition(|&c| c != b'\0').map_or(0, |i| i + 1);
+		println!("{}", ConsoleDisplay(&console[..len]));
+	}
+
+	/// Returns the extra cycles taken by a load or store of `size` bytes to the given address.
+	///
+	/// This accesses the caches, so it must only be called once per load or store.
+	fn latency(&self, address: i64, size: usize, access: Access) -> u8 {
+		let latency = self.region(address.cast_unsigned(), 1).map_or(0, |region| region.latency);
+		let latency = latency.saturating_add(self.cache_latency(address, size, access));
+		match self.misaligned {
+			Misaligned::Hardware { extra_cycles } if !is_aligned(address, size) => latency.saturating_add(extra_cycles),
+
+			Misaligned::Split if !is_aligned(address, size) => {
+				let cycles = (u16::from(latency) + 1) * u16::try_from(size).unwrap() - 1;
+				cycles.try_into().unwrap_or(u8::MAX)
+			},
+
+			Misaligned::Trap | Misaligned::Hardware { .. } | Misaligned::Split => latency,
+		}
+	}
+
+	/// Returns the extra cycles taken by the caches for an access to the given address. Devices are not cached.
+	fn cache_latency(&self, address: i64, len: usize, access: Access) -> u8 {
+		if !self.is_memory_backed(address, len) {
+			return 0;
+		}
+		self.caches.borrow_mut().access(address.cast_unsigned(), len, access)
+	}
+
+	/// Returns the region that contains all of the `len` bytes starting at `address`.
+	fn region(&self, address: u64, len: usize) -> Option<&MappedRegion> {
+		let i = self.regions.partition_point(|region| region.base <= address).checked_sub(1)?;
+		let region = &self.regions[i];
+		region.contains(address, len).then_some(region)
+	}
+
+	fn region_mut(&mut self, address: u64, len: usize) -> Option<&mut MappedRegion> {
+		let i = self.regions.partition_point(|region| region.base <= address).checked_sub(1)?;
+		let region = &mut self.regions[i];
+		region.contains(address, len).then_some(region)
+	}
+
+	/// Fills `buf` from the given address, or returns `None` if the region does not have the required permission.
+	///
+	/// Accesses that are not contained in a single region fail.
+	fn load_raw(&self, address: u64, buf: &mut [u8], is_permitted: impl FnOnce(Permissions) -> bool) -> Option<()> {
+		let region = self.region(address, buf.len()).filter(|region| is_permitted(region.permissions))?;
+		let offset = address - region.base;
+		match &region.storage {
+			Storage::Memory(pages) => pages.read(offset, buf),
+			Storage::Clint(clint) => buf.copy_from_slice(clint.load(offset).get(..buf.len())?),
+			Storage::LevelIo(level_io) => level_io.load(offset, buf)?,
+			Storage::Uart(uart) => uart.load(offset, buf),
+		}
+		Some(())
+	}
+
+	/// Writes `buf` to the given address, or returns `None` if the region is not writable.
+	///
+	/// Accesses that are not contained in a single region fail.
+	fn store_raw(&mut self, address: u64, buf: &[u8]) -> Option<()> {
+		let region = self.region_mut(address, buf.len()).filter(|region| region.permissions.write)?;
+		let offset = address - region.base;
+		match &mut region.storage {
+			Storage::Memory(pages) => pages.write(offset, buf),
+			Storage::Clint(clint) => clint.store(offset, buf),
+			Storage::LevelIo(level_io) => level_io.store(offset, buf),
+			Storage::Uart(uart) => uart.store(offset, buf),
+		}
+		self.num_writes += 1;
+		Some(())
+	}
+}
+
+impl MappedRegion {
+	fn contains(&self, address: u64, len: usize) -> bool {
+		let Ok(len) = u64::try_from(len) else { return false; };
+		self.base <= address && address <= self.last && len.saturating_sub(1) <= self.last - address
 	}
 }
 
 impl LoadOp {
-	pub(crate) fn exec(self, memory: &Memory, address: i64) -> i64 {
-		let address = address.cast_unsigned();
+	pub(crate) fn exec(self, memory: &Memory, address: i64) -> Result<i64, Exception> {
+		let mut data = [0_u8; 8];
+		let is_permitted = |permissions: Permissions| permissions.read;
 
-		match self {
-			Self::Byte | Self::ByteUnsigned => (),
-			Self::HalfWord | Self::HalfWordUnsigned => assert!(address.is_multiple_of(2), "0x{address:016x} is not aligned for reading a halfword"),
-			Self::Word | Self::WordUnsigned => assert!(address.is_multiple_of(4), "0x{address:016x} is not aligned for reading a word"),
-			Self::DoubleWord => assert!(address.is_multiple_of(8), "0x{address:016x} is not aligned for reading a doubleword"),
-		}
-
-		let data = match address {
-			0xffff_ffff_ffe0_0000..=0xffff_ffff_ffef_ffff => {
-				// in_file
-				let address = usize::try_from(address - 0xffff_ffff_ffe0_0000).unwrap();
-				[
-					memory.in_file.get(address).copied().unwrap_or_default(),
-					memory.in_file.get(address + 1).copied().unwrap_or_default(),
-					memory.in_file.get(address + 2).copied().unwrap_or_default(),
-					memory.in_file.get(address + 3).copied().unwrap_or_default(),
-					memory.in_file.get(address + 4).copied().unwrap_or_default(),
-					memory.in_file.get(address + 5).copied().unwrap_or_default(),
-					memory.in_file.get(address + 6).copied().unwrap_or_default(),
-					memory.in_file.get(address + 7).copied().unwrap_or_default(),
-				]
-			},
+		match memory.misaligned {
+			Misaligned::Trap if !is_aligned(address, self.size()) => return Err(Exception::LoadAddressMisaligned { address }),
 
-			0x8000_0000_0000_0000..=0xffff_ffff_ffff_ffff => {
-				// program
-				let address = usize::try_from(address - 0x8000_0000_0000_0000).unwrap();
-				[
-					memory.program.get(address).copied().unwrap_or_default(),
-					memory.program.get(address + 1).copied().unwrap_or_default(),
-					memory.program.get(address + 2).copied().unwrap_or_default(),
-					memory.program.get(address + 3).copied().unwrap_or_default(),
-					memory.program.get(address + 4).copied().unwrap_or_default(),
-					memory.program.get(address + 5).copied().unwrap_or_default(),
-					memory.program.get(address + 6).copied().unwrap_or_default(),
-					memory.program.get(address + 7).copied().unwrap_or_default(),
-				]
-			},
+			Misaligned::Split if !is_aligned(address, self.size()) =>
+				for (address, b) in (0..).map(|i| address.wrapping_add(i)).zip(&mut data[..self.size()]) {
+					memory.load_raw(address.cast_unsigned(), std::slice::from_mut(b), is_permitted)
+						.ok_or(Exception::LoadAccessFault { address })?;
+				},
 
-			0x0000_0000_0000_0000..=0x0000_0000_0400_1fff => {
-				// ram
-				let address = usize::try_from(address).unwrap();
-				[
-					memory.ram.get(address).copied().unwrap_or_default(),
-					memory.ram.get(address + 1).copied().unwrap_or_default(),
-					memory.ram.get(address + 2).copied().unwrap_or_default(),
-					memory.ram.get(address + 3).copied().unwrap_or_default(),
-					memory.ram.get(address + 4).copied().unwrap_or_default(),
-					memory.ram.get(address + 5).copied().unwrap_or_default(),
-					memory.ram.get(address + 6).copied().unwrap_or_default(),
-					memory.ram.get(address + 7).copied().unwrap_or_default(),
-				]
-			},
+			Misaligned::Trap | Misaligned::Hardware { .. } | Misaligned::Split =>
+				memory.load_raw(address.cast_unsigned(), &mut data[..self.size()], is_permitted)
+					.ok_or(Exception::LoadAccessFault { address })?,
+		}
 
-			address => panic!("EFAULT: 0x{address:016x} is unmapped"),
-		};
+		Ok(self.extend(data))
+	}
 
+	/// Sign- or zero-extends the loaded bytes at the start of `data`.
+	pub(crate) fn extend(self, data: [u8; 8]) -> i64 {
 		match self {
 			Self::Byte => i8::from_le_bytes([data[0]]).into(),
 			Self::ByteUnsigned => u64::from(u8::from_le_bytes([data[0]])).cast_signed(),
@@ -112,6 +353,20 @@ impl LoadOp {
 			Self::DoubleWord => i64::from_le_bytes(data),
 		}
 	}
+
+	/// Returns the extra cycles taken by this load from the given address.
+	pub(crate) fn latency(self, memory: &Memory, address: i64) -> u8 {
+		memory.latency(address, self.size(), Access::Load)
+	}
+
+	pub(crate) fn size(self) -> usize {
+		match self {
+			Self::Byte | Self::ByteUnsigned => 1,
+			Self::HalfWord | Self::HalfWordUnsigned => 2,
+			Self::Word | Self::WordUnsigned => 4,
+			Self::DoubleWord => 8,
+		}
+	}
 }
 
 impl TryFrom<u8> for LoadOp {
@@ -132,49 +387,39 @@ impl TryFrom<u8> for LoadOp {
 }
 
 impl StoreOp {
-	pub(crate) fn exec(self, memory: &mut Memory, address: i64, value: i64) {
-		let address = address.cast_unsigned();
+	pub(crate) fn exec(self, memory: &mut Memory, address: i64, value: i64) -> Result<(), Exception> {
+		let value = value.to_le_bytes();
+		let value = &value[..self.size()];
 
-		match self {
-			Self::Byte => (),
-			Self::HalfWord => assert!(address.is_multiple_of(2), "0x{address:016x} is not aligned for writing a halfword"),
-			Self::Word => assert!(address.is_multiple_of(4), "0x{address:016x} is not aligned for writing a word"),
-			Self::DoubleWord => assert!(address.is_multiple_of(8), "0x{address:016x} is not aligned for writing a doubleword"),
-		}
+		match memory.misaligned {
+			Misaligned::Trap if !is_aligned(address, self.size()) => return Err(Exception::StoreAddressMisaligned { address }),
 
-		let data: &mut [u8; 8] = match address {
-			0xffff_ffff_ffe0_0000..=0xffff_ffff_ffef_ffff => {
-				// in_file
-				panic!("EFAULT: 0x{address:016x} is readonly");
-			},
+			Misaligned::Split if !is_aligned(address, self.size()) =>
+				for (address, b) in (0..).map(|i| address.wrapping_add(i)).zip(value) {
+					memory.store_raw(address.cast_unsigned(), std::slice::from_ref(b))
+						.ok_or(Exception::StoreAccessFault { address })?;
+				},
 
-			0x8000_0000_0000_0000..=0xffff_ffff_ffff_ffff => {
-				// program
-				panic!("EFAULT: 0x{address:016x} is readonly");
-			},
+			Misaligned::Trap | Misaligned::Hardware { .. } | Misaligned::Split =>
+				memory.store_raw(address.cast_unsigned(), value)
+					.ok_or(Exception::StoreAccessFault { address })?,
+		}
 
-			0x0000_0000_0000_0000..=0x0000_0000_0400_1fff => {
-				// ram
-				let address = usize::try_from(address).unwrap();
-				if let Some(raw) = memory.ram.get_mut(address..).and_then(|s| s.first_chunk_mut()) {
-					raw
-				}
-				else {
-					memory.ram.resize(address + 8, 0_u8);
-					memory.ram[address..].first_chunk_mut().unwrap()
-				}
-			},
+		Ok(())
+	}
 
-			address => panic!("EFAULT: 0x{address:016x} is unmapped"),
-		};
+	/// Returns the extra cycles taken by this store to the given address.
+	pub(crate) fn latency(self, memory: &Memory, address: i64) -> u8 {
+		memory.latency(address, self.size(), Access::Store)
+	}
 
-		let copy_len = match self {
+	pub(crate) fn size(self) -> usize {
+		match self {
 			Self::Byte => 1,
 			Self::HalfWord => 2,
 			Self::Word => 4,
 			Self::DoubleWord => 8,
-		};
-		data[..copy_len].copy_from_slice(&value.to_le_bytes()[..copy_len]);
+		}
 	}
 }
 
@@ -192,9 +437,29 @@ impl TryFrom<u8> for StoreOp {
 	}
 }
 
-struct Console<'a>(&'a [u8]);
+#[derive(Debug)]
+pub(crate) enum Error {
+	Io(std::path::PathBuf, std::io::Error),
+	UnmappedSegment { address: u64, size: u64 },
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Io(path, err) => write!(f, "could not read {}: {err}", path.display()),
+			Self::UnmappedSegment { address, size } =>
+				write!(f, "segment 0x{address:016x}..0x{:016x} is not in a region backed by memory", address.wrapping_add(*size)),
+		}
+	}
+}
+
+fn is_aligned(address: i64, size: usize) -> bool {
+	address.cast_unsigned().is_multiple_of(u64::try_from(size).unwrap())
+}
+
+struct ConsoleDisplay<'a>(&'a [u8]);
 
-impl std::fmt::Display for Console<'_> {
+impl std::fmt::Display for ConsoleDisplay<'_> {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		fn write_line(f: &mut std::fmt::Formatter<'_>, line: &[u8]) -> std::fmt::Result {
 			for &c in line {
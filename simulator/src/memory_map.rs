@@ -0,0 +1,743 @@
+// The memory map is described by a small subset of TOML:
+//
+//     [[region]]
+//     name = "ram"
+//     base = 0x0
+//     size = 0x400_2000
+//     permissions = "rwx"
+//     backing = "zero"
+//     latency = 0
+//
+//     [console]
+//     base = 0x40_0000
+//     size = 0x2000
+//
+// Only `[console]` and `[[region]]` tables, bare keys, integers and basic strings are supported.
+//
+// A region can also be given on the command line as the same keys and values separated by commas,
+// like `name=rom,base=0x1000_0000,size=0x1000,permissions=rx,backing=file,file=rom.bin,latency=2`.
+
+#[derive(Clone, Debug)]
+pub(crate) struct MemoryMap {
+	/// Sorted by base address, and do not overlap.
+	pub(crate) regions: Vec<Region>,
+	/// The range of memory that is printed as the console when the program exits.
+	pub(crate) console: Option<Console>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Region {
+	pub(crate) name: String,
+	pub(crate) base: u64,
+	pub(crate) size: u64,
+	pub(crate) permissions: Permissions,
+	pub(crate) backing: Backing,
+	/// Extra cycles taken by a load or store to this region.
+	pub(crate) latency: u8,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Permissions {
+	pub(crate) read: bool,
+	pub(crate) write: bool,
+	pub(crate) execute: bool,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Backing {
+	Zero,
+	/// `None` means the `in_file` given on the command line.
+	File(Option<std::path::PathBuf>),
+	Device(Device),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Device {
+	Clint,
+	LevelIo,
+	Uart,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Console {
+	pub(crate) base: u64,
+	pub(crate) size: u64,
+}
+
+impl MemoryMap {
+	pub(crate) fn parse_toml(s: &str) -> Result<Self, Error> {
+		enum Table {
+			Region,
+			Console,
+		}
+
+		let mut tables: Vec<(usize, Table, Vec<(String, Value)>)> = vec![];
+
+		for (line_number, line) in (1..).zip(s.lines()) {
+			let line = strip_comment(line).trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			match line {
+				"[[region]]" => tables.push((line_number, Table::Region, vec![])),
+
+				"[console]" if !tables.iter().any(|(_, table, _)| matches!(table, Table::Console)) =>
+					tables.push((line_number, Table::Console, vec![])),
+
+				line => {
+					let Some((key, value)) = line.split_once('=') else { return Err(Error::Syntax { line_number }); };
+					let key = key.trim();
+					if key.is_empty() || !key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-') {
+						return Err(Error::Syntax { line_number });
+					}
+					let value = Value::parse_toml(value.trim()).ok_or(Error::Syntax { line_number })?;
+					let Some((_, _, pairs)) = tables.last_mut() else { return Err(Error::Syntax { line_number }); };
+					if pairs.iter().any(|(other, _)| other == key) {
+						return Err(Error::DuplicateKey { key: key.to_owned() }.at_line(line_number));
+					}
+					pairs.push((key.to_owned(), value));
+				},
+			}
+		}
+
+		let mut regions = vec![];
+		let mut console = None;
+		for (line_number, table, pairs) in tables {
+			match table {
+				Table::Region => regions.push(Region::from_pairs(pairs).map_err(|err| err.at_line(line_number))?),
+				Table::Console => console = Some(Console::from_pairs(pairs).map_err(|err| err.at_line(line_number))?),
+			}
+		}
+
+		Self::new(regions, console)
+	}
+
+	/// Adds a region given on the command line, or replaces the region with the same name.
+	pub(crate) fn set_region(&mut self, arg: &str) -> Result<(), Error> {
+		let mut pairs = vec![];
+		for pair in arg.split(',') {
+			let Some((key, value)) = pair.split_once('=') else { return Err(Error::InvalidRegion { arg: arg.to_owned() }); };
+			if pairs.iter().any(|(other, _)| other == key) {
+				return Err(Error::DuplicateKey { key: key.to_owned() });
+			}
+			pairs.push((key.to_owned(), Value::parse_unquoted(value)));
+		}
+		let region = Region::from_pairs(pairs)?;
+
+		let mut regions: Vec<_> = self.regions.drain(..).filter(|other| other.name != region.name).collect();
+		regions.push(region);
+		*self = Self::new(regions, self.console)?;
+		Ok(())
+	}
+
+	pub(crate) fn region(&self, name: &str) -> Option<&Region> {
+		self.regions.iter().find(|region| region.name == name)
+	}
+
+	/// Returns a linker script fragment that declares the regions as `MEMORY`, and `_<NAME>_PTR` and `_<NAME>_END_PTR` symbols
+	/// for every region and the console.
+	pub(crate) fn linker_script(&self) -> impl std::fmt::Display {
+		LinkerScript(self)
+	}
+
+	fn new(mut regions: Vec<Region>, console: Option<Console>) -> Result<Self, Error> {
+		regions.sort_by_key(|region| region.base);
+
+		for (i, region) in regions.iter().enumerate() {
+			if region.name.is_empty() || !region.name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+				return Err(Error::InvalidValue { key: "name".to_owned() });
+			}
+
+			if region.size == 0 || !region.base.is_multiple_of(8) || !region.size.is_multiple_of(8) {
+				return Err(Error::Misaligned { name: region.name.clone() });
+			}
+
+			if region.base.checked_add(region.size - 1).is_none() {
+				return Err(Error::InvalidValue { key: "size".to_owned() });
+			}
+
+			if let Some(other) = regions[..i].iter().find(|other| other.name == region.name) {
+				return Err(Error::DuplicateName { name: other.name.clone() });
+			}
+
+			if let Some(next) = regions.get(i + 1) && region.last() >= next.base {
+				return Err(Error::Overlap { a: region.name.clone(), b: next.name.clone() });
+			}
+		}
+
+		if let Some(Console { base, size }) = console {
+			let is_mapped =
+				size > 0 &&
+				base.checked_add(size - 1).is_some_and(|last|
+					regions.iter().any(|region|
+						!matches!(region.backing, Backing::Device(_)) &&
+						region.permissions.read &&
+						region.base <= base &&
+						last <= region.last()));
+			if !is_mapped {
+				return Err(Error::UnmappedConsole);
+			}
+		}
+
+		Ok(Self { regions, console })
+	}
+}
+
+impl Default for MemoryMap {
+	fn default() -> Self {
+		Self::new(
+			vec![
+				Region {
+					name: "ram".to_owned(),
+					base: 0x0000_0000_0000_0000,
+					size: 0x0000_0000_0400_2000,
+					permissions: Permissions { read: true, write: true, execute: true },
+					backing: Backing::Zero,
+					latency: 0,
+				},
+				Region {
+					name: "program".to_owned(),
+					base: 0x8000_0000_0000_0000,
+					size: 0x7fff_ffff_ffe0_0000,
+					permissions: Permissions { read: true, write: false, execute: true },
+					backing: Backing::Zero,
+					latency: 0,
+				},
+				Region {
+					name: "in_file".to_owned(),
+					base: 0xffff_ffff_ffe0_0000,
+					size: 0x0000_0000_0010_0000,
+					permissions: Permissions { read: true, write: false, execute: false },
+					backing: Backing::File(None),
+					latency: 0,
+				},
+				Region {
+					name: "clint".to_owned(),
+					base: 0xffff_ffff_fff0_0000,
+					size: 0x0000_0000_0001_0000,
+					permissions: Permissions { read: true, write: true, execute: false },
+					backing: Backing::Device(Device::Clint),
+					latency: 0,
+				},
+				Region {
+					name: "uart".to_owned(),
+					base: 0xffff_ffff_fff1_0000,
+					size: 0x0000_0000_0000_1000,
+					permissions: Permissions { read: true, write: true, execute: false },
+					backing: Backing::Device(Device::Uart),
+					latency: 0,
+				},
+				Region {
+					name: "level_io".to_owned(),
+					base: 0xffff_ffff_ffff_fff8,
+					size: 0x0000_0000_0000_0008,
+					permissions: Permissions { read: true, write: true, execute: false },
+					backing: Backing::Device(Device::LevelIo),
+					latency: 0,
+				},
+			],
+			Some(Console { base: 0x0000_0000_0040_0000, size: 0x2000 }),
+		)
+		.expect("hard-coded memory map is valid")
+	}
+}
+
+impl Region {
+	pub(crate) fn last(&self) -> u64 {
+		self.base + (self.size - 1)
+	}
+
+	pub(crate) fn contains(&self, address: u64) -> bool {
+		(self.base..=self.last()).contains(&address)
+	}
+
+	fn from_pairs(pairs: impl IntoIterator<Item = (String, Value)>) -> Result<Self, Error> {
+		let mut name = None;
+		let mut base = None;
+		let mut size = None;
+		let mut permissions = None;
+		let mut backing = None;
+		let mut file = None;
+		let mut device = None;
+		let mut latency = 0;
+
+		for (key, value) in pairs {
+			match (&*key, value) {
+				("name", Value::String(value)) => name = Some(value),
+				("base", Value::Integer(value)) => base = Some(value),
+				("size", Value::Integer(value)) => size = Some(value),
+				("permissions", Value::String(value)) =>
+					permissions = Some(value.parse().map_err(|()| Error::InvalidValue { key })?),
+				("backing", Value::String(value)) => backing = Some(value),
+				("file", Value::String(value)) => file = Some(value.into()),
+				("device", Value::String(value)) => device = Some(match &*value {
+					"clint" => Device::Clint,
+					"level_io" => Device::LevelIo,
+					"uart" => Device::Uart,
+					_ => return Err(Error::InvalidValue { key }),
+				}),
+				("latency", Value::Integer(value)) => latency = value.try_into().map_err(|_| Error::InvalidValue { key })?,
+				("name" | "base" | "size" | "permissions" | "backing" | "file" | "device" | "latency", _) => return Err(Error::InvalidValue { key }),
+				_ => return Err(Error::UnknownKey { key }),
+			}
+		}
+
+		let backing = match (backing.as_deref(), file, device) {
+			(None | Some("zero"), None, None) => Backing::Zero,
+			(Some("file"), file, None) => Backing::File(file),
+			(Some("device"), None, Some(device)) => Backing::Device(device),
+			(Some("device"), None, None) => return Err(Error::MissingKey { key: "device" }),
+			_ => return Err(Error::InvalidValue { key: "backing".to_owned() }),
+		};
+
+		Ok(Self {
+			name: name.ok_or(Error::MissingKey { key: "name" })?,
+			base: base.ok_or(Error::MissingKey { key: "base" })?,
+			size: size.ok_or(Error::MissingKey { key: "size" })?,
+			permissions: permissions.ok_or(Error::MissingKey { key: "permissions" })?,
+			backing,
+			latency,
+		})
+	}
+}
+
+impl std::str::FromStr for Permissions {
+	type Err = ();
+
+	/// Parses `rwx`, with `-` or nothing in place of permissions that are not granted.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut result = Self { read: false, write: false, execute: false };
+		let mut rest = s.as_bytes();
+		for (c, permission) in [(b'r', &mut result.read), (b'w', &mut result.write), (b'x', &mut result.execute)] {
+			match rest {
+				[first, rest_ @ ..] if *first == c => { *permission = true; rest = rest_; },
+				[b'-', rest_ @ ..] => rest = rest_,
+				_ => (),
+			}
+		}
+		if rest.is_empty() { Ok(result) } else { Err(()) }
+	}
+}
+
+impl std::fmt::Display for Permissions {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.read { f.write_str("r")?; }
+		if self.write { f.write_str("w")?; }
+		if self.execute { f.write_str("x")?; }
+		Ok(())
+	}
+}
+
+impl Console {
+	fn from_pairs(pairs: impl IntoIterator<Item = (String, Value)>) -> Result<Self, Error> {
+		let mut base = None;
+		let mut size = None;
+
+		for (key, value) in pairs {
+			match (&*key, value) {
+				("base", Value::Integer(value)) => base = Some(value),
+				("size", Value::Integer(value)) => size = Some(value),
+				("base" | "size", _) => return Err(Error::InvalidValue { key }),
+				_ => return Err(Error::UnknownKey { key }),
+			}
+		}
+
+		Ok(Self {
+			base: base.ok_or(Error::MissingKey { key: "base" })?,
+			size: size.ok_or(Error::MissingKey { key: "size" })?,
+		})
+	}
+}
+
+enum Value {
+	Integer(u64),
+	String(String),
+}
+
+impl Value {
+	fn parse_toml(s: &str) -> Option<Self> {
+		if let Some(s) = s.strip_prefix('"') {
+			let mut result = String::new();
+			let mut chars = s.chars();
+			loop {
+				match chars.next()? {
+					'"' => break,
+					'\\' => result.push(match chars.next()? {
+						'\\' => '\\',
+						'"' => '"',
+						'n' => '\n',
+						't' => '\t',
+						_ => return None,
+					}),
+					c => result.push(c),
+				}
+			}
+			chars.as_str().is_empty().then_some(Self::String(result))
+		}
+		else {
+			parse_integer(s).map(Self::Integer)
+		}
+	}
+
+	fn parse_unquoted(s: &str) -> Self {
+		parse_integer(s).map_or_else(|| Self::String(s.to_owned()), Self::Integer)
+	}
+}
+
+pub(crate) fn parse_integer(s: &str) -> Option<u64> {
+	let (s, radix) =
+		if let Some(s) = s.strip_prefix("0x") { (s, 16) }
+		else if let Some(s) = s.strip_prefix("0o") { (s, 8) }
+		else if let Some(s) = s.strip_prefix("0b") { (s, 2) }
+		else { (s, 10) };
+	if s.starts_with('_') || s.ends_with('_') || s.contains("__") {
+		return None;
+	}
+	let s = s.replace('_', "");
+	if s.starts_with('+') {
+		return None;
+	}
+	u64::from_str_radix(&s, radix).ok()
+}
+
+fn strip_comment(line: &str) -> &str {
+	let mut in_string = false;
+	let mut escaped = false;
+	for (i, c) in line.char_indices() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if in_string => escaped = true,
+			'"' => in_string = !in_string,
+			'#' if !in_string => return &line[..i],
+			_ => (),
+		}
+	}
+	line
+}
+
+struct LinkerScript<'a>(&'a MemoryMap);
+
+impl std::fmt::Display for LinkerScript<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		writeln!(f, "MEMORY {{")?;
+		for region in &self.0.regions {
+			write!(f, "\t{}", region.name)?;
+			// Devices have no attributes, so that the linker does not place orphan sections in them.
+			if !matches!(region.backing, Backing::Device(_)) {
+				write!(f, " ({})", region.permissions)?;
+			}
+			writeln!(f, " : ORIGIN = 0x{:016x}, LENGTH = 0x{:016x}", region.base, region.size)?;
+		}
+		writeln!(f, "}}")?;
+
+		let symbols =
+			self.0.regions.iter().map(|region| (&*region.name, region.base, region.size))
+			.chain(self.0.console.map(|Console { base, size }| ("console", base, size)));
+		for (name, base, size) in symbols {
+			let name = name.to_ascii_uppercase();
+			writeln!(f)?;
+			writeln!(f, "_{name}_PTR = 0x{base:016x};")?;
+			writeln!(f, "_{name}_END_PTR = _{name}_PTR + 0x{size:016x};")?;
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Debug)]
+pub(crate) enum Error {
+	DuplicateKey { key: String },
+	DuplicateName { name: String },
+	InvalidRegion { arg: String },
+	InvalidValue { key: String },
+	Line { line_number: usize, inner: Box<Error> },
+	Misaligned { name: String },
+	MissingKey { key: &'static str },
+	Overlap { a: String, b: String },
+	Syntax { line_number: usize },
+	UnknownKey { key: String },
+	UnmappedConsole,
+}
+
+impl Error {
+	fn at_line(self, line_number: usize) -> Self {
+		match self {
+			Self::Line { .. } | Self::Syntax { .. } => self,
+			inner => Self::Line { line_number, inner: Box::new(inner) },
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::DuplicateKey { key } => write!(f, "{key} is specified more than once"),
+			Self::DuplicateName { name } => write!(f, "there is more than one region named {name}"),
+			Self::InvalidRegion { arg } => write!(f, "{arg:?} is not a list of key=value pairs"),
+			Self::InvalidValue { key } => write!(f, "{key} has an invalid value"),
+			Self::Line { line_number, inner } => write!(f, "line {line_number}: {inner}"),
+			Self::Misaligned { name } => write!(f, "region {name} is empty or its base and size are not multiples of 8"),
+			Self::MissingKey { key } => write!(f, "{key} is required"),
+			Self::Overlap { a, b } => write!(f, "regions {a} and {b} overlap"),
+			Self::Syntax { line_number } => write!(f, "line {line_number}: malformed line"),
+			Self::UnknownKey { key } => write!(f, "unknown key {key}"),
+			Self::UnmappedConsole => f.write_str("console is not inside a readable region that is backed by memory"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Backing, MemoryMap, Permissions};
+
+	fn summarize(memory_map: &MemoryMap) -> Vec<String> {
+		memory_map.regions.iter()
+			.map(|region| format!(
+				"{} 0x{:x} 0x{:x} {} {} {}",
+				region.name,
+				region.base,
+				region.size,
+				region.permissions,
+				match &region.backing {
+					Backing::Zero => "zero".to_owned(),
+					Backing::File(None) => "in_file".to_owned(),
+					Backing::File(Some(path)) => format!("file={}", path.display()),
+					Backing::Device(device) => format!("{device:?}"),
+				},
+				region.latency,
+			))
+			.chain(memory_map.console.map(|console| format!("console 0x{:x} 0x{:x}", console.base, console.size)))
+			.collect()
+	}
+
+	#[test]
+	fn parse_toml() {
+		static TESTS: &[(&str, Result<&[&str], &str>)] = &[
+			(r#"
+				# Comment
+				[[region]]
+				name = "ram" # Comment
+				base = 0x0
+				size = 0x400_2000
+				permissions = "rwx"
+
+				[[region]]
+				name = "rom"
+				base = 0x1000_0000
+				size = 4096
+				permissions = "r-x"
+				backing = "file"
+				file = "rom#1.bin"
+				latency = 2
+
+				[[region]]
+				name = "clint"
+				base = 0o10_000_000_000
+				size = 0b1_0000_0000_0000_0000
+				permissions = "rw"
+				backing = "device"
+				device = "clint"
+
+				[[region]]
+				name = "in_file"
+				base = 0x2000_0000
+				size = 0x10
+				permissions = "r"
+				backing = "file"
+
+				[console]
+				base = 0x40_0000
+				size = 0x2000
+			"#, Ok(&[
+				"ram 0x0 0x4002000 rwx zero 0",
+				"rom 0x10000000 0x1000 rx file=rom#1.bin 2",
+				"in_file 0x20000000 0x10 r in_file 0",
+				"clint 0x40000000 0x10000 rw Clint 0",
+				"console 0x400000 0x2000",
+			])),
+
+			// The regions are sorted by base address
+			(r#"
+				[[region]]
+				name = "b"
+				base = 0x100
+				size = 0x100
+				permissions = "r"
+				[[region]]
+				name = "a"
+				base = 0x0
+				size = 0x100
+				permissions = "w"
+			"#, Ok(&[
+				"a 0x0 0x100 w zero 0",
+				"b 0x100 0x100 r zero 0",
+			])),
+
+			("", Ok(&[])),
+
+			("name = \"ram\"", Err("line 1: malformed line")),
+			("[[region]]\nname ram", Err("line 2: malformed line")),
+			("[[region]]\nname = \"ram", Err("line 2: malformed line")),
+			("[[region]]\nname = \"ram\" garbage", Err("line 2: malformed line")),
+			("[[region]]\nname = \"\\q\"", Err("line 2: malformed line")),
+			("[[region]]\nsize = 0x_1", Err("line 2: malformed line")),
+			("[[region]]\nsize = +1", Err("line 2: malformed line")),
+			("[regions]", Err("line 1: malformed line")),
+			("[console]\nbase = 0\nsize = 8\n[console]", Err("line 4: malformed line")),
+
+			("[[region]]\nname = \"ram\"\nname = \"rom\"", Err("line 3: name is specified more than once")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\ncolor = \"red\"", Err("line 1: unknown key color")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8", Err("line 1: permissions is required")),
+			("[[region]]\nname = \"ram\"\nbase = \"0\"\nsize = 8\npermissions = \"rwx\"", Err("line 1: base has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwz\"", Err("line 1: permissions has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"xr\"", Err("line 1: permissions has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\nlatency = 256", Err("line 1: latency has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\nbacking = \"flash\"", Err("line 1: backing has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\nfile = \"a.bin\"", Err("line 1: backing has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\nbacking = \"device\"", Err("line 1: device is required")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\nbacking = \"device\"\ndevice = \"gpu\"", Err("line 1: device has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\nbacking = \"zero\"\ndevice = \"uart\"", Err("line 1: backing has an invalid value")),
+
+			("[[region]]\nname = \"r-1\"\nbase = 0\nsize = 8\npermissions = \"rwx\"", Err("name has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 4\nsize = 8\npermissions = \"rwx\"", Err("region ram is empty or its base and size are not multiples of 8")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 0\npermissions = \"rwx\"", Err("region ram is empty or its base and size are not multiples of 8")),
+			("[[region]]\nname = \"ram\"\nbase = 0xffff_ffff_ffff_fff8\nsize = 0x10\npermissions = \"rwx\"", Err("size has an invalid value")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 8\npermissions = \"rwx\"\n[[region]]\nname = \"ram\"\nbase = 8\nsize = 8\npermissions = \"rwx\"", Err("there is more than one region named ram")),
+			("[[region]]\nname = \"a\"\nbase = 0\nsize = 0x10\npermissions = \"rwx\"\n[[region]]\nname = \"b\"\nbase = 8\nsize = 8\npermissions = \"rwx\"", Err("regions a and b overlap")),
+
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 0x10\npermissions = \"rw\"\n[console]\nbase = 8\nsize = 8", Ok(&["ram 0x0 0x10 rw zero 0", "console 0x8 0x8"])),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 0x10\npermissions = \"rw\"\n[console]\nbase = 8\nsize = 0x10", Err("console is not inside a readable region that is backed by memory")),
+			("[[region]]\nname = \"ram\"\nbase = 0\nsize = 0x10\npermissions = \"w\"\n[console]\nbase = 0\nsize = 8", Err("console is not inside a readable region that is backed by memory")),
+			("[[region]]\nname = \"uart\"\nbase = 0\nsize = 0x10\npermissions = \"rw\"\nbacking = \"device\"\ndevice = \"uart\"\n[console]\nbase = 0\nsize = 8", Err("console is not inside a readable region that is backed by memory")),
+			("[console]\nbase = 0\nsize = 8\ncolor = 1", Err("line 1: unknown key color")),
+		];
+
+		for &(input, expected) in TESTS {
+			let actual = MemoryMap::parse_toml(input).map(|memory_map| summarize(&memory_map)).map_err(|err| err.to_string());
+			let expected = expected.map(|expected| expected.iter().map(|&s| s.to_owned()).collect::<Vec<_>>()).map_err(ToOwned::to_owned);
+			assert_eq!(actual, expected, "{input}");
+		}
+	}
+
+	#[test]
+	fn set_region() {
+		static TESTS: &[(&str, Result<&[&str], &str>)] = &[
+			// Replaces the region with the same name
+			("name=ram,base=0,size=0x1000,permissions=rw,latency=3", Ok(&[
+				"ram 0x0 0x1000 rw zero 3",
+				"rom 0x10000 0x100 rx in_file 0",
+				"console 0x0 0x8",
+			])),
+
+			("name=uart,base=0x2000,size=0x100,permissions=rw,backing=device,device=uart", Ok(&[
+				"ram 0x0 0x100 rwx zero 0",
+				"uart 0x2000 0x100 rw Uart 0",
+				"rom 0x10000 0x100 rx in_file 0",
+				"console 0x0 0x8",
+			])),
+
+			("name=flash,base=0x1000,size=0x100,permissions=rx,backing=file,file=flash.bin", Ok(&[
+				"ram 0x0 0x100 rwx zero 0",
+				"flash 0x1000 0x100 rx file=flash.bin 0",
+				"rom 0x10000 0x100 rx in_file 0",
+				"console 0x0 0x8",
+			])),
+
+			("name=ram", Err("base is required")),
+			("name=ram,base", Err("\"name=ram,base\" is not a list of key=value pairs")),
+			("name=ram,name=rom", Err("name is specified more than once")),
+			("name=a,base=0x10000,size=8,permissions=r", Err("regions rom and a overlap")),
+			// The console must stay mapped
+			("name=ram,base=0,size=8,permissions=w", Err("console is not inside a readable region that is backed by memory")),
+		];
+
+		let memory_map = MemoryMap::parse_toml(r#"
+			[[region]]
+			name = "ram"
+			base = 0
+			size = 0x100
+			permissions = "rwx"
+
+			[[region]]
+			name = "rom"
+			base = 0x10000
+			size = 0x100
+			permissions = "rx"
+			backing = "file"
+
+			[console]
+			base = 0
+			size = 8
+		"#).unwrap();
+
+		for &(arg, expected) in TESTS {
+			let mut memory_map = memory_map.clone();
+			let actual = memory_map.set_region(arg).map(|()| summarize(&memory_map)).map_err(|err| err.to_string());
+			let expected = expected.map(|expected| expected.iter().map(|&s| s.to_owned()).collect::<Vec<_>>()).map_err(ToOwned::to_owned);
+			assert_eq!(actual, expected, "{arg}");
+		}
+	}
+
+	#[test]
+	fn permissions() {
+		static TESTS: &[(&str, Option<(bool, bool, bool)>)] = &[
+			("", Some((false, false, false))),
+			("---", Some((false, false, false))),
+			("r", Some((true, false, false))),
+			("r-x", Some((true, false, true))),
+			("rx", Some((true, false, true))),
+			("-w-", Some((false, true, false))),
+			("rwx", Some((true, true, true))),
+			("xr", None),
+			("rwxr", None),
+			("RWX", None),
+		];
+		for &(input, expected) in TESTS {
+			let actual = input.parse::<Permissions>().ok().map(|Permissions { read, write, execute }| (read, write, execute));
+			assert_eq!(actual, expected, "{input:?}");
+		}
+	}
+
+	#[test]
+	fn linker_script() {
+		let memory_map = MemoryMap::parse_toml(r#"
+			[[region]]
+			name = "ram"
+			base = 0
+			size = 0x1000
+			permissions = "rwx"
+
+			[[region]]
+			name = "uart"
+			base = 0x1_0000
+			size = 0x100
+			permissions = "rw"
+			backing = "device"
+			device = "uart"
+
+			[console]
+			base = 0x800
+			size = 0x100
+		"#).unwrap();
+
+		assert_eq!(memory_map.linker_script().to_string(), "\
+MEMORY {
+	ram (rwx) : ORIGIN = 0x0000000000000000, LENGTH = 0x0000000000001000
+	uart : ORIGIN = 0x0000000000010000, LENGTH = 0x0000000000000100
+}
+
+_RAM_PTR = 0x0000000000000000;
+_RAM_END_PTR = _RAM_PTR + 0x0000000000001000;
+
+_UART_PTR = 0x0000000000010000;
+_UART_END_PTR = _UART_PTR + 0x0000000000000100;
+
+_CONSOLE_PTR = 0x0000000000000800;
+_CONSOLE_END_PTR = _CONSOLE_PTR + 0x0000000000000100;
+");
+
+		// The default memory map is what `make test-memory-map` checks the freestanding binary's linker script against.
+		assert!(MemoryMap::default().linker_script().to_string().contains("\tprogram (rx) : ORIGIN = 0x8000000000000000, LENGTH = 0x7fffffffffe00000\n"));
+	}
+}
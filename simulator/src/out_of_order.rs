@@ -1,40 +1,258 @@
 use crate::{
+	branch_predictor::{self, BranchPredictor, BranchTargetBuffer},
+	config,
 	csrs::Csrs,
+	divider,
+	exception::Exception,
+	fetch_buffer::FetchBuffer,
+	fusion,
+	gdb,
+	htif::Htif,
 	instruction::Instruction,
 	memory::Memory,
 	multiplier::{self, State},
+	return_address_stack::{self, ReturnAddressStack},
+	symbols::Symbols,
+	syscalls::{SyscallResult, Syscalls},
 	tag::{Tag, TagAllocator},
-	ucode::{Ucode, BinaryOp, MulOp, UnaryOp},
-	x_regs::XRegs,
+	ucode::{Ucode, BinaryOp, DivOp, MulOp, UnaryOp},
+	x_regs::{XReg, XRegs},
 	LogLevel,
 	RegisterValue,
 	Statistics,
-	load_inst,
 };
 
-const ROB_MAX_LEN: usize = 32;
+/// The parameters of the core, for comparing designs.
+///
+/// The core sizing can be given on the command line as comma-separated key=value pairs,
+/// like `rob_size=64,fetch_width=2,fetch_block_size=16,add_units=4,mul_latency=2,div_radix=4`.
+#[derive(Clone, Debug)]
+pub(crate) struct Config {
+	/// Must have room for the four ucodes that a single instruction can decode to.
+	pub(crate) rob_size: usize,
+	/// The number of instructions that can be decoded per cycle.
+	pub(crate) fetch_width: std::num::NonZero<usize>,
+	/// The size and alignment in bytes of the block of instructions that is fetched into the fetch buffer per cycle.
+	/// Must be a power of two.
+	pub(crate) fetch_block_size: usize,
+	/// Must be able to hold a whole fetch block.
+	pub(crate) fetch_buffer_size: usize,
+	/// The number of ucodes that can be retired per cycle.
+	pub(crate) retire_width: std::num::NonZero<usize>,
+	pub(crate) functional_units: [FunctionalUnitConfig; 8],
+	/// The number of memory accesses per cycle, including the one by the oldest instruction.
+	pub(crate) memory_units: std::num::NonZero<usize>,
+	/// The number of loads that can be in the ROB.
+	pub(crate) load_queue_size: std::num::NonZero<usize>,
+	/// The number of stores that can be in the ROB.
+	pub(crate) store_queue_size: std::num::NonZero<usize>,
+	pub(crate) return_address_stack_depth: usize,
+	pub(crate) branch_predictor: branch_predictor::Kind,
+	pub(crate) branch_target_buffer_entries: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FunctionalUnitConfig {
+	kind: FunctionalUnitKind,
+	count: std::num::NonZero<usize>,
+	/// The number of cycles that the unit is occupied by each operation before its result is available.
+	/// For the multiplier and the divider, this is in addition to the rounds of the operation, minus one.
+	latency: std::num::NonZero<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum FunctionalUnitKind {
+	Add,
+	Cpop,
+	Czero,
+	/// Selects one quotient bit per round at radix 2, or two at radix 4.
+	Div { radix: divider::Radix },
+	Ext,
+	Mul,
+	Or,
+	Shift,
+}
+
+impl Config {
+	/// Sets the keys given on the command line.
+	pub(crate) fn set(&mut self, arg: &str) -> Result<(), config::Error> {
+		for pair in config::pairs(arg) {
+			let pair = pair?;
+			match pair.key {
+				"rob_size" => self.rob_size = Some(pair.integer()?).filter(|&value| value >= 4).ok_or_else(|| pair.invalid_value())?,
+				"fetch_width" => self.fetch_width = pair.non_zero()?,
+				"fetch_block_size" =>
+					self.fetch_block_size = Some(pair.integer()?).filter(|&value: &usize| value >= 2 && value.is_power_of_two()).ok_or_else(|| pair.invalid_value())?,
+				"fetch_buffer_size" => self.fetch_buffer_size = pair.integer()?,
+				"retire_width" => self.retire_width = pair.non_zero()?,
+				"memory_units" => self.memory_units = pair.non_zero()?,
+				"load_queue_size" => self.load_queue_size = pair.non_zero()?,
+				"store_queue_size" => self.store_queue_size = pair.non_zero()?,
+				"div_radix" => *FunctionalUnitConfig::div_radix_mut(&mut self.functional_units) = pair.div_radix()?,
+				key => {
+					let Some(field) = FunctionalUnitConfig::field_mut(&mut self.functional_units, key) else { return Err(pair.unknown_key()); };
+					*field = pair.non_zero()?;
+				},
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Checks the keys that depend on each other, once they have all been set.
+	pub(crate) fn check(&self) -> Result<(), config::Error> {
+		if self.fetch_buffer_size < self.fetch_block_size.max(4) {
+			return Err(config::Error::TooSmall { key: "fetch_buffer_size", min: "fetch_block_size and 4" });
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			rob_size: 32,
+			fetch_width: std::num::NonZero::<usize>::MIN,
+			fetch_block_size: 8,
+			fetch_buffer_size: 16,
+			retire_width: std::num::NonZero::new(32).expect("hard-coded value is not 0"),
+			functional_units: FunctionalUnitConfig::defaults(),
+			memory_units: std::num::NonZero::<usize>::MIN,
+			load_queue_size: std::num::NonZero::new(16).expect("hard-coded value is not 0"),
+			store_queue_size: std::num::NonZero::new(16).expect("hard-coded value is not 0"),
+			return_address_stack_depth: 16,
+			branch_predictor: branch_predictor::Kind::Btfnt,
+			branch_target_buffer_entries: 0,
+		}
+	}
+}
+
+impl FunctionalUnitConfig {
+	/// Two units of each kind.
+	pub(crate) fn defaults() -> [Self; 8] {
+		let functional_unit = |kind, latency| Self {
+			kind,
+			count: std::num::NonZero::new(2).expect("hard-coded value is not 0"),
+			latency: std::num::NonZero::new(latency).expect("hard-coded value is not 0"),
+		};
+
+		[
+			functional_unit(FunctionalUnitKind::Add, 1),
+			functional_unit(FunctionalUnitKind::Cpop, 2),
+			functional_unit(FunctionalUnitKind::Czero, 1),
+			functional_unit(FunctionalUnitKind::Div { radix: divider::Radix::Two }, 1),
+			functional_unit(FunctionalUnitKind::Ext, 1),
+			functional_unit(FunctionalUnitKind::Mul, 1),
+			functional_unit(FunctionalUnitKind::Or, 1),
+			functional_unit(FunctionalUnitKind::Shift, 1),
+		]
+	}
+
+	/// Returns the field set by a `<kind>_units` or `<kind>_latency` key.
+	pub(crate) fn field_mut<'a>(functional_units: &'a mut [Self], key: &str) -> Option<&'a mut std::num::NonZero<usize>> {
+		let (name, latency) =
+			if let Some(name) = key.strip_suffix("_units") { (name, false) }
+			else if let Some(name) = key.strip_suffix("_latency") { (name, true) }
+			else { return None; };
+		let functional_unit = functional_units.iter_mut().find(|functional_unit| functional_unit.kind.name() == name)?;
+		Some(if latency { &mut functional_unit.latency } else { &mut functional_unit.count })
+	}
+
+	/// Returns the field set by the `div_radix` key.
+	pub(crate) fn div_radix_mut(functional_units: &mut [Self]) -> &mut divider::Radix {
+		functional_units.iter_mut()
+			.find_map(|functional_unit| match &mut functional_unit.kind {
+				FunctionalUnitKind::Div { radix } => Some(radix),
+				_ => None,
+			})
+			.expect("there is a config for the divider")
+	}
+
+	pub(crate) fn build(functional_units: &[Self]) -> Vec<FunctionalUnit> {
+		functional_units.iter()
+			.flat_map(|&Self { kind, count, latency }| (1..=count.get()).map(move |i| FunctionalUnit {
+				name: format!("{}{i}", kind.name()),
+				latency,
+				inner: kind.build(),
+			}))
+			.collect()
+	}
+}
+
+impl FunctionalUnitKind {
+	fn name(self) -> &'static str {
+		match self {
+			Self::Add => "add",
+			Self::Cpop => "cpop",
+			Self::Czero => "czero",
+			Self::Div { .. } => "div",
+			Self::Ext => "ext",
+			Self::Mul => "mul",
+			Self::Or => "or",
+			Self::Shift => "shift",
+		}
+	}
+
+	fn build(self) -> Box<dyn ExecutionUnit> {
+		match self {
+			Self::Add => Box::new(AddFunctionalUnit),
+			Self::Cpop => Box::new(CpopFunctionalUnit),
+			Self::Czero => Box::new(CzeroFunctionalUnit),
+			Self::Div { radix } => Box::new(DivFunctionalUnit { radix }),
+			Self::Ext => Box::new(ExtFunctionalUnit),
+			Self::Mul => Box::new(MulFunctionalUnit),
+			Self::Or => Box::new(OrFunctionalUnit),
+			Self::Shift => Box::new(ShiftFunctionalUnit),
+		}
+	}
+}
 
 pub(crate) fn run(
 	memory: &mut Memory,
 	x_regs: &mut XRegs,
 	csrs: &mut Csrs,
 	statistics: &mut Statistics,
-	mut pc: i64,
-	max_retire_per_cycle: std::num::NonZero<usize>,
+	pc: &mut i64,
+	symbols: &Symbols,
+	mut syscalls: Option<&mut Syscalls>,
+	mut htif: Option<&mut Htif>,
+	mut gdb: Option<&mut gdb::Stub>,
+	fusions: &fusion::Config,
+	config: &Config,
 	log_level: LogLevel,
-) {
-	let mut rob = std::collections::VecDeque::<RobEntry>::with_capacity(ROB_MAX_LEN);
+) -> Result<i32, Exception> {
+	let mut fetch_buffer = FetchBuffer::new(*pc, config.fetch_block_size, config.fetch_buffer_size);
+
+	let mut predictors = Predictors {
+		branch_predictor: config.branch_predictor.build(),
+		branch_target_buffer: BranchTargetBuffer::new(config.branch_target_buffer_entries),
+		return_address_stack: ReturnAddressStack::new(config.return_address_stack_depth),
+	};
+
+	let mut functional_units = FunctionalUnitConfig::build(&config.functional_units);
+	let memory_units: Vec<_> = (1..=config.memory_units.get()).map(|i| format!("memory{i}")).collect();
+	for name in functional_units.iter().map(|functional_unit| &functional_unit.name).chain(&memory_units) {
+		statistics.fu_utilization.entry(name.clone()).or_default();
+	}
+
+	let mut rob = std::collections::VecDeque::<RobEntry>::with_capacity(config.rob_size);
 
 	let mut tag_allocator: TagAllocator = Default::default();
 
+	let debugged = gdb.is_some();
+
+	// Whether the oldest entry in the ROB is the first ucode of an instruction, ie an interrupt can be taken before it.
+	let mut inst_boundary = true;
+
 	loop {
 		{
-			let tick = csrs.cycle();
+			let tick = csrs.ticks();
 			if log_level == LogLevel::Debug {
 				if tick % 100 == 0 {
 					eprintln!();
 					eprintln!("===== {tick} =====");
-					eprintln!("0x{pc:016x}");
+					eprintln!("{}", symbols.display(fetch_buffer.pc()));
 				}
 			}
 			else if log_level >= LogLevel::Trace {
@@ -48,29 +266,74 @@ pub(crate) fn run(
 			}
 		}
 
-		let Some(retired) = execute(
+		let retired = match execute(
 			&mut rob,
-			max_retire_per_cycle,
-			&mut pc,
+			config.retire_width,
+			&mut functional_units,
+			&memory_units,
+			&mut fetch_buffer,
+			pc,
+			&mut inst_boundary,
+			&mut predictors,
 			x_regs,
 			csrs,
 			memory,
+			syscalls.as_deref_mut(),
+			gdb.as_deref_mut(),
 			statistics,
 			log_level,
-		) else { return; };
+		)? {
+			std::ops::ControlFlow::Continue(retired) => retired,
+			std::ops::ControlFlow::Break(status) => return Ok(status),
+		};
 
-		fetch(
-			&mut rob,
-			&mut pc,
-			x_regs,
-			csrs,
-			memory,
-			statistics,
-			&mut tag_allocator,
-			log_level,
-		);
+		fetch_buffer.fill(memory);
+		for _ in 0..config.fetch_width.get() {
+			if !fetch(
+				&mut rob,
+				config,
+				fusions,
+				&mut fetch_buffer,
+				&mut predictors,
+				x_regs,
+				csrs,
+				statistics,
+				&mut tag_allocator,
+				debugged,
+				symbols,
+				log_level,
+			) {
+				break;
+			}
+		}
+
+		csrs.tick(1, retired, memory);
 
-		csrs.tick(1, retired);
+		let num_writes = memory.num_writes();
+
+		if let Some(status) = htif.as_deref_mut().and_then(|htif| htif.poll(memory)) {
+			return Ok(status);
+		}
+
+		// The host wrote to memory, so loads that were executed before it might have read what it overwrote.
+		if memory.num_writes() != num_writes {
+			let first_issued_load = rob.iter().position(|rob_entry| matches!(
+				(rob_entry.inst, rob_entry.state),
+				(Ucode::Load { .. }, RobEntryState::MemoryWait(_) | RobEntryState::Done),
+			));
+			if let Some(index) = first_issued_load {
+				// The instruction at the head cannot be refetched if some of its ucodes have already retired.
+				let index = if inst_boundary { index } else { index.max(rob.iter().position(|rob_entry| rob_entry.instret != 0).map_or(rob.len(), |i| i + 1)) };
+				if index < rob.len() {
+					statistics.memory_ordering_violations += 1;
+					flush(&mut rob, index, &mut fetch_buffer, x_regs, csrs);
+				}
+			}
+		}
+
+		if let Some(status) = memory.exit_status() {
+			return Ok(status);
+		}
 
 		if log_level >= LogLevel::Trace {
 			eprintln!("->");
@@ -83,159 +346,300 @@ pub(crate) fn run(
 	}
 }
 
+/// Decodes the instruction at the front of the fetch buffer into the ROB.
+///
+/// Returns whether the next instruction can be decoded in the same cycle, ie the ROB had room, the instruction had been fetched,
+/// and it did not fault or redirect fetch with a jump that is predicted to be taken.
 fn fetch(
 	rob: &mut std::collections::VecDeque<RobEntry>,
-	pc: &mut i64,
+	config: &Config,
+	fusions: &fusion::Config,
+	fetch_buffer: &mut FetchBuffer,
+	predictors: &mut Predictors,
 	x_regs: &mut XRegs,
 	csrs: &mut Csrs,
-	memory: &mut Memory,
 	statistics: &mut Statistics,
 	tag_allocator: &mut TagAllocator,
+	debugged: bool,
+	symbols: &Symbols,
 	log_level: LogLevel,
-) {
-	if rob.len() < ROB_MAX_LEN - 3 && let Ok((inst, inst_len, instret)) = load_inst(memory, *pc, statistics) {
-		let next_inst_pc = pc.wrapping_add(inst_len);
-		let predicted_next_pc = match inst {
-			// BTFNT
-			Instruction::Branch { op: _, rs1: _, rs2: _, imm } =>
-				if imm < 0 {
-					pc.wrapping_add(imm)
-				}
-				else {
-					pc.wrapping_add(inst_len)
-				},
+) -> bool {
+	// Nothing after a fault can be fetched until the trap redirects fetch
+	if let Some(RobEntry { inst: Ucode::Fault(_), .. }) = rob.back() {
+		return false;
+	}
 
-			// Constant
-			Instruction::Jal { rd: _, imm } => pc.wrapping_add(imm),
-
-			// TODO: Instruction::Jalr
-			//
-			// # Return address stack
-			//
-			// Ref: 2.5.1 Unconditional jumps
-			//
-			// ## `jal rd, offset`
-			//
-			// +----+------------+
-			// | rd | RAS action |
-			// +====+============+
-			// | x1 | Push       |
-			// | x5 | Push       |
-			// | _  | None       |
-			// +----+------------+
-			//
-			// ## `jalr rd, offset(rs1)`
-			//
-			// +----+-----+----------------+
-			// | rd | rs1 |   RAS action   |
-			// +====+=====+================+
-			// | x1 | x5  | Pop, then push |
-			// | x5 | x1  | Pop, then push |
-			// | x1 | _   | Push           |
-			// | x5 | _   | Push           |
-			// | _  | x1  | Pop            |
-			// | _  | x5  | Pop            |
-			// | _  | _   | None           |
-			// +----+-----+----------------+
-			//
-			//
-			// # Other cases
-			//
-			// If rs1 is available, no prediction needed.
-			//
-			// `if let RegisterValue::Value(arg1) = x_regs.load(rs1) { arg1.wrapping_add(imm) & -2 }`
-
-			_ => pc.wrapping_add(inst_len),
-		};
+	if rob.len() + 4 > config.rob_size {
+		return false;
+	}
 
-		let tags = tag_allocator.allocate();
-		let ucode_1234 = Ucode::new(
-			inst,
-			*pc,
-			next_inst_pc,
-			predicted_next_pc,
-			x_regs,
-			csrs,
-			tags,
-		);
-		if let Some((ucode_1, ucode_234)) = ucode_1234 {
-			if let Ucode::Jump { .. } = ucode_1 {
-				statistics.jump_predictions += 1;
-			}
+	let pc = fetch_buffer.pc();
+
+	// Fusions are only counted once the instruction is known to fit in the load and store queues.
+	let mut fusion_statistics = fusion::Statistics::default();
+	let (inst, inst_len, instret) = match fetch_buffer.decode(fusions, &mut fusion_statistics) {
+		None => return false,
+		Some(Ok(inst)) => inst,
+		Some(Err(exception)) => {
+			let inst = Ucode::Fault(exception);
 			rob.push_back(RobEntry {
-				state: RobEntryState::initial_state(&ucode_1),
-				inst: ucode_1,
-				instret: ucode_234.map_or(instret, |_| 0),
+				pc,
+				state: RobEntryState::initial_state(&inst),
+				inst,
+				instret: 0,
+				jump: None,
+				training: None,
 			});
+			return false;
+		},
+	};
+
+	// An empty queue always accepts the instruction, so that a load pair can't deadlock a queue with only one entry.
+	let queue_is_full = |is_in_queue: fn(&Ucode) -> bool, queue_size: std::num::NonZero<usize>, entries: usize| {
+		let len = rob.iter().filter(|rob_entry| is_in_queue(&rob_entry.inst)).count();
+		len > 0 && len + entries > queue_size.get()
+	};
+	match inst {
+		Instruction::Load { .. } | Instruction::LuiLoad { .. } if queue_is_full(|inst| matches!(inst, Ucode::Load { .. }), config.load_queue_size, 1) => return false,
+		Instruction::LoadPair { .. } if queue_is_full(|inst| matches!(inst, Ucode::Load { .. }), config.load_queue_size, 2) => return false,
+		Instruction::Store { .. } if queue_is_full(|inst| matches!(inst, Ucode::Store { .. }), config.store_queue_size, 1) => return false,
+		_ => (),
+	}
+	statistics.fusions.merge(fusion_statistics);
+
+	let next_inst_pc = pc.wrapping_add(inst_len);
+
+	let return_address_stack_action = match inst {
+		Instruction::Jal { rd, imm: _ } => return_address_stack::Action::jal(rd),
+		Instruction::Jalr { rd, rs1, imm: _ } => return_address_stack::Action::jalr(rd, rs1),
+		_ => return_address_stack::Action::None,
+	};
+	let (return_address, overflowed) = predictors.return_address_stack.apply(return_address_stack_action, next_inst_pc);
+	if overflowed {
+		statistics.return_address_stack_overflows += 1;
+	}
 
-			if let Some((ucode_2, ucode_34)) = ucode_234 {
-				if let Ucode::Jump { .. } = ucode_2 {
-					statistics.jump_predictions += 1;
-				}
-				rob.push_back(RobEntry {
-					state: RobEntryState::initial_state(&ucode_2),
-					inst: ucode_2,
-					instret: ucode_34.map_or(instret, |_| 0),
-				});
+	let (predicted_next_pc, predictor, kind) = match inst {
+		Instruction::Branch { op: _, rs1: _, rs2: _, imm } |
+		Instruction::SltBranch { op: _, rd: _, rs1: _, rs2: _, imm } => {
+			let prediction = predictors.branch_predictor.predict(pc, imm < 0);
+			let predicted_next_pc = if prediction.taken { pc.wrapping_add(imm) } else { next_inst_pc };
+			(predicted_next_pc, predictors.branch_predictor.name(), JumpKind::Branch { prediction, next_inst_pc })
+		},
+
+		// Constant
+		Instruction::Jal { rd: _, imm } => (pc.wrapping_add(imm), "jal", JumpKind::Direct),
+
+		Instruction::Jalr { rd: _, rs1, imm } =>
+			// If rs1 is available, no prediction is needed.
+			if let RegisterValue::Value(rs1) = x_regs.load(rs1) {
+				(rs1.wrapping_add(imm) & -2, "rs1", JumpKind::Indirect)
+			}
+			// An empty stack falls through, and counts as a miss.
+			else if return_address_stack_action.pops() && predictors.return_address_stack.is_enabled() {
+				(return_address.unwrap_or(next_inst_pc), "ras", JumpKind::Indirect)
+			}
+			else if let Some(target) = predictors.branch_target_buffer.predict(pc) {
+				(target, "btb", JumpKind::Indirect)
+			}
+			else {
+				(next_inst_pc, "none", JumpKind::Indirect)
+			},
 
-				if let Some((ucode_3, ucode_4)) = ucode_34 {
-					if let Ucode::Jump { .. } = ucode_3 {
-						statistics.jump_predictions += 1;
-					}
-					rob.push_back(RobEntry {
-						state: RobEntryState::initial_state(&ucode_3),
-						inst: ucode_3,
-						instret: ucode_4.map_or(instret, |_| 0),
-					});
-
-					if let Some(ucode_4) = ucode_4 {
-						if let Ucode::Jump { .. } = ucode_4 {
-							statistics.jump_predictions += 1;
-						}
-						rob.push_back(RobEntry {
-							state: RobEntryState::initial_state(&ucode_4),
-							inst: ucode_4,
-							instret,
-						});
-					}
+		_ => (next_inst_pc, "none", JumpKind::Direct),
+	};
+
+	let tags = tag_allocator.allocate();
+	let ucode_1234 = Ucode::new(
+		inst,
+		pc,
+		next_inst_pc,
+		predicted_next_pc,
+		x_regs,
+		csrs,
+		tags,
+	);
+	// A debugger has to be able to stop at every instruction, so one without ucodes, like `nop`, still takes a ROB entry.
+	let ucode_1234 = ucode_1234.or_else(|| debugged.then(|| {
+		let (tag, _) = tag_allocator.allocate().allocate();
+		(Ucode::Mv { rd: (XReg::X0, tag), value: RegisterValue::Value(0) }, None)
+	}));
+
+	let mut push = |pc, inst: Ucode, instret| {
+		let jump =
+			if let Ucode::Jump { .. } = inst {
+				statistics.jump_predictions += 1;
+				Some(JumpPrediction {
+					predictor,
+					kind,
+					return_address_stack: predictors.return_address_stack.clone(),
+				})
+			}
+			else {
+				None
+			};
+		rob.push_back(RobEntry {
+			pc,
+			state: RobEntryState::initial_state(&inst),
+			inst,
+			instret,
+			jump,
+			training: None,
+		});
+	};
+
+	let mut ucodes = Vec::with_capacity(4);
+	if let Some((ucode_1, ucode_234)) = ucode_1234 {
+		ucodes.push(ucode_1);
+		if let Some((ucode_2, ucode_34)) = ucode_234 {
+			ucodes.push(ucode_2);
+			if let Some((ucode_3, ucode_4)) = ucode_34 {
+				ucodes.push(ucode_3);
+				if let Some(ucode_4) = ucode_4 {
+					ucodes.push(ucode_4);
 				}
 			}
 		}
+	}
 
-		if log_level >= LogLevel::Trace {
-			eprintln!("+ 0x{pc:016x} : {inst:?}");
+	// The first instruction of a fused pair whose second instruction can fault retires by itself,
+	// so that a fault in the second instruction is taken at that instruction's pc.
+	let (first_half_ucodes, first_half_len) = Ucode::first_half(inst).unwrap_or((0, 0));
+	let num_ucodes = ucodes.len();
+	for (i, ucode) in ucodes.into_iter().enumerate() {
+		if i < first_half_ucodes {
+			push(pc, ucode, i64::from(i + 1 == first_half_ucodes));
 		}
+		else {
+			let instret = if i + 1 == num_ucodes { instret - i64::from(first_half_ucodes > 0) } else { 0 };
+			push(pc.wrapping_add(first_half_len), ucode, instret);
+		}
+	}
+
+	if log_level >= LogLevel::Trace {
+		eprintln!("+ {} : {inst:?}", symbols.display(pc));
+	}
 
-		*pc = predicted_next_pc;
+	if predicted_next_pc == next_inst_pc {
+		fetch_buffer.advance(inst_len);
+		true
+	}
+	else {
+		fetch_buffer.redirect(predicted_next_pc);
+		false
 	}
 }
 
 fn execute(
 	rob: &mut std::collections::VecDeque<RobEntry>,
-	max_retire_per_cycle: std::num::NonZero<usize>,
-	pc: &mut i64,
+	retire_width: std::num::NonZero<usize>,
+	functional_units: &mut [FunctionalUnit],
+	memory_units: &[String],
+	fetch_buffer: &mut FetchBuffer,
+	retire_pc: &mut i64,
+	inst_boundary: &mut bool,
+	predictors: &mut Predictors,
 	x_regs: &mut XRegs,
 	csrs: &mut Csrs,
 	memory: &mut Memory,
+	syscalls: Option<&mut Syscalls>,
+	mut gdb: Option<&mut gdb::Stub>,
 	statistics: &mut Statistics,
 	log_level: LogLevel,
-) -> Option<i64> {
-	let mut remaining_retire = max_retire_per_cycle.get();
+) -> Result<std::ops::ControlFlow<i32, i64>, Exception> {
+	let mut remaining_retire = retire_width.get();
 
 	let mut next_rob = std::collections::VecDeque::with_capacity(rob.len());
 
+	let mut num_memory_units_used = 0;
+
 	if let Some(mut rob_entry) = rob.pop_front() {
+		*retire_pc = rob_entry.pc;
+
+		// The debugger stops before the oldest instruction, once every instruction before it has retired and before
+		// anything after it has, so the state it sees is precise. Everything after it is discarded and refetched once the
+		// program resumes, like after a trap.
+		if *inst_boundary && let Some(gdb) = gdb.as_deref_mut() && gdb.stops_at(rob_entry.pc) {
+			rob.clear();
+			x_regs.reset_all_tags(std::iter::empty());
+			csrs.reset_all_tags(std::iter::empty());
+			if let Some(status) = gdb.stop(retire_pc, x_regs, csrs, memory) {
+				return Ok(std::ops::ControlFlow::Break(status));
+			}
+			fetch_buffer.redirect(*retire_pc);
+			return Ok(std::ops::ControlFlow::Continue(0));
+		}
+
+		// Exceptions are only taken once the faulting instruction is the oldest one,
+		// so every older instruction has retired and every younger one can be discarded.
+		//
+		// Interrupts are taken the same way before the oldest instruction, unless it has already had side effects
+		// that cannot be undone. A wfi also retires first so that the handler returns to the instruction after it.
+		let can_interrupt = *inst_boundary && !matches!(
+			(rob_entry.inst, rob_entry.state),
+			(Ucode::Ecall { .. } | Ucode::Load { .. } | Ucode::Store { .. }, RobEntryState::Done) | (Ucode::Wfi, _),
+		);
+		let exception =
+			if can_interrupt && let Some(interrupt) = csrs.pending_interrupt() {
+				Some(interrupt)
+			}
+			else if let RobEntryState::Exception(exception) = rob_entry.state {
+				Some(exception)
+			}
+			else {
+				None
+			};
+		// The return address stack is left as it is, since the handler returns to the same call depth.
+		if let Some(exception) = exception {
+			if log_level >= LogLevel::Trace {
+				eprintln!("trap: {exception}");
+			}
+
+			rob.clear();
+			x_regs.reset_all_tags(std::iter::empty());
+			csrs.reset_all_tags(std::iter::empty());
+			fetch_buffer.redirect(csrs.take_trap(exception, rob_entry.pc).ok_or(exception)?);
+			*inst_boundary = true;
+			if let Some(gdb) = gdb {
+				gdb.advance();
+			}
+			statistics.num_ticks_where_instructions_not_retired += 1;
+			return Ok(std::ops::ControlFlow::Continue(0));
+		}
+
 		if let Ucode::Ebreak = rob_entry.inst {
-			return None;
+			return Ok(std::ops::ControlFlow::Break(0));
+		}
+
+		if let (Ucode::Wfi, RobEntryState::Pending) = (rob_entry.inst, rob_entry.state) && csrs.wfi_done() {
+			rob_entry.state = RobEntryState::Done;
+		}
+
+		// Syscalls have side effects, so they are only serviced once the ecall is the oldest instruction.
+		if let (Ucode::Ecall { rd }, RobEntryState::Pending) = (&mut rob_entry.inst, rob_entry.state) {
+			if let Some(syscalls) = syscalls {
+				match syscalls.ecall(x_regs, memory) {
+					SyscallResult::Return(value) => {
+						rd.2 = Some(value);
+						rob_entry.state = RobEntryState::Done;
+					},
+					SyscallResult::Exit(status) => return Ok(std::ops::ControlFlow::Break(status)),
+				}
+			}
+			else {
+				rob_entry.state = RobEntryState::Exception(Exception::EnvironmentCallFromMMode);
+			}
 		}
 
-		if matches!(rob_entry.state, RobEntryState::Pending) {
+		if matches!(rob_entry.state, RobEntryState::Pending | RobEntryState::MemoryWait(_)) {
 			remaining_retire = 0;
 		}
 
-		// Only the first entry gets to execute on memory functional unit
+		// Stores, fences and loads that have side effects only access memory once they are the oldest instruction.
+		// Stores are committed to memory here, right before they retire.
 		if MemoryFunctionalUnit.try_execute(&mut rob_entry, memory) {
-			*statistics.fu_utilization.entry("memory").or_default() += 1;
+			count_utilization(statistics, &memory_units[0]);
+			num_memory_units_used = 1;
 			next_rob.push_back(rob_entry);
 		}
 		else {
@@ -243,23 +647,13 @@ fn execute(
 		}
 	}
 
-	let functional_units: [&mut dyn FunctionalUnit; 14] = [
-		&mut AddFunctionalUnit("add1"),
-		&mut AddFunctionalUnit("add2"),
-		&mut CpopFunctionalUnit("cpop1"),
-		&mut CpopFunctionalUnit("cpop2"),
-		&mut CzeroFunctionalUnit("czero1"),
-		&mut CzeroFunctionalUnit("czero2"),
-		&mut ExtFunctionalUnit("ext1"),
-		&mut ExtFunctionalUnit("ext2"),
-		&mut MulFunctionalUnit("mul1"),
-		&mut MulFunctionalUnit("mul2"),
-		&mut OrFunctionalUnit("or1"),
-		&mut OrFunctionalUnit("or2"),
-		&mut ShiftFunctionalUnit("shift1"),
-		&mut ShiftFunctionalUnit("shift2"),
-	];
-	let mut functional_units: Vec<_> = functional_units.into();
+	// Units that are still busy with an operation from a previous cycle are not available for any other.
+	let mut busy = vec![false; functional_units.len()];
+	for rob_entry in &*rob {
+		if let RobEntryState::Busy { functional_unit, .. } = rob_entry.state {
+			busy[functional_unit] = true;
+		}
+	}
 
 	let mut retired = 0;
 	let mut misprediction = false;
@@ -268,6 +662,11 @@ fn execute(
 	while let Some(mut rob_entry) = rob.pop_front() {
 		if matches!(rob_entry.state, RobEntryState::Done) {
 			if !misprediction {
+				// An instruction that the debugger stops at waits until it is the oldest.
+				if *inst_boundary && gdb.as_deref().is_some_and(|gdb| gdb.stops_at(rob_entry.pc)) {
+					remaining_retire = 0;
+				}
+
 				if let Some((rd, tag, value)) = rob_entry.inst.done_rd() {
 					done_tags.push((tag, value));
 
@@ -284,28 +683,60 @@ fn execute(
 					}
 				}
 
-				if let Ucode::Jump { pc: RegisterValue::Value(next_pc), predicted_next_pc } = rob_entry.inst && next_pc != predicted_next_pc {
-					misprediction = true;
-					statistics.jump_mispredictions += 1;
-					remaining_retire = 0;
-					if log_level >= LogLevel::Trace {
-						eprintln!("jump misprediction!");
+				// A jump is resolved the first time it is seen to be done.
+				if
+					let Ucode::Jump { pc: RegisterValue::Value(next_pc), predicted_next_pc } = rob_entry.inst &&
+					let Some(jump) = rob_entry.jump.take()
+				{
+					let mispredicted = next_pc != predicted_next_pc;
+					rob_entry.training = Some((jump.kind, next_pc));
+
+					if jump.predictor == "ras" {
+						if mispredicted {
+							statistics.return_address_stack_misses += 1;
+						}
+						else {
+							statistics.return_address_stack_hits += 1;
+						}
 					}
 
-					*pc = next_pc;
-					x_regs.reset_all_tags(next_rob.iter().filter_map(|rob_entry| rob_entry.inst.rd()));
-					csrs.reset_all_tags(next_rob.iter().filter_map(|rob_entry| rob_entry.inst.csr()));
+					if mispredicted {
+						misprediction = true;
+						statistics.jump_mispredictions += 1;
+						*statistics.jump_mispredictions_by_predictor.entry(jump.predictor).or_default() += 1;
+						*statistics.jump_mispredictions_by_pc.entry(rob_entry.pc).or_default() += 1;
+						remaining_retire = 0;
+						if log_level >= LogLevel::Trace {
+							eprintln!("jump misprediction!");
+						}
 
-					rob_entry = RobEntry {
-						state: RobEntryState::Done,
-						inst: Ucode::Jump { pc: RegisterValue::Value(next_pc), predicted_next_pc: next_pc },
-						instret: rob_entry.instret,
-					};
+						fetch_buffer.redirect(next_pc);
+						predictors.return_address_stack = jump.return_address_stack;
+						x_regs.reset_all_tags(next_rob.iter().filter_map(|rob_entry| rob_entry.inst.rd()));
+						csrs.reset_all_tags(next_rob.iter().filter_map(|rob_entry| rob_entry.inst.csr()));
+					}
 				}
 
 				if let Some(r) = remaining_retire.checked_sub(1) {
 					remaining_retire = r;
 					retired += rob_entry.instret;
+
+					match rob_entry.training {
+						Some((JumpKind::Branch { prediction, next_inst_pc }, next_pc)) =>
+							predictors.branch_predictor.update(rob_entry.pc, prediction, next_pc != next_inst_pc),
+						Some((JumpKind::Indirect, next_pc)) => predictors.branch_target_buffer.update(rob_entry.pc, next_pc),
+						Some((JumpKind::Direct, _)) | None => (),
+					}
+					*inst_boundary = rob_entry.instret != 0;
+
+					if *inst_boundary && let Some(gdb) = gdb.as_deref_mut() {
+						gdb.advance();
+					}
+
+					// Give the interrupt that woke up the wfi a chance to be taken right after it.
+					if let Ucode::Wfi = rob_entry.inst {
+						remaining_retire = 0;
+					}
 				}
 				else {
 					next_rob.push_back(rob_entry);
@@ -315,21 +746,37 @@ fn execute(
 		else {
 			remaining_retire = 0;
 
-			let mut executed = false;
-			functional_units.retain_mut(|functional_unit| {
-				if executed {
-					return true;
+			if let Ucode::Load { .. } = rob_entry.inst {
+				if
+					!misprediction &&
+					let Some(memory_unit) = memory_units.get(num_memory_units_used) &&
+					try_execute_speculative_load(&mut rob_entry, &next_rob, memory, statistics)
+				{
+					count_utilization(statistics, memory_unit);
+					num_memory_units_used += 1;
 				}
-
-				if functional_unit.try_execute(&mut rob_entry) {
-					*statistics.fu_utilization.entry(functional_unit.statistics_key()).or_default() += 1;
-					executed = true;
-					false
+			}
+			else if let RobEntryState::Busy { functional_unit, remaining } = rob_entry.state {
+				count_utilization(statistics, &functional_units[functional_unit].name);
+				rob_entry.state = match remaining.checked_sub(1) {
+					Some(remaining) => RobEntryState::Busy { functional_unit, remaining },
+					None => RobEntryState::Done,
+				};
+			}
+			else if
+				let RobEntryState::Pending = rob_entry.state &&
+				let Some(i) = (0..functional_units.len()).find(|&i| !busy[i] && functional_units[i].inner.try_execute(&mut rob_entry.inst))
+			{
+				busy[i] = true;
+				if rob_entry.inst.done() {
+					rob_entry.state = RobEntryState::Done;
 				}
-				else {
-					true
+				let functional_unit = &functional_units[i];
+				count_utilization(statistics, &functional_unit.name);
+				if let (RobEntryState::Done, Some(remaining)) = (rob_entry.state, functional_unit.latency.get().checked_sub(2)) {
+					rob_entry.state = RobEntryState::Busy { functional_unit: i, remaining };
 				}
-			});
+			}
 
 			if !misprediction {
 				next_rob.push_back(rob_entry);
@@ -339,16 +786,34 @@ fn execute(
 
 	*rob = next_rob;
 
-	for (tag, value) in done_tags {
-		for rob_entry in &mut *rob {
-			if !(matches!(rob_entry.state, RobEntryState::Done)) {
-				rob_entry.update(tag, value);
-			}
+	// A store whose address becomes known might overlap a younger load that has already been executed
+	// with an older value, so that load and everything after it has to be executed again.
+	let mut violation = None;
+	for i in 0..rob.len() {
+		let rob_entry = &mut rob[i];
+		if matches!(rob_entry.state, RobEntryState::Done) {
+			continue;
+		}
+
+		let address_was_unknown = matches!(rob_entry.inst, Ucode::Store { addr: RegisterValue::Tag(_), .. });
+		for &(tag, value) in &done_tags {
+			rob_entry.update(tag, value);
+		}
+		if
+			address_was_unknown &&
+			let Ucode::Store { op, addr: RegisterValue::Value(addr), .. } = rob_entry.inst &&
+			let Some(load) = find_ordering_violation(rob, i, addr, op.size())
+		{
+			violation = Some(violation.map_or(load, |violation: usize| violation.min(load)));
 		}
 	}
+	if let Some(load) = violation {
+		if log_level >= LogLevel::Trace {
+			eprintln!("memory ordering violation!");
+		}
 
-	for functional_unit in functional_units {
-		_ = *statistics.fu_utilization.entry(functional_unit.statistics_key()).or_default();
+		statistics.memory_ordering_violations += 1;
+		flush(rob, load, fetch_buffer, x_regs, csrs);
 	}
 
 	if retired == 0 {
@@ -358,21 +823,175 @@ fn execute(
 		statistics.num_ticks_where_instructions_retired += 1;
 	}
 
-	Some(retired)
+	Ok(std::ops::ControlFlow::Continue(retired))
+}
+
+/// Executes a load that is not the oldest instruction, if it does not have side effects and no older store or ecall prevents it.
+///
+/// The load is forwarded the value of the youngest older store that it overlaps, if that store contains all of it.
+/// Stores whose address is not known yet are assumed not to overlap, which [`find_ordering_violation`] checks once it is known.
+fn try_execute_speculative_load(
+	rob_entry: &mut RobEntry,
+	older: &std::collections::VecDeque<RobEntry>,
+	memory: &Memory,
+	statistics: &mut Statistics,
+) -> bool {
+	match (&mut rob_entry.inst, rob_entry.state) {
+		(Ucode::Load { .. }, RobEntryState::MemoryWait(remaining)) => {
+			rob_entry.state = match remaining.checked_sub(1) {
+				Some(remaining) => RobEntryState::MemoryWait(remaining),
+				None => RobEntryState::Done,
+			};
+			true
+		},
+
+		(Ucode::Load { op, rd, addr: RegisterValue::Value(addr) }, RobEntryState::Pending) => {
+			let (addr, size) = (*addr, op.size());
+			if !memory.is_memory_backed(addr, size) {
+				return false;
+			}
+
+			for older in older.iter().rev() {
+				match (older.inst, older.state) {
+					// The syscall might write to memory.
+					(Ucode::Ecall { .. }, state) if !matches!(state, RobEntryState::Done) => return false,
+
+					(Ucode::Store { op: store_op, addr: RegisterValue::Value(store_addr), value }, _) if overlaps(store_addr, store_op.size(), addr, size) => {
+						let RegisterValue::Value(value) = value else { return false; };
+						let Some(offset) = contains(store_addr, store_op.size(), addr, size) else { return false; };
+						let mut data = [0_u8; 8];
+						data[..size].copy_from_slice(&value.to_le_bytes()[offset..(offset + size)]);
+						rd.2 = Some(op.extend(data));
+						rob_entry.state = RobEntryState::Done;
+						statistics.speculative_loads += 1;
+						statistics.store_to_load_forwards += 1;
+						return true;
+					},
+
+					_ => (),
+				}
+			}
+
+			rob_entry.state = match op.exec(memory, addr) {
+				Ok(value) => {
+					rd.2 = Some(value);
+					match op.latency(memory, addr).checked_sub(1) {
+						Some(remaining) => RobEntryState::MemoryWait(remaining),
+						None => RobEntryState::Done,
+					}
+				},
+				Err(exception) => RobEntryState::Exception(exception),
+			};
+			statistics.speculative_loads += 1;
+			true
+		},
+
+		_ => false,
+	}
+}
+
+/// Returns the index of the oldest load after the store at index `store` that has already been executed
+/// and overlaps the store, unless a store between them contains all of the load.
+fn find_ordering_violation(rob: &std::collections::VecDeque<RobEntry>, store: usize, addr: i64, size: usize) -> Option<usize> {
+	(store + 1..rob.len()).find(|&i| {
+		let (Ucode::Load { op, addr: RegisterValue::Value(load_addr), .. }, RobEntryState::MemoryWait(_) | RobEntryState::Done) = (rob[i].inst, rob[i].state) else {
+			return false;
+		};
+		overlaps(addr, size, load_addr, op.size()) &&
+			!rob.range(store + 1..i).any(|rob_entry| matches!(
+				rob_entry.inst,
+				Ucode::Store { op: store_op, addr: RegisterValue::Value(store_addr), .. }
+					if contains(store_addr, store_op.size(), load_addr, op.size()).is_some(),
+			))
+	})
+}
+
+/// Discards the instruction of the entry at `index` and everything after it, and refetches from that instruction.
+fn flush(
+	rob: &mut std::collections::VecDeque<RobEntry>,
+	index: usize,
+	fetch_buffer: &mut FetchBuffer,
+	x_regs: &mut XRegs,
+	csrs: &mut Csrs,
+) {
+	let mut index = index;
+	while index > 0 && rob[index - 1].instret == 0 {
+		index -= 1;
+	}
+
+	fetch_buffer.redirect(rob[index].pc);
+	rob.truncate(index);
+	x_regs.reset_all_tags(rob.iter().filter_map(|rob_entry| rob_entry.inst.rd()));
+	csrs.reset_all_tags(rob.iter().filter_map(|rob_entry| rob_entry.inst.csr()));
+}
+
+/// Returns whether the `a_size` bytes at `a` overlap the `b_size` bytes at `b`.
+fn overlaps(a: i64, a_size: usize, b: i64, b_size: usize) -> bool {
+	let fits = |offset: i64, size: usize| usize::try_from(offset.cast_unsigned()).is_ok_and(|offset| offset < size);
+	fits(b.wrapping_sub(a), a_size) || fits(a.wrapping_sub(b), b_size)
+}
+
+/// Returns the offset of the `b_size` bytes at `b` in the `a_size` bytes at `a`, if `a` contains all of them.
+fn contains(a: i64, a_size: usize, b: i64, b_size: usize) -> Option<usize> {
+	let offset = usize::try_from(b.wrapping_sub(a).cast_unsigned()).ok()?;
+	(offset + b_size <= a_size).then_some(offset)
+}
+
+fn count_utilization(statistics: &mut Statistics, name: &str) {
+	if let Some(count) = statistics.fu_utilization.get_mut(name) {
+		*count += 1;
+	}
+	else {
+		statistics.fu_utilization.insert(name.to_owned(), 1);
+	}
 }
 
 #[derive(Debug)]
 struct RobEntry {
+	pc: i64,
 	state: RobEntryState,
 	inst: Ucode,
 	instret: i64,
+	/// `None` for ucodes other than jumps, and once the jump has been resolved.
+	jump: Option<JumpPrediction>,
+	/// The kind and target of a resolved jump. The predictors are only trained with it once the jump retires,
+	/// so that jumps on a mispredicted path that are resolved before the older mispredicted jump do not train them.
+	training: Option<(JumpKind, i64)>,
+}
+
+#[derive(Debug)]
+struct JumpPrediction {
+	/// What predicted the jump's target, for statistics.
+	predictor: &'static str,
+	kind: JumpKind,
+	/// The state of the return address stack after the jump's own action,
+	/// to restore if the jump turns out to be mispredicted.
+	return_address_stack: ReturnAddressStack,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum JumpKind {
+	/// The branch predictor is trained with the outcome.
+	Branch { prediction: branch_predictor::Prediction, next_inst_pc: i64 },
+	/// The branch target buffer is trained with the target.
+	Indirect,
+	Direct,
+}
+
+struct Predictors {
+	branch_predictor: Box<dyn BranchPredictor>,
+	branch_target_buffer: BranchTargetBuffer,
+	return_address_stack: ReturnAddressStack,
 }
 
 #[derive(Clone, Copy, Debug)]
 enum RobEntryState {
 	Pending,
-	CpopWait(usize),
+	/// The result has been computed, but the unit at this index of the functional units takes `remaining + 1` more cycles to produce it.
+	Busy { functional_unit: usize, remaining: usize },
+	MemoryWait(u8),
 	Done,
+	Exception(Exception),
 }
 
 impl RobEntry {
@@ -388,7 +1007,10 @@ impl RobEntry {
 
 impl RobEntryState {
 	fn initial_state(inst: &Ucode) -> Self {
-		if inst.done() {
+		if let Ucode::Fault(exception) = *inst {
+			Self::Exception(exception)
+		}
+		else if inst.done() {
 			Self::Done
 		}
 		else {
@@ -397,25 +1019,29 @@ impl RobEntryState {
 	}
 }
 
-trait FunctionalUnit {
-	fn statistics_key(&self) -> &'static str;
-
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool;
+pub(crate) struct FunctionalUnit {
+	pub(crate) name: String,
+	pub(crate) latency: std::num::NonZero<usize>,
+	pub(crate) inner: Box<dyn ExecutionUnit>,
 }
 
-struct AddFunctionalUnit(&'static str);
+pub(crate) trait ExecutionUnit {
+	/// Executes the ucode, or one round of it for multi-round operations, if this unit can execute it and its operands are ready.
+	/// The ucode is done once its result has been written to it.
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool;
+}
 
-impl FunctionalUnit for AddFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
+struct AddFunctionalUnit;
 
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::BinaryOp {
+impl ExecutionUnit for AddFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::BinaryOp {
 				op,
 				rd,
 				rs1: RegisterValue::Value(arg1),
 				rs2: RegisterValue::Value(arg2),
-			}, RobEntryState::Pending) => {
+			} => {
 				let arg1 = *arg1;
 				let arg2 = *arg2;
 
@@ -447,7 +1073,6 @@ impl FunctionalUnit for AddFunctionalUnit {
 					_ => return false,
 				};
 				rd.2 = Some(value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
@@ -456,18 +1081,16 @@ impl FunctionalUnit for AddFunctionalUnit {
 	}
 }
 
-struct CpopFunctionalUnit(&'static str);
+struct CpopFunctionalUnit;
 
-impl FunctionalUnit for CpopFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
-
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::UnaryOp {
+impl ExecutionUnit for CpopFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::UnaryOp {
 				op,
 				rd,
 				rs: RegisterValue::Value(arg),
-			}, RobEntryState::Pending) => {
+			} => {
 				let arg = *arg;
 
 				#[allow(clippy::cast_possible_truncation)]
@@ -480,17 +1103,6 @@ impl FunctionalUnit for CpopFunctionalUnit {
 					_ => return false,
 				};
 				rd.2 = Some(value);
-				rob_entry.state = RobEntryState::CpopWait(0);
-				true
-			},
-
-			(_, RobEntryState::CpopWait(w)) => {
-				if let Some(next_w) = w.checked_sub(1) {
-					rob_entry.state = RobEntryState::CpopWait(next_w);
-				}
-				else {
-					rob_entry.state = RobEntryState::Done;
-				}
 				true
 			},
 
@@ -499,46 +1111,41 @@ impl FunctionalUnit for CpopFunctionalUnit {
 	}
 }
 
-struct CzeroFunctionalUnit(&'static str);
-
-impl FunctionalUnit for CzeroFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
+struct CzeroFunctionalUnit;
 
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::Csel {
+impl ExecutionUnit for CzeroFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::Csel {
 				rd,
 				rcond: RegisterValue::Value(0),
 				rs_eqz: RegisterValue::Value(value),
 				rs_nez: RegisterValue::Value(_),
-			}, RobEntryState::Pending) => {
+			} => {
 				rd.2 = Some(*value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
-			(Ucode::Csel {
+			Ucode::Csel {
 				rd,
 				rcond: RegisterValue::Value(rcond),
 				rs_eqz: RegisterValue::Value(_),
 				rs_nez: RegisterValue::Value(value),
-			}, RobEntryState::Pending) if *rcond != 0 => {
+			} if *rcond != 0 => {
 				rd.2 = Some(*value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
-			(Ucode::UnaryOp {
+			Ucode::UnaryOp {
 				op,
 				rd,
 				rs: RegisterValue::Value(arg),
-			}, RobEntryState::Pending) => {
+			} => {
 				let value = match *op {
 					UnaryOp::OrcB => i64::from_ne_bytes(arg.to_ne_bytes().map(|b| if b == 0 { 0x00 } else { 0xff })),
 					_ => return false,
 				};
 				rd.2 = Some(value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
@@ -547,18 +1154,39 @@ impl FunctionalUnit for CzeroFunctionalUnit {
 	}
 }
 
-struct ExtFunctionalUnit(&'static str);
+struct DivFunctionalUnit {
+	radix: divider::Radix,
+}
 
-impl FunctionalUnit for ExtFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
+impl ExecutionUnit for DivFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::Div {
+				op,
+				rd,
+				rs1: RegisterValue::Value(arg1),
+				rs2: RegisterValue::Value(arg2),
+				state,
+			} => {
+				rd.2 = DivOp::round(*op, state, *arg1, *arg2, self.radix);
+				true
+			},
 
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::UnaryOp {
+			_ => false,
+		}
+	}
+}
+
+struct ExtFunctionalUnit;
+
+impl ExecutionUnit for ExtFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::UnaryOp {
 				op,
 				rd,
 				rs: RegisterValue::Value(arg),
-			}, RobEntryState::Pending) => {
+			} => {
 				let arg = *arg;
 
 				#[allow(clippy::cast_possible_truncation)]
@@ -572,7 +1200,6 @@ impl FunctionalUnit for ExtFunctionalUnit {
 					_ => return false,
 				};
 				rd.2 = Some(value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
@@ -581,21 +1208,19 @@ impl FunctionalUnit for ExtFunctionalUnit {
 	}
 }
 
-struct MulFunctionalUnit(&'static str);
-
-impl FunctionalUnit for MulFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
+struct MulFunctionalUnit;
 
+impl ExecutionUnit for MulFunctionalUnit {
 	#[allow(clippy::many_single_char_names)]
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::Mul {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::Mul {
 				op,
 				rd,
 				rs1: RegisterValue::Value(arg1),
 				rs2: RegisterValue::Value(arg2),
 				state,
-			}, RobEntryState::Pending) => {
+			} => {
 				let (arg1_is_signed, arg1) = match *op {
 					MulOp::Mul |
 					MulOp::Mulh |
@@ -627,7 +1252,6 @@ impl FunctionalUnit for MulFunctionalUnit {
 					State::Mulw { i: i_, p: p_, mulw } =>
 						if matches!(*op, MulOp::Mulw) {
 							rd.2 = Some(mulw.into());
-							rob_entry.state = RobEntryState::Done;
 						}
 						else {
 							*i = i_;
@@ -635,14 +1259,7 @@ impl FunctionalUnit for MulFunctionalUnit {
 						},
 
 					State::Mul { mul, mulh } =>
-						if matches!(*op, MulOp::Mul) {
-							rd.2 = Some(mul);
-							rob_entry.state = RobEntryState::Done;
-						}
-						else {
-							rd.2 = Some(mulh);
-							rob_entry.state = RobEntryState::Done;
-						},
+						rd.2 = Some(if matches!(*op, MulOp::Mul) { mul } else { mulh }),
 				}
 				true
 			},
@@ -652,19 +1269,17 @@ impl FunctionalUnit for MulFunctionalUnit {
 	}
 }
 
-struct OrFunctionalUnit(&'static str);
-
-impl FunctionalUnit for OrFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
+struct OrFunctionalUnit;
 
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::BinaryOp {
+impl ExecutionUnit for OrFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::BinaryOp {
 				op,
 				rd,
 				rs1: RegisterValue::Value(arg1),
 				rs2: RegisterValue::Value(arg2),
-			}, RobEntryState::Pending) => {
+			} => {
 				let arg1 = *arg1;
 				let arg2 = *arg2;
 
@@ -674,7 +1289,6 @@ impl FunctionalUnit for OrFunctionalUnit {
 					_ => return false,
 				};
 				rd.2 = Some(value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
@@ -683,19 +1297,17 @@ impl FunctionalUnit for OrFunctionalUnit {
 	}
 }
 
-struct ShiftFunctionalUnit(&'static str);
+struct ShiftFunctionalUnit;
 
-impl FunctionalUnit for ShiftFunctionalUnit {
-	fn statistics_key(&self) -> &'static str { self.0 }
-
-	fn try_execute(&mut self, rob_entry: &mut RobEntry) -> bool {
-		match (&mut rob_entry.inst, rob_entry.state) {
-			(Ucode::BinaryOp {
+impl ExecutionUnit for ShiftFunctionalUnit {
+	fn try_execute(&mut self, inst: &mut Ucode) -> bool {
+		match inst {
+			Ucode::BinaryOp {
 				op,
 				rd,
 				rs1: RegisterValue::Value(arg1),
 				rs2: RegisterValue::Value(arg2),
-			}, RobEntryState::Pending) => {
+			} => {
 				let arg1 = *arg1;
 				let arg2 = *arg2;
 
@@ -732,7 +1344,6 @@ impl FunctionalUnit for ShiftFunctionalUnit {
 					_ => return false,
 				};
 				rd.2 = Some(value);
-				rob_entry.state = RobEntryState::Done;
 				true
 			},
 
@@ -752,10 +1363,19 @@ impl MemoryFunctionalUnit {
 				true
 			},
 
-			(Ucode::Load { op, rd, addr: RegisterValue::Value(addr) }, RobEntryState::Pending) => {
-				let result = op.exec(memory, *addr);
-				rd.2 = Some(result);
-				rob_entry.state = RobEntryState::Done;
+			(Ucode::Load { op, rd, addr: RegisterValue::Value(addr) }, state @ (RobEntryState::Pending | RobEntryState::MemoryWait(_))) => {
+				if let Some(wait) = memory_wait(state, || op.latency(memory, *addr)) {
+					rob_entry.state = RobEntryState::MemoryWait(wait);
+					return true;
+				}
+
+				match op.exec(memory, *addr) {
+					Ok(result) => {
+						rd.2 = Some(result);
+						rob_entry.state = RobEntryState::Done;
+					},
+					Err(exception) => rob_entry.state = RobEntryState::Exception(exception),
+				}
 				true
 			},
 
@@ -763,9 +1383,16 @@ impl MemoryFunctionalUnit {
 				op,
 				addr: RegisterValue::Value(addr),
 				value: RegisterValue::Value(value),
-			}, RobEntryState::Pending) => {
-				op.exec(memory, *addr, *value);
-				rob_entry.state = RobEntryState::Done;
+			}, state @ (RobEntryState::Pending | RobEntryState::MemoryWait(_))) => {
+				if let Some(wait) = memory_wait(state, || op.latency(memory, *addr)) {
+					rob_entry.state = RobEntryState::MemoryWait(wait);
+					return true;
+				}
+
+				rob_entry.state = match op.exec(memory, *addr, *value) {
+					Ok(()) => RobEntryState::Done,
+					Err(exception) => RobEntryState::Exception(exception),
+				};
 				true
 			},
 
@@ -773,3 +1400,15 @@ impl MemoryFunctionalUnit {
 		}
 	}
 }
+
+/// Returns the number of cycles that a load or store has to keep waiting for the memory before it can access it,
+/// or `None` if it can access it this cycle.
+///
+/// `latency` is only called when the access starts, since it accesses the caches.
+fn memory_wait(state: RobEntryState, latency: impl FnOnce() -> u8) -> Option<u8> {
+	let remaining = match state {
+		RobEntryState::MemoryWait(remaining) => remaining,
+		_ => latency(),
+	};
+	remaining.checked_sub(1)
+}
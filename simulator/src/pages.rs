@@ -0,0 +1,112 @@
+/// Sparse memory made of 4 KiB pages that are allocated when they are first written to.
+/// Pages that have never been written to read as 0.
+#[derive(Default)]
+pub(crate) struct Pages {
+	pages: std::collections::HashMap<u64, Box<[u8; PAGE_SIZE]>>,
+}
+
+const PAGE_SIZE: usize = 4096;
+
+impl Pages {
+	/// Reads `buf.len()` bytes starting at `address`, which may span pages.
+	pub(crate) fn read(&self, address: u64, buf: &mut [u8]) {
+		for (page_number, offset, range) in chunks(address, buf.len()) {
+			let buf = &mut buf[range];
+			match self.pages.get(&page_number) {
+				Some(page) => buf.copy_from_slice(&page[offset..(offset + buf.len())]),
+				None => buf.fill(0),
+			}
+		}
+	}
+
+	/// Writes `buf` starting at `address`, which may span pages.
+	pub(crate) fn write(&mut self, address: u64, buf: &[u8]) {
+		for (page_number, offset, range) in chunks(address, buf.len()) {
+			let buf = &buf[range];
+			let page = self.pages.entry(page_number).or_insert_with(|| Box::new([0; PAGE_SIZE]));
+			page[offset..(offset + buf.len())].copy_from_slice(buf);
+		}
+	}
+
+	/// Zeroes `len` bytes starting at `address`, which may span pages. Pages that have never been written to are not allocated.
+	pub(crate) fn zero(&mut self, address: u64, len: usize) {
+		for (page_number, offset, range) in chunks(address, len) {
+			if let Some(page) = self.pages.get_mut(&page_number) {
+				page[offset..(offset + range.len())].fill(0);
+			}
+		}
+	}
+}
+
+/// Splits the range of `len` bytes starting at `address` into the parts that are in each page,
+/// as the page number, the offset of the part in the page, and the range of the part in the original range.
+fn chunks(address: u64, len: usize) -> impl Iterator<Item = (u64, usize, std::ops::Range<usize>)> {
+	let page_size = u64::try_from(PAGE_SIZE).unwrap();
+	let mut start = 0;
+	std::iter::from_fn(move || {
+		if start == len {
+			return None;
+		}
+
+		let address = address.wrapping_add(u64::try_from(start).unwrap());
+		let page_number = address / page_size;
+		let offset = usize::try_from(address % page_size).unwrap();
+		let end = len.min(start + (PAGE_SIZE - offset));
+		let range = start..end;
+		start = end;
+		Some((page_number, offset, range))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PAGE_SIZE, Pages};
+
+	#[test]
+	fn it_works() {
+		let mut pages: Pages = Default::default();
+
+		// Untouched pages read as zeros, and reading does not allocate them.
+		let mut buf = [0xff_u8; 16];
+		pages.read(0x1234_5678, &mut buf);
+		assert_eq!(buf, [0; 16]);
+		assert!(pages.pages.is_empty());
+
+		// A write that spans a page boundary
+		let data: Vec<u8> = (1..=16).collect();
+		pages.write(0x2000 - 8, &data);
+		assert_eq!(pages.pages.keys().copied().collect::<std::collections::BTreeSet<_>>(), [1, 2].into());
+
+		// Reads that span the boundary, or that only overlap part of the data
+		let mut buf = [0_u8; 16];
+		pages.read(0x2000 - 8, &mut buf);
+		assert_eq!(buf[..], data[..]);
+		let mut buf = [0xff_u8; 8];
+		pages.read(0x2000 - 12, &mut buf);
+		assert_eq!(buf, [0, 0, 0, 0, 1, 2, 3, 4]);
+		pages.read(0x2000 + 4, &mut buf);
+		assert_eq!(buf, [13, 14, 15, 16, 0, 0, 0, 0]);
+
+		// A read that spans an untouched page between two written ones
+		pages.write(0x4000, &[0xaa]);
+		let mut buf = vec![0xff_u8; 2 * PAGE_SIZE + 1];
+		pages.read(0x2000, &mut buf);
+		assert_eq!(buf[..8], data[8..]);
+		assert!(buf[8..(2 * PAGE_SIZE)].iter().all(|&b| b == 0));
+		assert_eq!(buf[2 * PAGE_SIZE], 0xaa);
+
+		// Addresses at the top of the address space
+		pages.write(u64::MAX - 1, &[1, 2]);
+		let mut buf = [0_u8; 2];
+		pages.read(u64::MAX - 1, &mut buf);
+		assert_eq!(buf, [1, 2]);
+
+		// Zeroing only clears pages that have been written to, and does not allocate the others.
+		let num_pages = pages.pages.len();
+		pages.zero(0x2000 - 4, 3 * PAGE_SIZE);
+		assert_eq!(pages.pages.len(), num_pages);
+		let mut buf = [0xff_u8; 16];
+		pages.read(0x2000 - 8, &mut buf);
+		assert_eq!(buf, [1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+	}
+}
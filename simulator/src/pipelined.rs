@@ -0,0 +1,338 @@
+use crate::{
+	config,
+	csrs::Csrs,
+	exception::Exception,
+	fusion,
+	gdb,
+	htif::Htif,
+	in_order::{execute, execute_cycles, take_trap},
+	instruction::Instruction,
+	memory::Memory,
+	symbols::Symbols,
+	syscalls::{SyscallResult, Syscalls},
+	tag::EMPTY_TAG,
+	x_regs::{XReg, XRegs},
+	LogLevel,
+	Statistics,
+	load_inst,
+};
+
+// A classic 5-stage pipeline: IF, ID, EX, MEM and WB.
+//
+// Instructions are executed one at a time like the in-order implementation, and the pipeline only models their timing.
+// Each instruction enters a stage once it has finished the previous one and the instruction ahead of it has left the stage,
+// so a stall in one stage holds up the instructions behind it. Fetches take a cycle plus the instruction cache's latency,
+// EX takes the same number of cycles as the in-order implementation takes for the instruction, and MEM takes a cycle
+// plus the latency of the memory access.
+//
+// Operands are read from the register file in ID or forwarded to the start of EX, or of ID for jumps that are resolved there.
+// Fetching continues with the next sequential instruction, so a taken branch or a jump flushes the instructions
+// fetched after it once it is resolved. Traps and interrupts flush the whole pipeline.
+
+/// The parameters of the pipeline, for comparing designs.
+///
+/// Given on the command line as comma-separated key=value pairs, like `ex_forwarding=false,branch_stage=mem,flush_penalty=1`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Config {
+	/// Whether results are forwarded from the EX/MEM pipeline register, so that an instruction can use the result
+	/// of the one right before it without stalling.
+	pub(crate) ex_forwarding: bool,
+	/// Whether results, including those of loads, are forwarded from the MEM/WB pipeline register.
+	pub(crate) mem_forwarding: bool,
+	/// Whether the register file is written in the first half of WB and read in the second half of ID,
+	/// so that ID can read a result in the same cycle that it is written.
+	pub(crate) wb_forwarding: bool,
+	/// The stage in which conditional branches, `jalr` and `mret` are resolved. `jal` is always resolved in ID.
+	pub(crate) branch_stage: Stage,
+	/// Extra cycles taken to refetch after each flush.
+	pub(crate) flush_penalty: u8,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Stage {
+	Id,
+	Ex,
+	Mem,
+}
+
+impl Config {
+	/// Sets the keys given on the command line.
+	pub(crate) fn set(&mut self, arg: &str) -> Result<(), config::Error> {
+		for pair in config::pairs(arg) {
+			let pair = pair?;
+			match pair.key {
+				"ex_forwarding" => self.ex_forwarding = pair.bool()?,
+				"mem_forwarding" => self.mem_forwarding = pair.bool()?,
+				"wb_forwarding" => self.wb_forwarding = pair.bool()?,
+				"branch_stage" => self.branch_stage = match pair.value {
+					"id" => Stage::Id,
+					"ex" => Stage::Ex,
+					"mem" => Stage::Mem,
+					_ => return Err(pair.invalid_value()),
+				},
+				"flush_penalty" => self.flush_penalty = pair.integer()?,
+				_ => return Err(pair.unknown_key()),
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			ex_forwarding: true,
+			mem_forwarding: true,
+			wb_forwarding: true,
+			branch_stage: Stage::Ex,
+			flush_penalty: 0,
+		}
+	}
+}
+
+pub(crate) fn run(
+	memory: &mut Memory,
+	x_regs: &mut XRegs,
+	csrs: &mut Csrs,
+	statistics: &mut Statistics,
+	pc: &mut i64,
+	symbols: &Symbols,
+	mut syscalls: Option<&mut Syscalls>,
+	mut htif: Option<&mut Htif>,
+	mut gdb: Option<&mut gdb::Stub>,
+	fusions: &fusion::Config,
+	config: Config,
+	log_level: LogLevel,
+) -> Result<i32, Exception> {
+	let mut pipeline = Pipeline::default();
+
+	loop {
+		{
+			let tick = csrs.ticks();
+			if log_level == LogLevel::Debug {
+				if tick % 100 == 0 {
+					eprintln!();
+					eprintln!("===== {tick} =====");
+					eprintln!("{}", symbols.display(*pc));
+				}
+			}
+			else if log_level >= LogLevel::Trace {
+				eprintln!();
+				eprintln!("===== {tick} =====");
+				eprintln!("{x_regs}");
+				eprintln!("{csrs}");
+			}
+		}
+
+		if let Some(gdb) = gdb.as_deref_mut() && let Some(status) = gdb.before_instruction(pc, x_regs, csrs, memory) {
+			return Ok(status);
+		}
+
+		if let Some(interrupt) = csrs.pending_interrupt() {
+			pipeline.take_trap(interrupt, pc, csrs, memory, statistics, config, log_level)?;
+			continue;
+		}
+
+		let (inst, inst_len, mut instret) = match load_inst(memory, *pc, fusions, statistics) {
+			Ok(inst) => inst,
+			Err(exception) => {
+				pipeline.take_trap(exception, pc, csrs, memory, statistics, config, log_level)?;
+				continue;
+			},
+		};
+
+		let fetch_latency = memory.fetch_latency(*pc, inst_len.unsigned_abs().try_into().expect("instruction length fits in usize"));
+
+		if log_level >= LogLevel::Trace {
+			eprintln!("+ {} : {inst:?}", symbols.display(*pc));
+		}
+
+		// The interrupt that wakes up the wfi is taken after it retires, so that it returns to the instruction after the wfi.
+		if let Instruction::Wfi = inst {
+			while !csrs.wfi_done() {
+				statistics.num_ticks_where_instructions_not_retired += 1;
+				csrs.tick(1, 0, memory);
+				pipeline.now += 1;
+			}
+			pipeline.fetch_after = pipeline.fetch_after.max(pipeline.now);
+		}
+
+		if let Instruction::Ebreak = inst {
+			return Ok(0);
+		}
+
+		if let Instruction::Ecall = inst {
+			let Some(syscalls) = syscalls.as_deref_mut() else {
+				pipeline.take_trap(Exception::EnvironmentCallFromMMode, pc, csrs, memory, statistics, config, log_level)?;
+				continue;
+			};
+			match syscalls.ecall(x_regs, memory) {
+				SyscallResult::Return(value) => x_regs.store(XReg::X10, EMPTY_TAG, value),
+				SyscallResult::Exit(status) => return Ok(status),
+			}
+		}
+
+		let mut next_pc = pc.wrapping_add(inst_len);
+
+		let execute_cycles = execute_cycles(inst, x_regs);
+
+		let memory_latency = match execute(inst, *pc, &mut next_pc, &mut instret, x_regs, csrs, memory) {
+			Ok(memory_latency) => memory_latency,
+			Err(exception) => {
+				pipeline.take_trap(exception, pc, csrs, memory, statistics, config, log_level)?;
+				continue;
+			},
+		};
+
+		let redirect = next_pc != pc.wrapping_add(inst_len);
+
+		*pc = next_pc;
+
+		let cycles = pipeline.issue(inst, redirect, fetch_latency, execute_cycles, memory_latency, statistics, config);
+
+		statistics.num_ticks_where_instructions_retired += 1;
+		statistics.num_ticks_where_instructions_not_retired += usize::try_from(cycles).expect("cycles fit in usize") - 1;
+
+		csrs.tick(cycles.cast_signed(), instret, memory);
+
+		if let Some(status) = htif.as_deref_mut().and_then(|htif| htif.poll(memory)) {
+			return Ok(status);
+		}
+
+		if let Some(status) = memory.exit_status() {
+			return Ok(status);
+		}
+
+		if log_level >= LogLevel::Trace {
+			eprintln!("->");
+			eprintln!("{x_regs}");
+			eprintln!("{csrs}");
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+struct Pipeline {
+	/// The cycles in which the previous instruction entered IF, ID, EX, MEM and WB.
+	previous: [u64; 5],
+	/// The cycle from which the next instruction can be fetched, after a flush.
+	fetch_after: u64,
+	/// For the last instruction to write each register, the cycle from which its result can be forwarded,
+	/// and the cycle from which ID can read it from the register file.
+	ready: [(u64, u64); 32],
+	/// The number of cycles that have been ticked, ie one more than the cycle in which the previous instruction was in WB.
+	now: u64,
+}
+
+const IF: usize = 0;
+const ID: usize = 1;
+const EX: usize = 2;
+const MEM: usize = 3;
+const WB: usize = 4;
+
+impl Pipeline {
+	/// Moves an executed instruction through the pipeline, and returns the number of cycles until it leaves WB.
+	fn issue(
+		&mut self,
+		inst: Instruction,
+		redirect: bool,
+		fetch_latency: u8,
+		execute_cycles: u8,
+		memory_latency: u8,
+		statistics: &mut Statistics,
+		config: Config,
+	) -> u64 {
+		let durations = [
+			1 + u64::from(fetch_latency),
+			1,
+			u64::from(execute_cycles),
+			1 + u64::from(memory_latency),
+			1,
+		];
+
+		let resolve_stage = match inst {
+			Instruction::Jal { .. } => ID,
+			_ => match config.branch_stage {
+				Stage::Id => ID,
+				Stage::Ex => EX,
+				Stage::Mem => MEM,
+			},
+		};
+		let operand_stage = match inst {
+			Instruction::Branch { .. } | Instruction::Jalr { .. } | Instruction::SltBranch { .. } if resolve_stage == ID => ID,
+			_ => EX,
+		};
+
+		// The cycle from which the operands are available to the stage that needs them,
+		// either forwarded or read from the register file in ID.
+		let operands_ready =
+			inst.sources().into_iter().flatten()
+			.map(|rs| {
+				let (forwarded, register_file) = self.ready[usize::from(rs)];
+				forwarded.min(register_file + u64::try_from(operand_stage - ID).expect("stage fits in u64"))
+			})
+			.max()
+			.unwrap_or_default();
+
+		let mut stages = [0; 5];
+		for stage in IF..=WB {
+			// The instruction ahead of this one has left the stage once it has entered the next one.
+			let stage_is_free = self.previous.get(stage + 1).copied().unwrap_or_default();
+			stages[stage] = match stage.checked_sub(1) {
+				Some(previous_stage) => (stages[previous_stage] + durations[previous_stage]).max(stage_is_free),
+				None => self.fetch_after.max(stage_is_free),
+			};
+			if stage == operand_stage && operands_ready > stages[stage] {
+				statistics.data_hazard_stall_cycles += usize::try_from(operands_ready - stages[stage]).expect("cycles fit in usize");
+				stages[stage] = operands_ready;
+			}
+		}
+		self.previous = stages;
+
+		for (i, rd) in inst.destinations().into_iter().enumerate() {
+			let Some(rd) = rd else {
+				continue;
+			};
+			// The lui half of a fused lui; load is available from EX.
+			let result_stage = match inst {
+				Instruction::Load { .. } | Instruction::LoadPair { .. } => MEM,
+				Instruction::LuiLoad { .. } if i == 1 => MEM,
+				_ => EX,
+			};
+			let forwarded =
+				if config.ex_forwarding && result_stage == EX { stages[MEM] }
+				else if config.mem_forwarding { stages[WB] }
+				else { u64::MAX };
+			let register_file = if config.wb_forwarding { stages[WB] } else { stages[WB] + 1 };
+			self.ready[usize::from(rd)] = (forwarded, register_file);
+		}
+
+		if redirect {
+			statistics.pipeline_flushes += 1;
+			self.fetch_after = stages[resolve_stage] + durations[resolve_stage] + u64::from(config.flush_penalty);
+		}
+
+		let cycles = stages[WB] + 1 - self.now;
+		self.now = stages[WB] + 1;
+		cycles
+	}
+
+	/// Takes a trap once the instructions before it have left the pipeline, and flushes it.
+	fn take_trap(
+		&mut self,
+		exception: Exception,
+		pc: &mut i64,
+		csrs: &mut Csrs,
+		memory: &mut Memory,
+		statistics: &mut Statistics,
+		config: Config,
+		log_level: LogLevel,
+	) -> Result<(), Exception> {
+		take_trap(exception, pc, csrs, memory, statistics, log_level)?;
+		self.now += 1;
+		statistics.pipeline_flushes += 1;
+		self.fetch_after = self.now + u64::from(config.flush_penalty);
+		Ok(())
+	}
+}
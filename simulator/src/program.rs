@@ -0,0 +1,160 @@
+use crate::{
+	elf::{self, Elf},
+	symbols::Symbols,
+};
+
+pub(crate) const DEFAULT_BASE_ADDRESS: u64 = 0x8000_0000_0000_0000;
+
+pub(crate) struct Program {
+	pub(crate) segments: Vec<Segment>,
+	pub(crate) entry: i64,
+	pub(crate) symbols: Symbols,
+}
+
+pub(crate) struct Segment {
+	pub(crate) address: u64,
+	pub(crate) data: Vec<u8>,
+	/// The size of the segment in memory, which is at least `data.len()`. The bytes after `data` are zero, ie .bss
+	pub(crate) size: u64,
+}
+
+pub(crate) fn load(
+	path: &std::path::Path,
+	supported_extensions: riscv::SupportedExtensions,
+) -> Result<Program, Error> {
+	let is_assembly = path.extension().is_some_and(|extension| extension == "S" || extension == "s");
+	if is_assembly {
+		let program = std::fs::read_to_string(path).map_err(Error::Io)?;
+		let (program, labels) = assemble(&program, supported_extensions).map_err(Error::Assemble)?;
+		let symbols = Symbols::new(labels.into_iter().map(|(offset, name)| (DEFAULT_BASE_ADDRESS + offset, 0, name)));
+		Ok(Program { symbols, ..Program::flat(program) })
+	}
+	else {
+		let program = std::fs::read(path).map_err(Error::Io)?;
+		if Elf::is_elf(&program) {
+			load_elf(&program).map_err(Error::Elf)
+		}
+		else {
+			Ok(Program::flat(program))
+		}
+	}
+}
+
+impl Program {
+	fn flat(data: Vec<u8>) -> Self {
+		let size = data.len().try_into().unwrap();
+		Self {
+			segments: vec![Segment { address: DEFAULT_BASE_ADDRESS, data, size }],
+			entry: DEFAULT_BASE_ADDRESS.cast_signed(),
+			symbols: Default::default(),
+		}
+	}
+}
+
+fn load_elf(raw: &[u8]) -> Result<Program, elf::Error> {
+	let elf = Elf::parse(raw)?;
+
+	let segments =
+		elf.load_segments()?
+		.into_iter()
+		.map(|segment| Segment { address: segment.vaddr, data: segment.data.to_owned(), size: segment.memsz })
+		.collect();
+
+	let symbols = Symbols::new(elf.symbols()?.into_iter().map(|(address, size, name)| (address, size, name.to_owned())));
+
+	Ok(Program {
+		segments,
+		entry: elf.entry.cast_signed(),
+		symbols,
+	})
+}
+
+/// Returns the encoded program and the offsets of its labels.
+fn assemble(program: &str, supported_extensions: riscv::SupportedExtensions) -> Result<(Vec<u8>, Vec<(u64, String)>), Vec<Diagnostic>> {
+	let mut result = vec![];
+	let mut labels = vec![];
+	let mut diagnostics = vec![];
+
+	// Parse each line separately so that diagnostics can report line numbers.
+	for (line_number, line) in (1..).zip(program.lines()) {
+		let (label, line) = riscv::split_label(line.as_bytes());
+		if let Some(label) = label {
+			labels.push((result.len().try_into().unwrap(), label.to_owned()));
+		}
+
+		for instruction in riscv::parse_program(std::iter::once(line), supported_extensions) {
+			let instruction = match instruction {
+				Ok(instruction) => instruction,
+				Err(err) => {
+					diagnostics.push(Diagnostic { line_number, message: err.to_string() });
+					continue;
+				},
+			};
+
+			match instruction.encode(supported_extensions) {
+				Ok((lo, hi)) => {
+					result.extend_from_slice(&lo.to_le_bytes());
+					if let Some(hi) = hi {
+						result.extend_from_slice(&hi.to_le_bytes());
+					}
+				},
+
+				Err(err) => diagnostics.push(Diagnostic {
+					line_number,
+					message: format!("instruction could not be encoded {instruction}: {err}"),
+				}),
+			}
+		}
+	}
+
+	if diagnostics.is_empty() {
+		Ok((result, labels))
+	}
+	else {
+		Err(diagnostics)
+	}
+}
+
+#[derive(Debug)]
+pub(crate) enum Error {
+	Assemble(Vec<Diagnostic>),
+	Elf(elf::Error),
+	Io(std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Assemble(diagnostics) => {
+				let mut first = true;
+				for diagnostic in diagnostics {
+					if first {
+						first = false;
+					}
+					else {
+						writeln!(f)?;
+					}
+
+					write!(f, "{diagnostic}")?;
+				}
+				Ok(())
+			},
+
+			Self::Elf(err) => err.fmt(f),
+
+			Self::Io(err) => err.fmt(f),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub(crate) struct Diagnostic {
+	line_number: usize,
+	message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "line {}: {}", self.line_number, self.message)
+	}
+}
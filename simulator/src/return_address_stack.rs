@@ -0,0 +1,106 @@
+// Ref: 2.5.1 Unconditional jumps
+//
+// ## `jal rd, offset`
+//
+// +----+------------+
+// | rd | RAS action |
+// +====+============+
+// | x1 | Push       |
+// | x5 | Push       |
+// | _  | None       |
+// +----+------------+
+//
+// ## `jalr rd, offset(rs1)`
+//
+// +----+-----+----------------+
+// | rd | rs1 |   RAS action   |
+// +====+=====+================+
+// | x1 | x5  | Pop, then push |
+// | x5 | x1  | Pop, then push |
+// | x1 | _   | Push           |
+// | x5 | _   | Push           |
+// | _  | x1  | Pop            |
+// | _  | x5  | Pop            |
+// | _  | _   | None           |
+// +----+-----+----------------+
+
+use crate::XReg;
+
+/// Pushing onto a full stack discards the oldest entry, and popping an empty stack returns nothing.
+#[derive(Clone, Debug)]
+pub(crate) struct ReturnAddressStack {
+	entries: std::collections::VecDeque<i64>,
+	depth: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Action {
+	None,
+	Push,
+	Pop,
+	PopThenPush,
+}
+
+impl ReturnAddressStack {
+	pub(crate) fn new(depth: usize) -> Self {
+		Self {
+			entries: std::collections::VecDeque::with_capacity(depth),
+			depth,
+		}
+	}
+
+	pub(crate) fn is_enabled(&self) -> bool {
+		self.depth != 0
+	}
+
+	/// Returns the popped return address, if any, and whether the push overflowed the stack.
+	pub(crate) fn apply(&mut self, action: Action, return_address: i64) -> (Option<i64>, bool) {
+		let popped = match action {
+			Action::None | Action::Push => None,
+			Action::Pop | Action::PopThenPush => self.entries.pop_back(),
+		};
+
+		let overflowed = match action {
+			Action::None | Action::Pop => false,
+			Action::Push | Action::PopThenPush => self.push(return_address),
+		};
+
+		(popped, overflowed)
+	}
+
+	fn push(&mut self, return_address: i64) -> bool {
+		if self.depth == 0 {
+			return false;
+		}
+
+		let overflowed = self.entries.len() == self.depth;
+		if overflowed {
+			self.entries.pop_front();
+		}
+		self.entries.push_back(return_address);
+		overflowed
+	}
+}
+
+impl Action {
+	pub(crate) fn jal(rd: XReg) -> Self {
+		if is_link(rd) { Self::Push } else { Self::None }
+	}
+
+	pub(crate) fn pops(self) -> bool {
+		matches!(self, Self::Pop | Self::PopThenPush)
+	}
+
+	pub(crate) fn jalr(rd: XReg, rs1: XReg) -> Self {
+		match (is_link(rd), is_link(rs1)) {
+			(true, true) if rd != rs1 => Self::PopThenPush,
+			(true, _) => Self::Push,
+			(false, true) => Self::Pop,
+			(false, false) => Self::None,
+		}
+	}
+}
+
+fn is_link(r: XReg) -> bool {
+	matches!(r, XReg::X1 | XReg::X5)
+}
@@ -0,0 +1,171 @@
+use crate::{
+	memory::Memory,
+	x_regs::{XReg, XRegs},
+};
+
+#[derive(Debug, Default)]
+pub(crate) struct Symbols {
+	// Sorted by address
+	inner: Vec<Symbol>,
+	by_name: std::collections::BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+struct Symbol {
+	address: u64,
+	/// 0 if the size is not known, in which case the symbol extends to the next one.
+	size: u64,
+	name: String,
+}
+
+impl Symbols {
+	/// Takes the address, size and name of each symbol. A size of 0 means that the size is not known.
+	pub(crate) fn new(symbols: impl IntoIterator<Item = (u64, u64, String)>) -> Self {
+		let mut inner: Vec<_> = symbols.into_iter().map(|(address, size, name)| Symbol { address, size, name }).collect();
+		// Collected before deduplicating, since the names of symbols that share an address with another symbol
+		// can still be looked up.
+		let by_name = inner.iter().map(|symbol| (symbol.name.clone(), symbol.address)).collect();
+		// Of the symbols that share an address, prefer the largest one, so that a function wins over a label at its start.
+		inner.sort_by(|a, b| a.address.cmp(&b.address).then(b.size.cmp(&a.size)).then_with(|| a.name.cmp(&b.name)));
+		inner.dedup_by_key(|symbol| symbol.address);
+		Self { inner, by_name }
+	}
+
+	/// Parses a symbol map in the format printed by `nm` or `nm -S`, ie `<address> [[<size>] <type>] <name>`
+	/// with a hex address and size.
+	pub(crate) fn parse_map(map: &str) -> Result<Self, usize> {
+		fn parse_hex(s: &str) -> Option<u64> {
+			u64::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16).ok()
+		}
+
+		let mut symbols = vec![];
+
+		for (line_number, line) in (1..).zip(map.lines()) {
+			let parts: Vec<_> = line.split_whitespace().collect();
+			let (address, size, name) = match parts[..] {
+				[] => continue,
+				[address, name] | [address, _, name] => (address, "0", name),
+				[address, size, _, name] => (address, size, name),
+				_ => return Err(line_number),
+			};
+			let (Some(address), Some(size)) = (parse_hex(address), parse_hex(size)) else { return Err(line_number); };
+			symbols.push((address, size, name.to_owned()));
+		}
+
+		Ok(Self::new(symbols))
+	}
+
+	/// Returns the symbol that contains the given address, and the offset of the address from the symbol.
+	pub(crate) fn lookup(&self, address: i64) -> Option<(&str, u64)> {
+		let address = address.cast_unsigned();
+		let i = self.inner.partition_point(|symbol| symbol.address <= address).checked_sub(1)?;
+		let Symbol { address: symbol_address, size, name } = &self.inner[i];
+		let offset = address - symbol_address;
+		// An address past the end of a sized symbol is in padding or data that has no symbol of its own.
+		(*size == 0 || offset < *size).then_some((name, offset))
+	}
+
+	pub(crate) fn address_of(&self, name: &str) -> Option<u64> {
+		self.by_name.get(name).copied()
+	}
+
+	pub(crate) fn display(&self, address: i64) -> impl std::fmt::Display {
+		Symbolized(self, address)
+	}
+
+	pub(crate) fn backtrace<'a>(&'a self, pc: i64, x_regs: &'a XRegs, memory: &'a Memory) -> impl std::fmt::Display + 'a {
+		Backtrace { symbols: self, pc, x_regs, memory }
+	}
+}
+
+struct Symbolized<'a>(&'a Symbols, i64);
+
+impl std::fmt::Display for Symbolized<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let &Self(symbols, address) = self;
+		write!(f, "0x{address:016x}")?;
+		match symbols.lookup(address) {
+			Some((name, 0)) => write!(f, " <{name}>")?,
+			Some((name, offset)) => write!(f, " <{name}+0x{offset:x}>")?,
+			None => (),
+		}
+		Ok(())
+	}
+}
+
+const BACKTRACE_MAX_DEPTH: usize = 64;
+
+// Reconstructs the call stack from the frame pointer chain, assuming the standard RISC-V frame layout
+// where the frame pointer points to the caller's sp and the return address and previous frame pointer
+// are saved at fp - 8 and fp - 16 respectively.
+//
+// Functions compiled without frame pointers and leaf functions that do not save ra are not visible
+// in the chain, which is why ra is also printed separately.
+struct Backtrace<'a> {
+	symbols: &'a Symbols,
+	pc: i64,
+	x_regs: &'a XRegs,
+	memory: &'a Memory,
+}
+
+impl std::fmt::Display for Backtrace<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let &Self { symbols, pc, x_regs, memory } = self;
+
+		let ra = x_regs.load_committed(XReg::X1);
+		let mut fp = x_regs.load_committed(XReg::X8);
+
+		writeln!(f, "backtrace:")?;
+		writeln!(f, "  pc: {}", symbols.display(pc))?;
+		writeln!(f, "  ra: {}", symbols.display(ra))?;
+
+		for i in 0..BACKTRACE_MAX_DEPTH {
+			if fp == 0 {
+				break;
+			}
+
+			let (Some(saved_ra), Some(saved_fp)) = (memory.peek(fp.wrapping_sub(8)), memory.peek(fp.wrapping_sub(16))) else {
+				writeln!(f, "  #{i}: frame pointer 0x{fp:016x} is invalid")?;
+				break;
+			};
+			writeln!(f, "  #{i}: {}", symbols.display(saved_ra))?;
+
+			fp = saved_fp;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Symbols;
+
+	#[test]
+	fn lookup() {
+		static TESTS: &[(i64, Option<(&str, u64)>)] = &[
+			(0x0fff, None),
+			(0x1000, Some(("f", 0))),
+			(0x1004, Some(("f", 4))),
+			// Padding after the end of f
+			(0x1008, None),
+			(0x1010, Some(("g", 0))),
+			(0x1fff, Some(("g", 0xfef))),
+			(0x2000, Some(("data", 0))),
+		];
+
+		let symbols = Symbols::parse_map("\
+0x1000 0x8 T f
+1000 t .Lf
+1010 T g
+0000000000002000 d data
+").unwrap();
+		for &(address, expected) in TESTS {
+			assert_eq!(symbols.lookup(address), expected, "0x{address:x}");
+		}
+
+		assert_eq!(symbols.address_of(".Lf"), Some(0x1000));
+		assert_eq!(Symbols::parse_map("1000 0x8 T f g").unwrap_err(), 1);
+		assert_eq!(Symbols::parse_map("\nxyz f").unwrap_err(), 2);
+	}
+}
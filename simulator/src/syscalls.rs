@@ -0,0 +1,515 @@
+use crate::{
+	memory::{Memory, LoadOp, StoreOp},
+	memory_map::MemoryMap,
+	program::Segment,
+	tag::EMPTY_TAG,
+	x_regs::{XReg, XRegs},
+};
+
+// Ref: Linux include/uapi/asm-generic/unistd.h
+const SYS_IOCTL: i64 = 29;
+const SYS_OPENAT: i64 = 56;
+const SYS_CLOSE: i64 = 57;
+const SYS_READ: i64 = 63;
+const SYS_WRITE: i64 = 64;
+const SYS_WRITEV: i64 = 66;
+const SYS_FSTAT: i64 = 80;
+const SYS_EXIT: i64 = 93;
+const SYS_EXIT_GROUP: i64 = 94;
+const SYS_SET_TID_ADDRESS: i64 = 96;
+const SYS_CLOCK_GETTIME: i64 = 113;
+const SYS_BRK: i64 = 214;
+
+// Ref: Linux include/uapi/asm-generic/errno-base.h, errno.h
+const ENOENT: i64 = 2;
+const EIO: i64 = 5;
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+const EINVAL: i64 = 22;
+const ENOTTY: i64 = 25;
+const ENAMETOOLONG: i64 = 36;
+const ENOSYS: i64 = 38;
+
+// Ref: Linux include/uapi/asm-generic/fcntl.h
+const AT_FDCWD: i64 = -100;
+const O_ACCMODE: i64 = 0o3;
+const O_WRONLY: i64 = 0o1;
+const O_RDWR: i64 = 0o2;
+const O_CREAT: i64 = 0o100;
+const O_EXCL: i64 = 0o200;
+const O_TRUNC: i64 = 0o1000;
+const O_APPEND: i64 = 0o2000;
+
+// Ref: Linux include/uapi/linux/uio.h
+const UIO_MAXIOV: i64 = 1024;
+
+// Ref: Linux include/uapi/linux/stat.h
+const S_IFCHR: i64 = 0o020_000;
+const S_IFDIR: i64 = 0o040_000;
+const S_IFREG: i64 = 0o100_000;
+
+// Ref: Linux include/uapi/linux/time.h
+const CLOCK_REALTIME: i64 = 0;
+const CLOCK_MONOTONIC: i64 = 1;
+const CLOCK_MONOTONIC_RAW: i64 = 4;
+const CLOCK_BOOTTIME: i64 = 7;
+
+// Ref: Linux include/uapi/linux/auxvec.h
+const AT_NULL: i64 = 0;
+const AT_PAGESZ: i64 = 6;
+const AT_RANDOM: i64 = 25;
+
+const PAGE_SIZE: i64 = 4096;
+
+/// The space below the initial stack pointer that the program break cannot grow into.
+const STACK_SIZE: i64 = 8 * 1024 * 1024;
+
+/// The thread ID of the program's only thread.
+const TID: i64 = 1;
+
+/// Emulates the Linux syscalls that a statically-linked newlib or musl program needs,
+/// using the host's stdin, stdout, stderr and filesystem.
+pub(crate) struct Syscalls {
+	brk_min: i64,
+	brk_max: i64,
+	brk: i64,
+	stack_top: i64,
+	files: std::collections::BTreeMap<i64, std::fs::File>,
+	start: std::time::Instant,
+}
+
+pub(crate) enum SyscallResult {
+	/// The syscall returned this value in a0.
+	Return(i64),
+	/// The program exited with this status.
+	Exit(i32),
+}
+
+impl Syscalls {
+	/// RAM is the region named `ram`. The program break starts at the page after the highest segment in it,
+	/// and the stack starts at the end of it.
+	pub(crate) fn new(segments: &[Segment], memory_map: &MemoryMap) -> Self {
+		let ram = memory_map.region("ram");
+
+		let brk =
+			segments.iter()
+			.filter(|Segment { address, .. }| ram.is_some_and(|ram| ram.contains(*address)))
+			.map(|Segment { address, size, .. }| address + size)
+			.max()
+			.unwrap_or_else(|| ram.map_or(0, |ram| ram.base))
+			.max(PAGE_SIZE.cast_unsigned())
+			.next_multiple_of(PAGE_SIZE.cast_unsigned())
+			.cast_signed();
+
+		Self {
+			brk_min: brk,
+			brk_max: brk,
+			brk,
+			stack_top: ram.map_or(0, |ram| ram.last().wrapping_add(1)).cast_signed(),
+			files: Default::default(),
+			start: std::time::Instant::now(),
+		}
+	}
+
+	/// Sets up the initial stack at the top of RAM like the Linux ELF loader does,
+	/// with `argc`, `argv`, an empty `envp` and the auxiliary vector.
+	pub(crate) fn init_stack(&mut self, argv0: &std::ffi::OsStr, memory: &mut Memory, x_regs: &mut XRegs) {
+		let mut sp = self.stack_top;
+
+		let argv0 = argv0.as_encoded_bytes();
+		sp -= i64::try_from(argv0.len()).unwrap() + 1;
+		let argv0_ptr = sp;
+		write_bytes(memory, argv0_ptr, argv0).expect("stack is in RAM");
+
+		// Used by libcs to seed stack canaries. It does not need to be random to be useful for a simulator.
+		sp -= 16;
+		let random_ptr = sp;
+		write_bytes(memory, random_ptr, b"riscv simulator\0").expect("stack is in RAM");
+
+		let words = [
+			1, argv0_ptr, 0,
+			0,
+			AT_PAGESZ, PAGE_SIZE,
+			AT_RANDOM, random_ptr,
+			AT_NULL, 0,
+		];
+		sp = (sp - i64::try_from(words.len() * 8).unwrap()) & -16;
+		for (address, word) in (sp..).step_by(8).zip(words) {
+			StoreOp::DoubleWord.exec(memory, address, word).expect("stack is in RAM");
+		}
+
+		x_regs.store(XReg::X2, EMPTY_TAG, sp);
+
+		self.brk_max = sp - STACK_SIZE;
+	}
+
+	pub(crate) fn ecall(&mut self, x_regs: &XRegs, memory: &mut Memory) -> SyscallResult {
+		let args = [XReg::X10, XReg::X11, XReg::X12].map(|x_reg| x_regs.load_committed(x_reg));
+		self.syscall(x_regs.load_committed(XReg::X17), args, memory)
+	}
+
+	pub(crate) fn syscall(&mut self, n: i64, [a0, a1, a2]: [i64; 3], memory: &mut Memory) -> SyscallResult {
+		let result = match n {
+			SYS_OPENAT => self.openat(memory, a0, a1, a2),
+			SYS_CLOSE => self.close(a0),
+			SYS_READ => self.read(memory, a0, a1, a2),
+			SYS_WRITE => self.write(memory, a0, a1, a2),
+			SYS_WRITEV => self.writev(memory, a0, a1, a2),
+			SYS_IOCTL => self.ioctl(a0),
+			SYS_FSTAT => self.fstat(memory, a0, a1),
+			#[allow(clippy::cast_possible_truncation)]
+			SYS_EXIT | SYS_EXIT_GROUP => return SyscallResult::Exit((a0 & 0xff) as i32),
+			SYS_SET_TID_ADDRESS => Ok(TID),
+			SYS_CLOCK_GETTIME => self.clock_gettime(memory, a0, a1),
+			SYS_BRK => Ok(self.brk(a0)),
+			_ => Err(ENOSYS),
+		};
+		SyscallResult::Return(result.unwrap_or_else(|errno| -errno))
+	}
+
+	fn openat(&mut self, memory: &Memory, dirfd: i64, pathname: i64, flags: i64) -> Result<i64, i64> {
+		let pathname = read_c_str(memory, pathname)?;
+		let pathname = std::str::from_utf8(&pathname).map_err(|_| ENOENT)?;
+		let pathname = std::path::Path::new(pathname);
+		if dirfd != AT_FDCWD && pathname.is_relative() {
+			return Err(ENOSYS);
+		}
+
+		let mut options = std::fs::OpenOptions::new();
+		match flags & O_ACCMODE {
+			O_WRONLY => options.write(true),
+			O_RDWR => options.read(true).write(true),
+			_ => options.read(true),
+		};
+		if flags & O_CREAT != 0 {
+			if flags & O_EXCL != 0 {
+				options.create_new(true);
+			}
+			else {
+				options.create(true);
+			}
+		}
+		options.truncate(flags & O_TRUNC != 0);
+		options.append(flags & O_APPEND != 0);
+
+		let file = options.open(pathname).map_err(|err| errno(&err))?;
+
+		let fd = (3..i64::MAX).find(|fd| !self.files.contains_key(fd)).expect("fds are not exhausted");
+		self.files.insert(fd, file);
+		Ok(fd)
+	}
+
+	fn close(&mut self, fd: i64) -> Result<i64, i64> {
+		match fd {
+			// The host's stdio is never closed.
+			0..=2 => Ok(0),
+			fd => self.files.remove(&fd).map(|_| 0).ok_or(EBADF),
+		}
+	}
+
+	fn read(&mut self, memory: &mut Memory, fd: i64, buf: i64, count: i64) -> Result<i64, i64> {
+		use std::io::Read;
+
+		let count = u64::try_from(count).map_err(|_| EINVAL)?;
+		// Only allocate as much as the destination can hold, so that a huge count does not exhaust the host's memory.
+		// This is a short read like the one for a count that runs past the end of the file.
+		let writable_len = memory.writable_len(buf);
+		if count > 0 && writable_len == 0 {
+			return Err(EFAULT);
+		}
+		let mut data = vec![0_u8; usize::try_from(count.min(writable_len)).map_err(|_| EINVAL)?];
+		let len = match fd {
+			0 => std::io::stdin().read(&mut data),
+			1 | 2 => return Err(EBADF),
+			fd => self.files.get_mut(&fd).ok_or(EBADF)?.read(&mut data),
+		};
+		let len = len.map_err(|err| errno(&err))?;
+		write_bytes(memory, buf, &data[..len])?;
+		Ok(len.try_into().unwrap())
+	}
+
+	fn write(&mut self, memory: &Memory, fd: i64, buf: i64, count: i64) -> Result<i64, i64> {
+		use std::io::Write;
+
+		let data = read_bytes(memory, buf, count)?;
+		let len = match fd {
+			0 => return Err(EBADF),
+			1 => {
+				let mut stdout = std::io::stdout().lock();
+				stdout.write(&data).and_then(|len| { stdout.flush()?; Ok(len) })
+			},
+			2 => std::io::stderr().write(&data),
+			fd => self.files.get_mut(&fd).ok_or(EBADF)?.write(&data),
+		};
+		let len = len.map_err(|err| errno(&err))?;
+		Ok(len.try_into().unwrap())
+	}
+
+	/// Writes each buffer of the `iovec` array in turn, stopping at the first short write like Linux does.
+	fn writev(&mut self, memory: &Memory, fd: i64, iov: i64, iovcnt: i64) -> Result<i64, i64> {
+		if !(0..=UIO_MAXIOV).contains(&iovcnt) {
+			return Err(EINVAL);
+		}
+
+		let mut result = 0;
+		for i in 0..iovcnt {
+			let iov = iov.wrapping_add(i * 16);
+			if !memory.is_memory_backed(iov, 16) {
+				return Err(EFAULT);
+			}
+			let base = LoadOp::DoubleWord.exec(memory, iov).map_err(|_| EFAULT)?;
+			let len = LoadOp::DoubleWord.exec(memory, iov.wrapping_add(8)).map_err(|_| EFAULT)?;
+			let written = match self.write(memory, fd, base, len) {
+				Ok(written) => written,
+				Err(_) if result > 0 => break,
+				Err(errno) => return Err(errno),
+			};
+			result += written;
+			if written < len {
+				break;
+			}
+		}
+		Ok(result)
+	}
+
+	/// None of the files are terminals, so every request fails like it does for a regular file.
+	/// In particular, `TIOCGWINSZ` fails, which is how libcs detect that stdout is not a terminal.
+	fn ioctl(&self, fd: i64) -> Result<i64, i64> {
+		match fd {
+			0..=2 => Err(ENOTTY),
+			fd if self.files.contains_key(&fd) => Err(ENOTTY),
+			_ => Err(EBADF),
+		}
+	}
+
+	// Ref: Linux include/uapi/asm-generic/stat.h
+	fn fstat(&self, memory: &mut Memory, fd: i64, statbuf: i64) -> Result<i64, i64> {
+		let (mode, size, mtime) = match fd {
+			0..=2 => (S_IFCHR | 0o620, 0, std::time::Duration::ZERO),
+			fd => {
+				let metadata = self.files.get(&fd).ok_or(EBADF)?.metadata().map_err(|err| errno(&err))?;
+				let mode = if metadata.is_dir() { S_IFDIR | 0o755 } else { S_IFREG | 0o644 };
+				let mode = if metadata.permissions().readonly() { mode & !0o222 } else { mode };
+				let size = metadata.len().cast_signed();
+				let mtime =
+					metadata.modified().ok()
+					.and_then(|mtime| mtime.duration_since(std::time::SystemTime::UNIX_EPOCH).ok())
+					.unwrap_or_default();
+				(mode, size, mtime)
+			},
+		};
+
+		let mtime_sec = mtime.as_secs().cast_signed();
+		let mtime_nsec = i64::from(mtime.subsec_nanos());
+
+		let mut stat = [0_u8; 128];
+		stat[16..20].copy_from_slice(&mode.to_le_bytes()[..4]); // st_mode
+		stat[20..24].copy_from_slice(&1_u32.to_le_bytes()); // st_nlink
+		stat[48..56].copy_from_slice(&size.to_le_bytes()); // st_size
+		stat[56..60].copy_from_slice(&PAGE_SIZE.to_le_bytes()[..4]); // st_blksize
+		stat[64..72].copy_from_slice(&((size + 511) / 512).to_le_bytes()); // st_blocks
+		for offset in [72, 88, 104] {
+			// st_atime, st_mtime, st_ctime
+			stat[offset..(offset + 8)].copy_from_slice(&mtime_sec.to_le_bytes());
+			stat[(offset + 8)..(offset + 16)].copy_from_slice(&mtime_nsec.to_le_bytes());
+		}
+		write_bytes(memory, statbuf, &stat)?;
+		Ok(0)
+	}
+
+	fn clock_gettime(&self, memory: &mut Memory, clockid: i64, tp: i64) -> Result<i64, i64> {
+		let time = match clockid {
+			CLOCK_REALTIME =>
+				std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap_or_default(),
+			CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_BOOTTIME =>
+				self.start.elapsed(),
+			_ => return Err(EINVAL),
+		};
+
+		let mut timespec = [0_u8; 16];
+		timespec[..8].copy_from_slice(&time.as_secs().to_le_bytes());
+		timespec[8..].copy_from_slice(&u64::from(time.subsec_nanos()).to_le_bytes());
+		write_bytes(memory, tp, &timespec)?;
+		Ok(0)
+	}
+
+	/// Returns the new program break, or the current one if `addr` is out of range.
+	fn brk(&mut self, addr: i64) -> i64 {
+		if (self.brk_min..=self.brk_max).contains(&addr) {
+			self.brk = addr;
+		}
+		self.brk
+	}
+}
+
+fn errno(err: &std::io::Error) -> i64 {
+	err.raw_os_error().map_or(EIO, i64::from)
+}
+
+/// Only reads from memory, since loading from a device has side effects like consuming the UART's input.
+fn read_bytes(memory: &Memory, address: i64, len: i64) -> Result<Vec<u8>, i64> {
+	let len_usize = usize::try_from(len).map_err(|_| EINVAL)?;
+	if len_usize > 0 && !memory.is_memory_backed(address, len_usize) {
+		return Err(EFAULT);
+	}
+
+	(0..len)
+		.map(|i| {
+			let b = LoadOp::ByteUnsigned.exec(memory, address.wrapping_add(i)).map_err(|_| EFAULT)?;
+			Ok(b.to_le_bytes()[0])
+		})
+		.collect()
+}
+
+fn read_c_str(memory: &Memory, address: i64) -> Result<Vec<u8>, i64> {
+	let mut result = vec![];
+	for i in 0..PAGE_SIZE {
+		if !memory.is_memory_backed(address.wrapping_add(i), 1) {
+			return Err(EFAULT);
+		}
+		let b = LoadOp::ByteUnsigned.exec(memory, address.wrapping_add(i)).map_err(|_| EFAULT)?;
+		if b == 0 {
+			return Ok(result);
+		}
+		result.push(b.to_le_bytes()[0]);
+	}
+	Err(ENAMETOOLONG)
+}
+
+fn write_bytes(memory: &mut Memory, address: i64, data: &[u8]) -> Result<(), i64> {
+	for (address, &b) in (address..).zip(data) {
+		StoreOp::Byte.exec(memory, address, b.into()).map_err(|_| EFAULT)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AT_FDCWD, AT_NULL, AT_PAGESZ, AT_RANDOM, EBADF, EFAULT, EINVAL, ENOENT, ENOSYS, O_CREAT, O_TRUNC, O_WRONLY, PAGE_SIZE, STACK_SIZE, Syscalls, write_bytes};
+	use crate::{
+		cache::Caches,
+		memory::{LoadOp, Memory, Misaligned, StoreOp},
+		memory_map::MemoryMap,
+		x_regs::{XReg, XRegs},
+	};
+
+	const UART: i64 = 0xffff_ffff_fff1_0000_u64.cast_signed();
+
+	fn syscalls() -> (Syscalls, Memory) {
+		let memory_map = MemoryMap::default();
+		let in_file_path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+		let memory = Memory::new(&memory_map, Misaligned::Trap, vec![], in_file_path, Default::default(), Caches::default()).unwrap();
+		(Syscalls::new(&[], &memory_map), memory)
+	}
+
+	/// Returns a path for a temporary file that is unique to the test.
+	fn temp_path(name: &str) -> std::path::PathBuf {
+		std::env::temp_dir().join(format!("simulator-syscalls-{}-{name}", std::process::id()))
+	}
+
+	fn openat(syscalls: &mut Syscalls, memory: &mut Memory, dirfd: i64, path: &std::path::Path, flags: i64) -> Result<i64, i64> {
+		let mut pathname = path.as_os_str().as_encoded_bytes().to_owned();
+		pathname.push(b'\0');
+		write_bytes(memory, 0x1000, &pathname).unwrap();
+		syscalls.openat(memory, dirfd, 0x1000, flags)
+	}
+
+	#[test]
+	fn init_stack() {
+		let (mut syscalls, mut memory) = syscalls();
+		let mut x_regs: XRegs = Default::default();
+		syscalls.init_stack("prog".as_ref(), &mut memory, &mut x_regs);
+
+		let sp = x_regs.load_committed(XReg::X2);
+		assert_eq!(sp % 16, 0);
+
+		let words: Vec<_> = (0..10).map(|i| LoadOp::DoubleWord.exec(&memory, sp + i * 8).unwrap()).collect();
+		let argv0 = 0x400_2000 - 5;
+		let random = argv0 - 16;
+		assert_eq!(words, [1, argv0, 0, 0, AT_PAGESZ, PAGE_SIZE, AT_RANDOM, random, AT_NULL, 0]);
+		assert!(sp + 80 <= random);
+		assert_eq!(super::read_c_str(&memory, argv0).unwrap(), b"prog");
+		assert_eq!(super::read_bytes(&memory, random, 16).unwrap(), b"riscv simulator\0");
+	}
+
+	#[test]
+	fn brk() {
+		let (mut syscalls, mut memory) = syscalls();
+
+		// The break cannot grow until the stack is set up.
+		assert_eq!(syscalls.brk(0), PAGE_SIZE);
+		assert_eq!(syscalls.brk(PAGE_SIZE + 1), PAGE_SIZE);
+
+		let mut x_regs: XRegs = Default::default();
+		syscalls.init_stack("prog".as_ref(), &mut memory, &mut x_regs);
+		let brk_max = x_regs.load_committed(XReg::X2) - STACK_SIZE;
+
+		assert_eq!(syscalls.brk(0), PAGE_SIZE);
+		assert_eq!(syscalls.brk(PAGE_SIZE + 1), PAGE_SIZE + 1);
+		assert_eq!(syscalls.brk(brk_max), brk_max);
+		assert_eq!(syscalls.brk(brk_max + 1), brk_max);
+		assert_eq!(syscalls.brk(PAGE_SIZE - 1), brk_max);
+		assert_eq!(syscalls.brk(PAGE_SIZE), PAGE_SIZE);
+	}
+
+	#[test]
+	fn openat_close() {
+		let (mut syscalls, mut memory) = syscalls();
+		let path = temp_path("openat");
+
+		assert_eq!(openat(&mut syscalls, &mut memory, AT_FDCWD, &path, 0), Err(ENOENT));
+		assert_eq!(openat(&mut syscalls, &mut memory, AT_FDCWD, &path, O_WRONLY | O_CREAT), Ok(3));
+		assert_eq!(openat(&mut syscalls, &mut memory, AT_FDCWD, &path, 0), Ok(4));
+		assert_eq!(openat(&mut syscalls, &mut memory, 3, "relative".as_ref(), 0), Err(ENOSYS));
+		assert_eq!(syscalls.openat(&memory, AT_FDCWD, UART, 0), Err(EFAULT));
+
+		assert_eq!(syscalls.close(3), Ok(0));
+		assert_eq!(syscalls.close(3), Err(EBADF));
+		assert_eq!(openat(&mut syscalls, &mut memory, AT_FDCWD, &path, 0), Ok(3));
+		assert_eq!(openat(&mut syscalls, &mut memory, AT_FDCWD, &path, 0), Ok(5));
+		assert_eq!(syscalls.close(4), Ok(0));
+		assert_eq!(openat(&mut syscalls, &mut memory, AT_FDCWD, &path, 0), Ok(4));
+
+		assert_eq!(syscalls.close(1), Ok(0));
+		assert_eq!(syscalls.close(6), Err(EBADF));
+
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn write() {
+		let (mut syscalls, mut memory) = syscalls();
+		let path = temp_path("write");
+		let fd = openat(&mut syscalls, &mut memory, AT_FDCWD, &path, O_WRONLY | O_CREAT | O_TRUNC).unwrap();
+
+		write_bytes(&mut memory, 0x2000, b"abcdef").unwrap();
+		assert_eq!(syscalls.write(&memory, fd, 0x2000, 3), Ok(3));
+		assert_eq!(syscalls.write(&memory, fd, 0x2000, -1), Err(EINVAL));
+		assert_eq!(syscalls.write(&memory, fd, 0x400_2000 - 2, 3), Err(EFAULT));
+		assert_eq!(syscalls.write(&memory, fd, UART, 1), Err(EFAULT));
+		assert_eq!(syscalls.write(&memory, 0, 0x2000, 1), Err(EBADF));
+
+		let iovs = [
+			// Stops at the first buffer that cannot be read, and returns what was written before it.
+			(&[(0x2000, 2), (0x2003, 3), (UART, 1), (0x2000, 1)][..], Ok(5)),
+			(&[(0x2000, 1), (0x2000, -1)], Ok(1)),
+			(&[(UART, 1)], Err(EFAULT)),
+			(&[(0x2000, -1)], Err(EINVAL)),
+			(&[], Ok(0)),
+		];
+		for (iov, expected) in iovs {
+			for (i, &(base, len)) in (0..).zip(iov) {
+				StoreOp::DoubleWord.exec(&mut memory, 0x3000 + i * 16, base).unwrap();
+				StoreOp::DoubleWord.exec(&mut memory, 0x3000 + i * 16 + 8, len).unwrap();
+			}
+			let iovcnt = i64::try_from(iov.len()).unwrap();
+			assert_eq!(syscalls.writev(&memory, fd, 0x3000, iovcnt), expected, "{iov:?}");
+		}
+		assert_eq!(syscalls.writev(&memory, fd, 0x3000, -1), Err(EINVAL));
+		assert_eq!(syscalls.writev(&memory, fd, UART, 1), Err(EFAULT));
+
+		assert_eq!(syscalls.close(fd), Ok(0));
+		assert_eq!(std::fs::read(&path).unwrap(), b"abcabdefa");
+		std::fs::remove_file(path).unwrap();
+	}
+}
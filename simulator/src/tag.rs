@@ -1,6 +1,6 @@
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 #[repr(transparent)]
-pub(crate) struct Tag(u16);
+pub(crate) struct Tag(u32);
 
 pub(crate) const EMPTY_TAG: Tag = Tag(0);
 
@@ -16,9 +16,13 @@ pub(crate) struct TagAllocator {
 }
 
 impl TagAllocator {
-	pub(crate) fn allocate(&mut self) -> OneTickTags4 {
+	/// Allocates the tags for the ucodes of one instruction.
+	///
+	/// Tags are 32-bit, so they only wrap around after 2^30 instructions,
+	/// long after the instruction that had the same tag before has left any ROB.
+	pub(crate) fn allocate(&mut self) -> InstTags4 {
 		let next = &mut self.next;
-		let result = OneTickTags4(Tag(next.0), Tag(next.0.wrapping_add(1)), Tag(next.0.wrapping_add(2)), Tag(next.0.wrapping_add(3)));
+		let result = InstTags4(Tag(next.0), Tag(next.0.wrapping_add(1)), Tag(next.0.wrapping_add(2)), Tag(next.0.wrapping_add(3)));
 		next.0 = next.0.wrapping_add(4);
 		result
 	}
@@ -30,33 +34,33 @@ impl Default for TagAllocator {
 	}
 }
 
-pub(crate) struct OneTickTags4(Tag, Tag, Tag, Tag);
+pub(crate) struct InstTags4(Tag, Tag, Tag, Tag);
 
-impl OneTickTags4 {
-	pub(crate) fn allocate(self) -> (Tag, OneTickTags3) {
-		(self.0, OneTickTags3(self.1, self.2, self.3))
+impl InstTags4 {
+	pub(crate) fn allocate(self) -> (Tag, InstTags3) {
+		(self.0, InstTags3(self.1, self.2, self.3))
 	}
 }
 
-pub(crate) struct OneTickTags3(Tag, Tag, Tag);
+pub(crate) struct InstTags3(Tag, Tag, Tag);
 
-impl OneTickTags3 {
-	pub(crate) fn allocate(self) -> (Tag, OneTickTags2) {
-		(self.0, OneTickTags2(self.1, self.2))
+impl InstTags3 {
+	pub(crate) fn allocate(self) -> (Tag, InstTags2) {
+		(self.0, InstTags2(self.1, self.2))
 	}
 }
 
-pub(crate) struct OneTickTags2(Tag, Tag);
+pub(crate) struct InstTags2(Tag, Tag);
 
-impl OneTickTags2 {
-	pub(crate) fn allocate(self) -> (Tag, OneTickTags1) {
-		(self.0, OneTickTags1(self.1))
+impl InstTags2 {
+	pub(crate) fn allocate(self) -> (Tag, InstTags1) {
+		(self.0, InstTags1(self.1))
 	}
 }
 
-pub(crate) struct OneTickTags1(Tag);
+pub(crate) struct InstTags1(Tag);
 
-impl OneTickTags1 {
+impl InstTags1 {
 	pub(crate) fn allocate(self) -> Tag {
 		self.0
 	}
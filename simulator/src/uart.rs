@@ -0,0 +1,137 @@
+// Ref: TI PC16550D Universal Asynchronous Receiver/Transmitter With FIFOs
+//
+// +--------+-------------+--------------------------------------------------------------------+
+// | Offset | Register    | Behavior                                                           |
+// +========+=============+====================================================================+
+// | 0x0    | RBR (read)  | The next byte from stdin, or 0 if there is none.                   |
+// | 0x0    | THR (write) | The byte is written to stdout immediately.                         |
+// | 0x5    | LSR         | Bit 0 (DR) is set if RBR has a byte. Bits 5 and 6 (THRE and TEMT)  |
+// |        |             | are always set, since the transmitter is never busy.               |
+// | 0x7    | SCR         | Scratch register that holds whatever was last written to it.       |
+// +--------+-------------+--------------------------------------------------------------------+
+//
+// Every other register reads as 0 and ignores writes, so there are no interrupts, FIFO controls or baud rate.
+//
+// When stdin is a terminal it is read in the background, so that DR is only set once a byte has been typed.
+// Otherwise it is read when the program checks for a byte, so the program sees the same input
+// at the same cycle on every run.
+#[derive(Debug, Default)]
+pub(crate) struct Uart {
+	rx: std::cell::RefCell<Rx>,
+	scr: u8,
+}
+
+const RBR: u64 = 0x0;
+const THR: u64 = 0x0;
+const LSR: u64 = 0x5;
+const SCR: u64 = 0x7;
+
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+#[derive(Debug, Default)]
+struct Rx {
+	next: Option<u8>,
+	stdin: Stdin,
+}
+
+#[derive(Debug, Default)]
+enum Stdin {
+	#[default]
+	Unopened,
+	Blocking,
+	Terminal(std::sync::mpsc::Receiver<u8>),
+	Eof,
+}
+
+impl Uart {
+	pub(crate) fn load(&self, offset: u64, buf: &mut [u8]) {
+		for (b, offset) in buf.iter_mut().zip(offset..) {
+			*b = match offset {
+				RBR => self.rx.borrow_mut().take().unwrap_or_default(),
+				LSR => {
+					let mut lsr = LSR_THRE | LSR_TEMT;
+					if self.rx.borrow_mut().peek().is_some() {
+						lsr |= LSR_DR;
+					}
+					lsr
+				},
+				SCR => self.scr,
+				_ => 0,
+			};
+		}
+	}
+
+	pub(crate) fn store(&mut self, offset: u64, data: &[u8]) {
+		use std::io::Write;
+
+		for (offset, &b) in (offset..).zip(data) {
+			match offset {
+				THR => {
+					let mut stdout = std::io::stdout().lock();
+					_ = stdout.write_all(&[b]);
+					_ = stdout.flush();
+				},
+				SCR => self.scr = b,
+				_ => (),
+			}
+		}
+	}
+}
+
+impl Rx {
+	fn take(&mut self) -> Option<u8> {
+		self.peek()?;
+		self.next.take()
+	}
+
+	fn peek(&mut self) -> Option<u8> {
+		use std::io::{IsTerminal, Read};
+
+		if self.next.is_some() {
+			return self.next;
+		}
+
+		if matches!(self.stdin, Stdin::Unopened) {
+			self.stdin =
+				if std::io::stdin().is_terminal() {
+					let (sender, receiver) = std::sync::mpsc::channel();
+					std::thread::spawn(move || {
+						for b in std::io::stdin().lock().bytes() {
+							let Ok(b) = b else { break; };
+							if sender.send(b).is_err() {
+								break;
+							}
+						}
+					});
+					Stdin::Terminal(receiver)
+				}
+				else {
+					Stdin::Blocking
+				};
+		}
+
+		match &self.stdin {
+			Stdin::Unopened => unreachable!(),
+
+			Stdin::Blocking => {
+				let mut b = [0_u8];
+				match std::io::stdin().read(&mut b) {
+					Ok(1) => self.next = Some(b[0]),
+					_ => self.stdin = Stdin::Eof,
+				}
+			},
+
+			Stdin::Terminal(receiver) => match receiver.try_recv() {
+				Ok(b) => self.next = Some(b),
+				Err(std::sync::mpsc::TryRecvError::Empty) => (),
+				Err(std::sync::mpsc::TryRecvError::Disconnected) => self.stdin = Stdin::Eof,
+			},
+
+			Stdin::Eof => (),
+		}
+
+		self.next
+	}
+}
@@ -2,7 +2,8 @@
 
 use crate::{
 	RegisterValue,
-	csrs::{Csr, Csrs},
+	csrs::{Csr, Csrs, MSTATUS_MPIE},
+	exception::Exception,
 	instruction::{
 		Instruction,
 		BranchOp,
@@ -11,8 +12,9 @@ use crate::{
 		MemoryBase, MemoryOffset,
 	},
 	memory::{LoadOp, StoreOp},
+	divider,
 	multiplier::I132,
-	tag::{Tag, OneTickTags4},
+	tag::{Tag, InstTags4},
 	x_regs::{XReg, XRegs},
 };
 
@@ -20,7 +22,12 @@ use crate::{
 pub(crate) enum Ucode {
 	BinaryOp { op: BinaryOp, rd: (XReg, Tag, Option<i64>), rs1: RegisterValue, rs2: RegisterValue },
 	Csel { rd: (XReg, Tag, Option<i64>), rcond: RegisterValue, rs_eqz: RegisterValue, rs_nez: RegisterValue },
+	Div { op: DivOp, rd: (XReg, Tag, Option<i64>), rs1: RegisterValue, rs2: RegisterValue, state: Option<divider::State> },
 	Ebreak,
+	/// Services a syscall, and returns its result in a0.
+	Ecall { rd: (XReg, Tag, Option<i64>) },
+	/// An instruction that could not be fetched or decoded. Never created by `Ucode::new`.
+	Fault(Exception),
 	Fence,
 	Jump { pc: RegisterValue, predicted_next_pc: i64 },
 	Mul { op: MulOp, rd: (XReg, Tag, Option<i64>), rs1: RegisterValue, rs2: RegisterValue, state: Option<(u8, I132)> },
@@ -29,6 +36,7 @@ pub(crate) enum Ucode {
 	Load { op: LoadOp, rd: (XReg, Tag, Option<i64>), addr: RegisterValue },
 	Store { op: StoreOp, addr: RegisterValue, value: RegisterValue },
 	UnaryOp { op: UnaryOp, rd: (XReg, Tag, Option<i64>), rs: RegisterValue },
+	Wfi,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -79,6 +87,55 @@ pub(crate) enum BinaryOp {
 	Xor,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum DivOp {
+	Div,
+	Divu,
+	Divuw,
+	Divw,
+	Rem,
+	Remu,
+	Remuw,
+	Remw,
+}
+
+impl DivOp {
+	/// Runs one round of the division, and returns the result once it is done.
+	pub(crate) fn round(self, state: &mut Option<divider::State>, arg1: i64, arg2: i64, radix: divider::Radix) -> Option<i64> {
+		let (is_signed, width, is_remainder) = match self {
+			Self::Div => (true, 64, false),
+			Self::Divu => (false, 64, false),
+			Self::Divuw => (false, 32, false),
+			Self::Divw => (true, 32, false),
+			Self::Rem => (true, 64, true),
+			Self::Remu => (false, 64, true),
+			Self::Remuw => (false, 32, true),
+			Self::Remw => (true, 32, true),
+		};
+		let state_ = state.unwrap_or_else(|| divider::State::initial(is_signed, width, arg1, arg2));
+		match divider::round(state_, radix) {
+			divider::Round::Pending(state_) => {
+				*state = Some(state_);
+				None
+			},
+
+			divider::Round::Done { quotient, remainder } => Some(if is_remainder { remainder } else { quotient }),
+		}
+	}
+
+	/// Runs all the rounds of the division, and returns the result and the number of rounds.
+	pub(crate) fn run(self, arg1: i64, arg2: i64, radix: divider::Radix) -> (i64, u8) {
+		let mut state = None;
+		let mut rounds = 1;
+		loop {
+			if let Some(value) = self.round(&mut state, arg1, arg2, radix) {
+				return (value, rounds);
+			}
+			rounds += 1;
+		}
+	}
+}
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum MulOp {
 	Mul,
@@ -96,7 +153,7 @@ impl Ucode {
 		predicted_next_pc: i64,
 		x_regs: &mut XRegs,
 		csrs: &mut Csrs,
-		tags: OneTickTags4,
+		tags: InstTags4,
 	) -> Option<(Self, Option<(Self, Option<(Self, Option<Self>)>)>)> {
 		fn try_rename_x_reg(
 			rd: XReg,
@@ -151,26 +208,13 @@ impl Ucode {
 				))
 			}
 			else {
-				match rs {
-					RegisterValue::Value(_) => {
-						let csr = try_rename_csr(csr, csrs, tag_licsr)?;
-						Some((
-							Ucode::MvCsr { csr, value: rs },
-							None,
-						))
-					},
-
-					RegisterValue::Tag(tag) =>
-						if csrs.rename(csr, tag) {
-							Some((
-								Ucode::MvCsr { csr: (csr, tag), value: rs },
-								None
-							))
-						}
-						else {
-							None
-						},
-				}
+				// Unlike `assign_x_reg`, the CSR can't share the tag of `rs` because the value written to the CSR
+				// is legalized first.
+				let csr = try_rename_csr(csr, csrs, tag_licsr)?;
+				Some((
+					Ucode::MvCsr { csr, value: rs },
+					None,
+				))
 			}
 		}
 
@@ -231,6 +275,34 @@ impl Ucode {
 				Some((inst_op, Some((inst_add, Some((inst_czero, Some(inst_jump)))))))
 			},
 
+			Instruction::SltBranch { op, rd, rs1, rs2, imm } => {
+				let rs1 = x_regs.load(rs1);
+				let rs2 = x_regs.load(rs2);
+
+				// The comparison of the branch is the result of the slt, so it is written to rd.
+				let (tag_op, tags) = tags.allocate();
+				_ = x_regs.rename(rd, tag_op);
+				let (tag_add, tags) = tags.allocate();
+				let (op, rs_eqz, rs_nez) = match op {
+					BranchOp::LessThan => (BinaryOp::Slt, RegisterValue::Value(next_inst_pc), RegisterValue::Tag(tag_add)),
+					BranchOp::GreaterThanOrEqual => (BinaryOp::Slt, RegisterValue::Tag(tag_add), RegisterValue::Value(next_inst_pc)),
+					BranchOp::LessThanUnsigned => (BinaryOp::Sltu, RegisterValue::Value(next_inst_pc), RegisterValue::Tag(tag_add)),
+					BranchOp::GreaterThanOrEqualUnsigned => (BinaryOp::Sltu, RegisterValue::Tag(tag_add), RegisterValue::Value(next_inst_pc)),
+					BranchOp::Equal | BranchOp::NotEqual => unreachable!("slt is only fused with a beqz or bnez"),
+				};
+
+				let inst_op = Self::BinaryOp { op, rd: (rd, tag_op, None), rs1, rs2 };
+
+				let inst_add = Self::BinaryOp { op: BinaryOp::Add, rd: (XReg::X0, tag_add, None), rs1: RegisterValue::Value(pc), rs2: RegisterValue::Value(imm) };
+
+				let (tag_czero, _) = tags.allocate();
+				let inst_czero = Self::Csel { rd: (XReg::X0, tag_czero, None), rcond: RegisterValue::Tag(tag_op), rs_eqz, rs_nez };
+
+				let inst_jump = Self::Jump { pc: RegisterValue::Tag(tag_czero), predicted_next_pc };
+
+				Some((inst_op, Some((inst_add, Some((inst_czero, Some(inst_jump)))))))
+			},
+
 			Instruction::Csrrw { rd, rs1, csr } => {
 				let rs1 = x_regs.load(rs1);
 				let (tag_li, tags) = tags.allocate();
@@ -424,6 +496,12 @@ impl Ucode {
 
 			Instruction::Ebreak => Some((Self::Ebreak, None)),
 
+			Instruction::Ecall => {
+				let (tag_a0, _) = tags.allocate();
+				let rd = try_rename_x_reg(XReg::X10, x_regs, tag_a0).expect("a0 is not x0");
+				Some((Self::Ecall { rd }, None))
+			},
+
 			Instruction::Fence => Some((Self::Fence, None)),
 
 			Instruction::Jal { rd, imm: _ } => {
@@ -501,12 +579,65 @@ impl Ucode {
 				Some((inst_addr, Some((inst_load, None))))
 			},
 
+			Instruction::LoadPair { op, rd1, rd2, base, imm, len1: _ } => {
+				let base = x_regs.load(base);
+				let size = i64::try_from(op.size()).expect("load size fits in i64");
+
+				let (tag_addr1, tags) = tags.allocate();
+				let inst_addr1 = Self::BinaryOp { op: BinaryOp::Add, rd: (XReg::X0, tag_addr1, None), rs1: base, rs2: RegisterValue::Value(imm) };
+
+				let (tag_load1, tags) = tags.allocate();
+				_ = x_regs.rename(rd1, tag_load1);
+				let inst_load1 = Self::Load { op, rd: (rd1, tag_load1, None), addr: RegisterValue::Tag(tag_addr1) };
+
+				let (tag_addr2, tags) = tags.allocate();
+				let inst_addr2 = Self::BinaryOp { op: BinaryOp::Add, rd: (XReg::X0, tag_addr2, None), rs1: base, rs2: RegisterValue::Value(imm.wrapping_add(size)) };
+
+				let tag_load2 = tags.allocate();
+				_ = x_regs.rename(rd2, tag_load2);
+				let inst_load2 = Self::Load { op, rd: (rd2, tag_load2, None), addr: RegisterValue::Tag(tag_addr2) };
+
+				Some((inst_addr1, Some((inst_load1, Some((inst_addr2, Some(inst_load2)))))))
+			},
+
 			Instruction::Lui { rd, imm } => {
 				let (tag_li, _) = tags.allocate();
 				let inst_li = assign_x_reg(rd, RegisterValue::Value(imm), x_regs, tag_li)?;
 				Some((inst_li, None))
 			},
 
+			Instruction::LuiLoad { op, rd, lui_rd, lui_imm, offset, lui_len: _ } => {
+				let (tag_li, tags) = tags.allocate();
+				let inst_li = assign_x_reg(lui_rd, RegisterValue::Value(lui_imm), x_regs, tag_li)?;
+
+				let (tag_load, _) = tags.allocate();
+				_ = x_regs.rename(rd, tag_load);
+				let inst_load = Self::Load { op, rd: (rd, tag_load, None), addr: RegisterValue::Value(lui_imm.wrapping_add(offset)) };
+
+				Some((inst_li, Some((inst_load, None))))
+			},
+
+			Instruction::Mret => {
+				// MIE = MPIE, MPIE = 1. The other fields of mstatus are hard-wired, so the legalization done by `MvCsr`
+				// takes care of discarding the bits shifted into them.
+				let mstatus = csrs.load(Csr::Mstatus);
+				let mepc = csrs.load(Csr::Mepc);
+
+				let (tag_srl, tags) = tags.allocate();
+				let inst_srl = Self::BinaryOp { op: BinaryOp::Srl, rd: (XReg::X0, tag_srl, None), rs1: mstatus, rs2: RegisterValue::Value(4) };
+
+				let (tag_or, tags) = tags.allocate();
+				let inst_or = Self::BinaryOp { op: BinaryOp::Or, rd: (XReg::X0, tag_or, None), rs1: RegisterValue::Tag(tag_srl), rs2: RegisterValue::Value(MSTATUS_MPIE) };
+
+				let (tag_licsr, _) = tags.allocate();
+				let csr = try_rename_csr(Csr::Mstatus, csrs, tag_licsr)?;
+				let inst_licsr = Self::MvCsr { csr, value: RegisterValue::Tag(tag_or) };
+
+				let inst_jump = Self::Jump { pc: mepc, predicted_next_pc };
+
+				Some((inst_srl, Some((inst_or, Some((inst_licsr, Some(inst_jump)))))))
+			},
+
 			// c.mv
 			Instruction::Op { op: OpOp::Add, rd, rs1: XReg::X0, rs2 } => {
 				let rs2 = x_regs.load(rs2);
@@ -559,6 +690,10 @@ impl Ucode {
 
 					OpOp::CzeroNez => Some((Self::Csel { rd, rcond: rs2, rs_eqz: rs1, rs_nez: RegisterValue::Value(0) }, None)),
 
+					OpOp::Div => Some((Self::Div { op: DivOp::Div, rd, rs1, rs2, state: None }, None)),
+
+					OpOp::Divu => Some((Self::Div { op: DivOp::Divu, rd, rs1, rs2, state: None }, None)),
+
 					OpOp::Max => {
 						let (tag_slt, _) = tags.allocate();
 						let inst_slt = Self::BinaryOp { op: BinaryOp::Slt, rd: (XReg::X0, tag_slt, None), rs1, rs2 };
@@ -599,6 +734,10 @@ impl Ucode {
 
 					OpOp::Orn => Some((Self::BinaryOp { op: BinaryOp::Orn, rd, rs1, rs2 }, None)),
 
+					OpOp::Rem => Some((Self::Div { op: DivOp::Rem, rd, rs1, rs2, state: None }, None)),
+
+					OpOp::Remu => Some((Self::Div { op: DivOp::Remu, rd, rs1, rs2, state: None }, None)),
+
 					OpOp::Rol => Some((Self::BinaryOp { op: BinaryOp::Rol, rd, rs1, rs2 }, None)),
 
 					OpOp::Ror => Some((Self::BinaryOp { op: BinaryOp::Ror, rd, rs1, rs2 }, None)),
@@ -645,8 +784,16 @@ impl Ucode {
 
 					Op32Op::Addw => Some((Self::BinaryOp { op: BinaryOp::Addw, rd, rs1, rs2 }, None)),
 
+					Op32Op::Divuw => Some((Self::Div { op: DivOp::Divuw, rd, rs1, rs2, state: None }, None)),
+
+					Op32Op::Divw => Some((Self::Div { op: DivOp::Divw, rd, rs1, rs2, state: None }, None)),
+
 					Op32Op::Mulw => Some((Self::Mul { op: MulOp::Mulw, rd, rs1, rs2, state: None }, None)),
 
+					Op32Op::Remuw => Some((Self::Div { op: DivOp::Remuw, rd, rs1, rs2, state: None }, None)),
+
+					Op32Op::Remw => Some((Self::Div { op: DivOp::Remw, rd, rs1, rs2, state: None }, None)),
+
 					Op32Op::Rolw => Some((Self::BinaryOp { op: BinaryOp::Rolw, rd, rs1, rs2 }, None)),
 
 					Op32Op::Rorw => Some((Self::BinaryOp { op: BinaryOp::Rorw, rd, rs1, rs2 }, None)),
@@ -857,6 +1004,18 @@ impl Ucode {
 
 				Some((inst_addr, Some((inst_store, None))))
 			},
+
+			Instruction::Wfi => Some((Self::Wfi, None)),
+		}
+	}
+
+	/// For a fused instruction whose second instruction can fault after the first one has written its `rd`,
+	/// returns the number of ucodes that make up the first instruction, and its length.
+	pub(crate) fn first_half(inst: Instruction) -> Option<(usize, i64)> {
+		match inst {
+			Instruction::LoadPair { len1, .. } => Some((2, len1)),
+			Instruction::LuiLoad { lui_len, .. } => Some((1, lui_len)),
+			_ => None,
 		}
 	}
 
@@ -872,7 +1031,13 @@ impl Ucode {
 				rs_eqz.update(tag, new_value);
 				rs_nez.update(tag, new_value);
 			},
+			Self::Div { op: _, rd: _, rs1, rs2, state: _ } => {
+				rs1.update(tag, new_value);
+				rs2.update(tag, new_value);
+			},
 			Self::Ebreak => (),
+			Self::Ecall { rd: _ } => (),
+			Self::Fault(_) => (),
 			Self::Fence => (),
 			Self::Jump { pc, predicted_next_pc: _ } => pc.update(tag, new_value),
 			Self::Mul { op: _, rd: _, rs1, rs2, state: _ } => {
@@ -887,6 +1052,7 @@ impl Ucode {
 				value.update(tag, new_value);
 			},
 			Self::UnaryOp { op: _, rd: _, rs } => rs.update(tag, new_value),
+			Self::Wfi => (),
 		}
 	}
 
@@ -894,6 +1060,8 @@ impl Ucode {
 		match *self {
 			Ucode::BinaryOp { rd, .. } |
 			Ucode::Csel { rd, .. } |
+			Ucode::Div { rd, .. } |
+			Ucode::Ecall { rd } |
 			Ucode::Load { rd, .. } |
 			Ucode::Mul { rd, .. } |
 			Ucode::UnaryOp { rd, .. }
@@ -906,10 +1074,12 @@ impl Ucode {
 				=> Some((rd, tag, None)),
 
 			Ucode::Ebreak |
+			Ucode::Fault(_) |
 			Ucode::Fence |
 			Ucode::Jump { .. } |
 			Ucode::MvCsr { .. } |
-			Ucode::Store { .. }
+			Ucode::Store { .. } |
+			Ucode::Wfi
 				=> None,
 		}
 	}
@@ -931,9 +1101,10 @@ impl Ucode {
 		}
 	}
 
+	/// The value is legalized, so it can be forwarded to the users of the tag as-is.
 	pub(crate) fn done_csr(&self) -> Option<(Csr, Tag, i64)> {
 		let (csr, csr_tag, value) = self.csr()?;
-		Some((csr, csr_tag, value?))
+		Some((csr, csr_tag, csr.legalize(value?)))
 	}
 
 	pub(crate) fn done(&self) -> bool {
@@ -941,12 +1112,16 @@ impl Ucode {
 		match *self {
 			Ucode::BinaryOp { rd, .. } |
 			Ucode::Csel { rd, .. } |
+			Ucode::Div { rd, .. } |
+			Ucode::Ecall { rd } |
 			Ucode::Mul { rd, .. } |
 			Ucode::UnaryOp { rd, .. }
 				=> matches!(rd, (_, _, Some(_))),
 
 			Ucode::Ebreak => false,
 
+			Ucode::Fault(_) => false,
+
 			Ucode::Fence => false,
 
 			Ucode::Mv { value, .. } |
@@ -958,6 +1133,8 @@ impl Ucode {
 			Ucode::Jump { pc, .. } => matches!(pc, RegisterValue::Value(_)),
 
 			Ucode::Store { .. } => false,
+
+			Ucode::Wfi => false,
 		}
 	}
 }
@@ -53,6 +53,11 @@ impl XRegs {
 		}
 	}
 
+	/// Returns the last value stored to the register, regardless of any pending renames.
+	pub(crate) fn load_committed(&self, x_reg: XReg) -> i64 {
+		self.inner[usize::from(x_reg)].0
+	}
+
 	pub(crate) fn rename(&mut self, x_reg: XReg, tag: Tag) -> bool {
 		let i = usize::from(x_reg);
 		if i == 0 {
@@ -1178,6 +1178,18 @@ instructions! {
 		#[i("csrrwi", System)]
 		Csrrwi { dest: Register, csr: Csr, imm: i32 },
 
+		#[r("div", Op)]
+		Div { dest: Register, src1: Register, src2: Register },
+
+		#[r("divu", Op)]
+		Divu { dest: Register, src1: Register, src2: Register },
+
+		#[r("divuw", Op32)]
+		Divuw { dest: Register, src1: Register, src2: Register },
+
+		#[r("divw", Op32)]
+		Divw { dest: Register, src1: Register, src2: Register },
+
 		#[i("ebreak", System)]
 		EBreak,
 
@@ -1230,6 +1242,9 @@ instructions! {
 		#[r("minu", Op)]
 		Minu { dest: Register, src1: Register, src2: Register },
 
+		#[i("mret", System)]
+		MRet,
+
 		#[r("mul", Op)]
 		Mul { dest: Register, src1: Register, src2: Register },
 
@@ -1257,6 +1272,18 @@ instructions! {
 		#[r("orn", Op)]
 		Orn { dest: Register, src1: Register, src2: Register },
 
+		#[r("rem", Op)]
+		Rem { dest: Register, src1: Register, src2: Register },
+
+		#[r("remu", Op)]
+		Remu { dest: Register, src1: Register, src2: Register },
+
+		#[r("remuw", Op32)]
+		Remuw { dest: Register, src1: Register, src2: Register },
+
+		#[r("remw", Op32)]
+		Remw { dest: Register, src1: Register, src2: Register },
+
 		Rev8 { dest: Register, src: Register },
 
 		#[r("rol", Op)]
@@ -1370,6 +1397,9 @@ instructions! {
 		#[s("sw", Store)]
 		Sw { base: Register, offset: i32, src: Register },
 
+		#[i("wfi", System)]
+		Wfi,
+
 		#[r("xnor", Op)]
 		Xnor { dest: Register, src1: Register, src2: Register },
 
@@ -2638,6 +2668,10 @@ funct! {
 		Csrrwi = 0b101,
 		CZeroEqz = 0b101,
 		CZeroNez = 0b111,
+		Div = 0b100,
+		Divu = 0b101,
+		Divuw = 0b101,
+		Divw = 0b100,
 		EBreak = 0b000,
 		ECall = 0b000,
 		Jalr = 0b000,
@@ -2652,6 +2686,7 @@ funct! {
 		Maxu = 0b111,
 		Min = 0b100,
 		Minu = 0b101,
+		MRet = 0b000,
 		Mul = 0b000,
 		Mulh = 0b001,
 		Mulhsu = 0b010,
@@ -2661,6 +2696,10 @@ funct! {
 		OrcB = 0b101,
 		Ori = 0b110,
 		Orn = 0b110,
+		Rem = 0b110,
+		Remu = 0b111,
+		Remuw = 0b111,
+		Remw = 0b110,
 		Rev8 = 0b101,
 		Rol = 0b001,
 		Rolw = 0b001,
@@ -2699,6 +2738,7 @@ funct! {
 		Sub = 0b000,
 		Subw = 0b000,
 		Sw = 0b010,
+		Wfi = 0b000,
 		Xnor = 0b100,
 		Xor = 0b100,
 		Xori = 0b100,
@@ -2729,6 +2769,10 @@ funct! {
 		Ctzw = 0b011_0000,
 		CZeroEqz = 0b000_0111,
 		CZeroNez = 0b000_0111,
+		Div = 0b000_0001,
+		Divu = 0b000_0001,
+		Divuw = 0b000_0001,
+		Divw = 0b000_0001,
 		Max = 0b000_0101,
 		Maxu = 0b000_0101,
 		Min = 0b000_0101,
@@ -2741,6 +2785,10 @@ funct! {
 		Or = 0b000_0000,
 		OrcB = 0b001_0100,
 		Orn = 0b010_0000,
+		Rem = 0b000_0001,
+		Remu = 0b000_0001,
+		Remuw = 0b000_0001,
+		Remw = 0b000_0001,
 		Rev8_32 = 0b011_0100,
 		Rev8_64 = 0b011_0101,
 		Rol = 0b011_0000,
@@ -2784,6 +2832,8 @@ funct! {
 	enum Func12 {
 		EBreak = 0b0000_0000_0001,
 		ECall = 0b0000_0000_0000,
+		MRet = 0b0011_0000_0010,
+		Wfi = 0b0001_0000_0101,
 	}
 }
 
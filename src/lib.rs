@@ -27,6 +27,18 @@ pub fn parse_program<'a>(
 		})
 }
 
+/// Splits a label definition like `loop:` off the start of the line, and returns the label and the rest of the line.
+///
+/// Labels can only be defined, so that they can be emitted as symbols. They cannot be used as operands.
+pub fn split_label(line: &[u8]) -> (Option<&str>, &[u8]) {
+	let trimmed = line.trim_ascii_start();
+	let len = trimmed.iter().position(|&b| !(b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'$'))).unwrap_or(trimmed.len());
+	match trimmed.split_at(len) {
+		(label @ [first, ..], [b':', rest @ ..]) if !first.is_ascii_digit() => (core::str::from_utf8(label).ok(), rest),
+		_ => (None, line),
+	}
+}
+
 enum SmallIterator<T> {
 	Empty,
 	One(T),
@@ -115,6 +127,24 @@ mod tests {
 	extern crate std;
 	use std::prelude::v1::*;
 
+	#[test]
+	fn split_label() {
+		static TESTS: &[(&str, Option<&str>, &str)] = &[
+			("loop:", Some("loop"), ""),
+			("  _start: addi a0, a0, 1", Some("_start"), " addi a0, a0, 1"),
+			(".L1$x:# comment", Some(".L1$x"), "# comment"),
+			("addi a0, a0, 1", None, "addi a0, a0, 1"),
+			("1: nop", None, "1: nop"),
+			(": nop", None, ": nop"),
+			("# loop:", None, "# loop:"),
+		];
+		for &(line, expected_label, expected_rest) in TESTS {
+			let (label, rest) = super::split_label(line.as_bytes());
+			assert_eq!(label, expected_label, "{line:?}");
+			assert_eq!(rest, expected_rest.as_bytes(), "{line:?}");
+		}
+	}
+
 	#[test]
 	fn full_uncompressed32() {
 		static TESTS: &[(&str, &[(u16, Option<u16>)])] = &[
@@ -246,6 +276,10 @@ mod tests {
 
 			("ctzw a0, a1", &[(0x951b, Some(0x6015))]),
 
+			("div a0, a1, a2", &[(0xc533, Some(0x02c5))]),
+
+			("divu a0, a1, a2", &[(0xd533, Some(0x02c5))]),
+
 			("ebreak", &[(0x0073, Some(0x0010))]),
 
 			("ecall", &[(0x0073, Some(0x0000))]),
@@ -328,6 +362,8 @@ mod tests {
 
 			("minu a0, a1, a2", &[(0xd533, Some(0x0ac5))]),
 
+			("mret", &[(0x0073, Some(0x3020))]),
+
 			("mv a0, a1", &[(0x8513, Some(0x0005))]),
 
 			("neg a0, a1", &[(0x0533, Some(0x40b0))]),
@@ -352,6 +388,10 @@ mod tests {
 
 			("pause", &[(0x000f, Some(0x0100))]),
 
+			("rem a0, a1, a2", &[(0xe533, Some(0x02c5))]),
+
+			("remu a0, a1, a2", &[(0xf533, Some(0x02c5))]),
+
 			("ret", &[(0x8067, Some(0x0000))]),
 
 			("rev8 a0, a1", &[(0xd513, Some(0x6985))]),
@@ -420,6 +460,8 @@ mod tests {
 			("tail -4", &[(0x0317, Some(0x0000)), (0x0067, Some(0xffc3))]),
 			("tail 8", &[(0x0317, Some(0x0000)), (0x0067, Some(0x0083))]),
 
+			("wfi", &[(0x0073, Some(0x1050))]),
+
 			("xnor a0, a1, a2", &[(0xc533, Some(0x40c5))]),
 
 			("xor a0, a1, a2", &[(0xc533, Some(0x00c5))]),
@@ -739,6 +781,10 @@ mod tests {
 
 			("bseti a0, a1, 63", &[(0x9513, Some(0x2bf5))]),
 
+			("divuw a0, a1, a2", &[(0xd53b, Some(0x02c5))]),
+
+			("divw a0, a1, a2", &[(0xc53b, Some(0x02c5))]),
+
 			("ld a0, -36", &[(0x0517, Some(0x0000)), (0x3503, Some(0xfdc5))]),
 			("ld a0, 72", &[(0x0517, Some(0x0000)), (0x3503, Some(0x0485))]),
 			("ld a0, -11(a1)", &[(0xb503, Some(0xff55))]),
@@ -751,6 +797,10 @@ mod tests {
 
 			("negw a0, a1", &[(0x053b, Some(0x40b0))]),
 
+			("remuw a0, a1, a2", &[(0xf53b, Some(0x02c5))]),
+
+			("remw a0, a1, a2", &[(0xe53b, Some(0x02c5))]),
+
 			("rolw a0, a1, a2", &[(0x953b, Some(0x60c5))]),
 
 			("rori a0, a1, 63", &[(0xd513, Some(0x63f5))]),
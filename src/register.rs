@@ -217,7 +217,17 @@ csr! {
 		CycleH = "cycleh" => 0xc80,
 		InstRet = "instret" => 0xc02,
 		InstRetH = "instreth" => 0xc82,
+		Mcause = "mcause" => 0x342,
+		Mcountinhibit = "mcountinhibit" => 0x320,
+		Mepc = "mepc" => 0x341,
+		Mhartid = "mhartid" => 0xf14,
+		Mie = "mie" => 0x304,
+		Mip = "mip" => 0x344,
 		Misa = "misa" => 0x301,
+		Mscratch = "mscratch" => 0x340,
+		Mstatus = "mstatus" => 0x300,
+		Mtval = "mtval" => 0x343,
+		Mtvec = "mtvec" => 0x305,
 		Time = "time" => 0xc01,
 		TimeH = "timeh" => 0xc81,
 	}
@@ -16,7 +16,7 @@ impl SupportedExtensions {
 }
 
 impl SupportedExtensions {
-	pub(crate) fn contains(self, other: Self) -> bool {
+	pub fn contains(self, other: Self) -> bool {
 		self.0 & other.0 == other.0
 	}
 }